@@ -1,36 +1,190 @@
-use libmudtelnet::compatibility::CompatibilityTable;
+use libmudtelnet::bytes::Bytes;
 use libmudtelnet::events::{TelnetEvents, TelnetIAC, TelnetNegotiation, TelnetSubnegotiation};
+use libmudtelnet::telnet::op_command::{
+  AO, AYT, BRK, DM, DO, EC, EL, EOR, GA, IAC, IP, NOP, SB, SE, WILL,
+};
+use libmudtelnet::telnet::op_option::{MCCP2, MCCP3, MXP};
+use libmudtelnet::testing::{
+  assert_escape_roundtrip, assert_matches_reference, assert_seeded_chunking_invariant,
+  ReferenceParser,
+};
 use libmudtelnet::Parser;
 
 use libtelnet_rs::compatibility::CompatibilityTable as OgCompatibilityTable;
 use libtelnet_rs::events::TelnetEvents as OgTelnetEvents;
 use libtelnet_rs::Parser as OgParser;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub struct TelnetApplication {
-  pub options: Vec<(u8, u8)>,
-  pub received_data: Vec<Vec<u8>>,
+pub use libmudtelnet::testing::TelnetApplication;
+
+/// The original `libtelnet-rs` parser, as the reference for differential tests.
+///
+/// libmudtelnet fixes some bugs that the pinned `libtelnet-rs` fork still has. Rather than patching the fork for
+/// each of them, this is the one place the known differences are accounted for. Received data is handed to the
+/// original parser as it arrives, except that anything it's known to handle differently is handed over on its own,
+/// so its results can be adjusted. These are the intentional differences:
+///
+/// * Receiving DO for an option doesn't mark it as enabled remotely.
+/// * An escaped IAC IAC is received as data, not as an IAC command.
+/// * A command split across reads is held until the rest of it arrives, rather than being dropped.
+/// * SE, DM, BRK, IP, AO, AYT, EC and EL are two byte commands, and don't take the byte following them.
+/// * IAC SB IAC SE at the end of a read is the start of a subnegotiation for option 255, not a complete one.
+/// * Only an enabled MCCP2 or MCCP3 subnegotiation starts compression. Otherwise the data after it is parsed as
+///   usual.
+/// * An escaped IAC IAC SE in a subnegotiation's payload doesn't end the subnegotiation.
+/// * Accepting MXP also sends the IAC SB MXP IAC SE that starts MXP mode.
+pub struct OgReference {
+  parser: OgParser,
+  // The start of a command or subnegotiation that hasn't been received in full yet.
+  pending: Vec<u8>,
 }
 
-pub fn test_app(app: &TelnetApplication) {
-  let mut parser = Parser::with_support(CompatibilityTable::from_options(&app.options));
-  let mut og_parser = OgParser::with_support(OgCompatibilityTable::from_options(&app.options));
+impl OgReference {
+  pub fn new(parser: OgParser) -> Self {
+    Self {
+      parser,
+      pending: Vec::new(),
+    }
+  }
+
+  /// The length of the command, subnegotiation or run of data at the start of `data`, or None if it's incomplete.
+  fn unit_len(data: &[u8]) -> Option<usize> {
+    // Data runs until the next IAC that isn't part of an escaped IAC IAC at its start.
+    let data_len = |start: usize| {
+      data[start..]
+        .iter()
+        .position(|&byte| byte == IAC)
+        .map_or(data.len(), |pos| start + pos)
+    };
+    match data {
+      [] | [IAC] => None,
+      [IAC, IAC, ..] => Some(data_len(2)),
+      [IAC, GA | EOR | NOP | SE | DM | BRK | IP | AO | AYT | EC | EL, ..] => Some(2),
+      [IAC, SB, _, payload @ ..] => {
+        // An IAC in the payload escapes the byte following it, unless that's SE.
+        let mut escaped = false;
+        for (index, &byte) in payload.iter().enumerate() {
+          match (escaped, byte) {
+            (true, SE) => return Some(index + 4),
+            (false, IAC) => escaped = true,
+            _ => escaped = false,
+          }
+        }
+        None
+      }
+      [IAC, _] => None,
+      [IAC, _, _, ..] => Some(3),
+      _ => Some(data_len(1)),
+    }
+  }
+
+  /// Whether the original parser is known to handle the unit differently.
+  fn diverges(unit: &[u8]) -> bool {
+    match unit {
+      [IAC, IAC, ..]
+      | [IAC, DO, _]
+      | [IAC, SE | DM | BRK | IP | AO | AYT | EC | EL]
+      | [IAC, SB, MCCP2 | MCCP3, ..] => true,
+      [IAC, SB, _, payload @ .., IAC, SE] => payload.windows(2).any(|pair| pair == [IAC, SE]),
+      _ => false,
+    }
+  }
 
-  for data in &app.received_data {
-    let our_events = parser.receive(&data);
-    let og_events = events(og_parser.receive(&data));
-    assert_eq!(our_events, og_events);
+  /// Hand a unit the original parser is known to handle differently to it, adjusting the results.
+  ///
+  /// # Returns
+  ///
+  /// `bool` - Whether `rest`, the data received after the unit, was handed over for decompression as well.
+  fn process(&mut self, unit: &[u8], rest: &[u8], events: &mut Vec<TelnetEvents>) -> bool {
+    match unit {
+      [IAC, IAC, ..] => {
+        events.push(TelnetEvents::DataReceive(Bytes::copy_from_slice(unit)));
+        false
+      }
+      [IAC, DO, option] => {
+        let before = self.parser.options.get_option(*option);
+        let mxp_start = [IAC, SB, MXP, IAC, SE];
+        events.extend(self.og(unit).into_iter().map(|event| match event {
+          TelnetEvents::DataSend(data) if *option == MXP && data[..] == [IAC, WILL, MXP] => {
+            TelnetEvents::DataSend(Bytes::from([&data[..], &mxp_start].concat()))
+          }
+          event => event,
+        }));
+        let mut entry = self.parser.options.get_option(*option);
+        entry.remote_state = before.remote_state;
+        self.parser.options.set_option(*option, entry);
+        false
+      }
+      [IAC, SB, option, payload @ .., IAC, SE] => {
+        let entry = self.parser.options.get_option(*option);
+        let compressed = (*option == MCCP2 || *option == MCCP3) && entry.local && entry.local_state;
+        // The original parser ends the subnegotiation at the first IAC SE. Give it an empty payload instead, and
+        // put the payload back in the event.
+        let escaped_se = payload.windows(2).any(|pair| pair == [IAC, SE]);
+        let mut input = if escaped_se {
+          vec![IAC, SB, *option, IAC, SE]
+        } else {
+          unit.to_vec()
+        };
+        if compressed {
+          input.extend_from_slice(rest);
+        }
+        events.extend(self.og(&input).into_iter().map(|event| match event {
+          TelnetEvents::Subnegotiation(sub) if escaped_se => TelnetEvents::Subnegotiation(
+            TelnetSubnegotiation::new(sub.option, Bytes::copy_from_slice(payload)),
+          ),
+          event => event,
+        }));
+        compressed
+      }
+      _ => {
+        events.extend(self.og(unit));
+        false
+      }
+    }
+  }
+
+  fn og(&mut self, data: &[u8]) -> Vec<TelnetEvents> {
+    events(self.parser.receive(data))
+  }
+}
+
+impl ReferenceParser for OgReference {
+  fn receive(&mut self, data: &[u8]) -> Vec<TelnetEvents> {
+    let mut buffered = core::mem::take(&mut self.pending);
+    buffered.extend_from_slice(data);
+    let mut events = Vec::new();
+    // Data before `start` has been handed to the original parser, and data before `end` split into units.
+    let (mut start, mut end) = (0, 0);
+    while let Some(len) = Self::unit_len(&buffered[end..]) {
+      let (unit, rest) = buffered[end..].split_at(len);
+      if !Self::diverges(unit) {
+        end += len;
+        continue;
+      }
+      events.extend(self.og(&buffered[start..end]));
+      end = if self.process(unit, rest, &mut events) {
+        buffered.len()
+      } else {
+        end + len
+      };
+      start = end;
+    }
+    events.extend(self.og(&buffered[start..end]));
+    self.pending = buffered.split_off(end);
+    events
   }
 
-  for i in 0..255 {
-    assert_eq!(
-      parser.options.get_option(i).into_u8(),
-      og_parser.options.get_option(i).into_u8()
-    );
+  fn option_bits(&self, option: u8) -> u8 {
+    self.parser.options.get_option(option).into_u8()
   }
 }
 
+pub fn test_app(app: &TelnetApplication) {
+  let og_parser = OgParser::with_support(OgCompatibilityTable::from_options(&app.options));
+  assert_matches_reference(app, &mut OgReference::new(og_parser));
+  assert_seeded_chunking_invariant(app);
+}
+
 pub fn events(events: Vec<OgTelnetEvents>) -> Vec<TelnetEvents> {
   events.into_iter().map(event).collect()
 }
@@ -52,9 +206,8 @@ pub fn event(event: OgTelnetEvents) -> TelnetEvents {
 
 pub fn test_escape(data: Vec<u8>) {
   // For any input if we escape it, and then unescape it, we should get back the original data.
+  assert_escape_roundtrip(&data);
   let escaped = Parser::escape_iac(data.clone());
-  let unescaped = Parser::unescape_iac(escaped.clone());
-  assert_eq!(data, unescaped);
 
   // The same should be true for the original implementation.
   let og_escaped = OgParser::escape_iac(data.clone());
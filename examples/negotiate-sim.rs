@@ -0,0 +1,178 @@
+//! Simulate the option negotiation between a client and a server.
+//!
+//! Usage: `cargo run --example negotiate-sim --features testing -- client.conf server.conf`
+//!
+//! Each config file lists one option per line, by name (e.g. `GMCP`) or number, followed by any of:
+//!
+//! * `local` - The option is supported on this end.
+//! * `remote` - The option is supported on the other end.
+//! * `will` - Offer to enable the option on this end when the connection opens.
+//! * `do` - Ask the other end to enable the option when the connection opens.
+//!
+//! Blank lines and anything after a `#` are ignored. For example, a server offering GMCP and asking for the
+//! client's terminal type:
+//!
+//! ```text
+//! GMCP   local will
+//! TTYPE  remote do
+//! ```
+//!
+//! The negotiations each end sends are printed as they're exchanged, followed by the options enabled once both
+//! ends settle.
+
+use std::convert::TryFrom;
+use std::process::ExitCode;
+use std::{env, fs};
+
+use libmudtelnet::compatibility::CompatibilityTable;
+use libmudtelnet::events::{TelnetEvents, TelnetNegotiation};
+use libmudtelnet::telnet::op_command::{DONT, IAC, WILL};
+use libmudtelnet::telnet::KnownOption;
+use libmudtelnet::testing::{duplex, Endpoint};
+
+/// The most rounds to simulate before deciding the ends are stuck in a negotiation loop.
+const MAX_ROUNDS: usize = 100;
+
+/// An end's configuration: the options it supports, and the negotiations it starts with.
+#[derive(Default)]
+struct Config {
+  table: CompatibilityTable,
+  will: Vec<u8>,
+  do_: Vec<u8>,
+}
+
+fn parse_option(name: &str) -> Option<u8> {
+  name.parse().ok().or_else(|| {
+    (0..=u8::MAX).find(|&value| {
+      KnownOption::try_from(value).is_ok_and(|option| option.name().eq_ignore_ascii_case(name))
+    })
+  })
+}
+
+fn parse_config(text: &str) -> Result<Config, String> {
+  let mut config = Config::default();
+  for (number, line) in text.lines().enumerate() {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+      continue;
+    };
+    let option =
+      parse_option(name).ok_or_else(|| format!("line {}: unknown option {name}", number + 1))?;
+    let mut entry = config.table.get_option(option);
+    for word in words {
+      match word.to_ascii_lowercase().as_str() {
+        "local" => entry.local = true,
+        "remote" => entry.remote = true,
+        "will" => config.will.push(option),
+        "do" => config.do_.push(option),
+        _ => return Err(format!("line {}: unknown setting {word}", number + 1)),
+      }
+    }
+    config.table.set_option(option, entry);
+  }
+  Ok(config)
+}
+
+fn load_config(path: &str) -> Result<Config, String> {
+  let text = fs::read_to_string(path).map_err(|error| format!("{path}: {error}"))?;
+  parse_config(&text).map_err(|error| format!("{path}: {error}"))
+}
+
+/// Send an end's opening negotiations.
+fn open(end: &mut Endpoint, config: &Config, route: &str) {
+  let mut events: Vec<TelnetEvents> = config
+    .will
+    .iter()
+    .filter_map(|&option| end.parser._will(option))
+    .collect();
+  events.extend(
+    config
+      .do_
+      .iter()
+      .filter_map(|&option| end.parser._do(option)),
+  );
+  print_sent(route, &events);
+  for event in events {
+    end.send(event);
+  }
+}
+
+/// Print what an end sent, showing negotiations symbolically.
+fn print_sent(route: &str, events: &[TelnetEvents]) {
+  for event in events {
+    let TelnetEvents::DataSend(data) = event else {
+      continue;
+    };
+    let mut rest = &data[..];
+    while let [IAC, command @ WILL..=DONT, option, remaining @ ..] = rest {
+      println!("{route}: {}", TelnetNegotiation::new(*command, *option));
+      rest = remaining;
+    }
+    if !rest.is_empty() {
+      println!(
+        "{route}: {}",
+        TelnetEvents::DataSend(data.slice(data.len() - rest.len()..))
+      );
+    }
+  }
+}
+
+fn print_enabled(name: &str, end: &Endpoint) {
+  let (mut local, mut remote) = (Vec::new(), Vec::new());
+  for value in 0..=u8::MAX {
+    let entry = end.parser.options.get_option(value);
+    let option = KnownOption::try_from(value)
+      .map_or_else(|value| value.to_string(), |option| option.to_string());
+    if entry.local_state {
+      local.push(option.clone());
+    }
+    if entry.remote_state {
+      remote.push(option);
+    }
+  }
+  println!("{name} enabled locally: {}", list(&local));
+  println!("{name} enabled remotely: {}", list(&remote));
+}
+
+fn list(options: &[String]) -> String {
+  if options.is_empty() {
+    String::from("none")
+  } else {
+    options.join(", ")
+  }
+}
+
+fn main() -> ExitCode {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let [client_path, server_path] = args.as_slice() else {
+    eprintln!("usage: negotiate-sim <client config> <server config>");
+    return ExitCode::FAILURE;
+  };
+  let (client_config, server_config) = match (load_config(client_path), load_config(server_path)) {
+    (Ok(client), Ok(server)) => (client, server),
+    (Err(error), _) | (_, Err(error)) => {
+      eprintln!("{error}");
+      return ExitCode::FAILURE;
+    }
+  };
+
+  let (mut client, mut server) = duplex(client_config.table.clone(), server_config.table.clone());
+  open(&mut server, &server_config, "server -> client");
+  open(&mut client, &client_config, "client -> server");
+  let mut rounds = 0;
+  while client.has_incoming() || server.has_incoming() {
+    if rounds == MAX_ROUNDS {
+      println!("Still negotiating after {MAX_ROUNDS} rounds, giving up. The ends may be in a negotiation loop.");
+      break;
+    }
+    rounds += 1;
+    print_sent("client -> server", &client.poll());
+    print_sent("server -> client", &server.poll());
+  }
+
+  println!();
+  print_enabled("client", &client);
+  print_enabled("server", &server);
+  ExitCode::SUCCESS
+}
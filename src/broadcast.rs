@@ -0,0 +1,121 @@
+//! Encoding a message once for many connections.
+//!
+//! A MUD server sending the same room message to hundreds of players would otherwise escape it and append line
+//! endings once per recipient. A [`Broadcast`] does that work once, and hands every connection a `DataSend` event
+//! sharing the same `Bytes`, so sending it costs a reference count increment.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::events::TelnetEvents;
+use crate::telnet::op_command::{GA, IAC};
+use crate::telnet::op_option::SGA;
+use crate::Parser;
+
+/// A message encoded once, ready to be sent to any number of connections.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Broadcast {
+  // The encoded message followed by IAC GA. The message alone is a slice of it, sharing the allocation.
+  with_go_ahead: Bytes,
+}
+
+impl Broadcast {
+  /// Encode a message.
+  ///
+  /// # Arguments
+  ///
+  /// `text` - The message, already in the character set the recipients use.
+  ///
+  /// # Notes
+  ///
+  /// IAC bytes are escaped, and line endings are sent as `\r\n`, whether the message uses `\n` or `\r\n`. A line
+  /// ending is appended if the message doesn't end with one. Recipients using different character sets need a
+  /// `Broadcast` per character set, encoded from the message converted for it.
+  #[must_use]
+  pub fn new(text: &[u8]) -> Self {
+    let mut buf = BytesMut::with_capacity(text.len() + text.len() / 16 + 4);
+    let mut previous = None;
+    for &byte in text {
+      match byte {
+        b'\n' if previous != Some(b'\r') => buf.put(&b"\r\n"[..]),
+        IAC => buf.put(&[IAC, IAC][..]),
+        _ => buf.put_u8(byte),
+      }
+      previous = Some(byte);
+    }
+    if !buf.ends_with(b"\r\n") {
+      buf.put(&b"\r\n"[..]);
+    }
+    buf.put(&[IAC, GA][..]);
+    Self {
+      with_go_ahead: buf.freeze(),
+    }
+  }
+
+  /// The encoded message.
+  ///
+  /// # Arguments
+  ///
+  /// `go_ahead` - Whether the message is followed by `IAC GA`, marking the end of the server's output.
+  #[must_use]
+  pub fn to_bytes(&self, go_ahead: bool) -> Bytes {
+    if go_ahead {
+      self.with_go_ahead.clone()
+    } else {
+      self.with_go_ahead.slice(..self.with_go_ahead.len() - 2)
+    }
+  }
+
+  /// The `DataSend` event sending the message on a connection.
+  ///
+  /// # Arguments
+  ///
+  /// `parser` - The connection's parser. The message is followed by `IAC GA` unless SGA is enabled locally.
+  ///
+  /// # Notes
+  ///
+  /// Connections in half-duplex mode should pass the message to `Parser::send_output` instead, which appends
+  /// `IAC GA` itself.
+  #[must_use]
+  pub fn event(&self, parser: &Parser) -> TelnetEvents {
+    let go_ahead = !parser.options.get_option(SGA).local_state;
+    TelnetEvents::DataSend(self.to_bytes(go_ahead))
+  }
+}
+
+#[cfg(test)]
+mod test_broadcast {
+  use super::*;
+  use crate::compatibility::{CompatibilityEntry, CompatibilityTable};
+
+  #[test]
+  fn test_encoding() {
+    let broadcast = Broadcast::new(b"A goblin\xff arrives.\nIt waves.\r\n");
+    assert_eq!(
+      &broadcast.to_bytes(false)[..],
+      b"A goblin\xff\xff arrives.\r\nIt waves.\r\n"
+    );
+    assert_eq!(
+      &broadcast.to_bytes(true)[..],
+      b"A goblin\xff\xff arrives.\r\nIt waves.\r\n\xff\xf9"
+    );
+    assert_eq!(&Broadcast::new(b"Hi").to_bytes(false)[..], b"Hi\r\n");
+  }
+
+  #[test]
+  fn test_event() {
+    let broadcast = Broadcast::new(b"Hi");
+    let plain = Parser::new();
+    let mut table = CompatibilityTable::new();
+    table.set_option(SGA, CompatibilityEntry::new(true, false, true, false));
+    let sga = Parser::with_support(table);
+    let (TelnetEvents::DataSend(first), TelnetEvents::DataSend(second)) =
+      (broadcast.event(&plain), broadcast.event(&sga))
+    else {
+      panic!("expected DataSend events");
+    };
+    assert_eq!(&first[..], b"Hi\r\n\xff\xf9");
+    assert_eq!(&second[..], b"Hi\r\n");
+    // Both share the encoded message.
+    assert_eq!(first.as_ptr(), second.as_ptr());
+  }
+}
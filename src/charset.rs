@@ -0,0 +1,334 @@
+//! Encoding and decoding of CHARSET ([RFC 2066]) subnegotiation payloads.
+//!
+//! The functions in this module operate on subnegotiation payloads with telnet `IAC`
+//! escaping already removed (see [`Parser::unescape_iac`]). Encoded payloads are
+//! unescaped as well, and are intended to be passed to [`Parser::subnegotiation`], which
+//! takes care of escaping.
+//!
+//! [RFC 2066]: https://www.rfc-editor.org/rfc/rfc2066
+//! [`Parser::unescape_iac`]: crate::Parser::unescape_iac
+//! [`Parser::subnegotiation`]: crate::Parser::subnegotiation
+
+use alloc::vec::Vec;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Request that the remote end use one of a list of character sets.
+pub const REQUEST: u8 = 1;
+/// Accept a character set offered in a `REQUEST`.
+pub const ACCEPTED: u8 = 2;
+/// Reject all of the character sets offered in a `REQUEST`.
+pub const REJECTED: u8 = 3;
+/// Send a translation table for a previously accepted character set.
+pub const TTABLE_IS: u8 = 4;
+/// Refuse a translation table and abandon the character set negotiation.
+pub const TTABLE_REJECTED: u8 = 5;
+/// Acknowledge a translation table was received and understood.
+pub const TTABLE_ACK: u8 = 6;
+/// Indicate a translation table was not received correctly and should be resent.
+pub const TTABLE_NAK: u8 = 7;
+
+/// The marker that prefixes a `REQUEST` character set list when translation tables are supported.
+const TTABLE_MARKER: &[u8] = b"[TTABLE]";
+
+/// The separator used when encoding character set lists and translation tables.
+const SEPARATOR: u8 = b' ';
+
+/// A decoded CHARSET subnegotiation message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CharsetMessage {
+  /// A list of acceptable character sets, in order of preference.
+  ///
+  /// If the sender is willing to receive a translation table, `ttable_version` holds the
+  /// highest table version it understands.
+  Request {
+    ttable_version: Option<u8>,
+    charsets: Vec<Bytes>,
+  },
+  /// The named character set was accepted.
+  Accepted(Bytes),
+  /// None of the requested character sets were accepted.
+  Rejected,
+  /// A translation table for the accepted character set.
+  TtableIs(TranslationTable),
+  /// The translation table was refused.
+  TtableRejected,
+  /// The translation table was accepted.
+  TtableAck,
+  /// The translation table was not understood and should be sent again.
+  TtableNak,
+}
+
+impl CharsetMessage {
+  /// Decode a CHARSET subnegotiation payload.
+  ///
+  /// Returns `None` if the payload is empty, has an unknown message code, or is malformed.
+  #[must_use]
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    let (&code, body) = payload.split_first()?;
+    match code {
+      REQUEST => decode_request(body),
+      ACCEPTED => Some(CharsetMessage::Accepted(Bytes::copy_from_slice(body))),
+      REJECTED => Some(CharsetMessage::Rejected),
+      TTABLE_IS => TranslationTable::decode(body).map(CharsetMessage::TtableIs),
+      TTABLE_REJECTED => Some(CharsetMessage::TtableRejected),
+      TTABLE_ACK => Some(CharsetMessage::TtableAck),
+      TTABLE_NAK => Some(CharsetMessage::TtableNak),
+      _ => None,
+    }
+  }
+
+  /// Encode the message into an (unescaped) subnegotiation payload.
+  #[must_use]
+  pub fn to_bytes(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    match self {
+      CharsetMessage::Request {
+        ttable_version,
+        charsets,
+      } => {
+        buf.put_u8(REQUEST);
+        if let Some(version) = ttable_version {
+          buf.put(TTABLE_MARKER);
+          buf.put_u8(*version);
+        }
+        for charset in charsets {
+          buf.put_u8(SEPARATOR);
+          buf.put(&charset[..]);
+        }
+      }
+      CharsetMessage::Accepted(charset) => {
+        buf.put_u8(ACCEPTED);
+        buf.put(&charset[..]);
+      }
+      CharsetMessage::Rejected => buf.put_u8(REJECTED),
+      CharsetMessage::TtableIs(table) => {
+        buf.put_u8(TTABLE_IS);
+        table.encode(&mut buf);
+      }
+      CharsetMessage::TtableRejected => buf.put_u8(TTABLE_REJECTED),
+      CharsetMessage::TtableAck => buf.put_u8(TTABLE_ACK),
+      CharsetMessage::TtableNak => buf.put_u8(TTABLE_NAK),
+    }
+    buf.freeze()
+  }
+}
+
+fn decode_request(body: &[u8]) -> Option<CharsetMessage> {
+  let (ttable_version, list) = match body.strip_prefix(TTABLE_MARKER) {
+    Some(rest) => {
+      let (&version, list) = rest.split_first()?;
+      (Some(version), list)
+    }
+    None => (None, body),
+  };
+  // An empty list has no separator either.
+  let charsets = match list.split_first() {
+    Some((&sep, names)) => names
+      .split(|&b| b == sep)
+      .filter(|name| !name.is_empty())
+      .map(Bytes::copy_from_slice)
+      .collect(),
+    None => Vec::new(),
+  };
+  Some(CharsetMessage::Request {
+    ttable_version,
+    charsets,
+  })
+}
+
+/// A version 1 CHARSET translation table.
+///
+/// `map_1` translates each of the `count_1` characters of `charset_1` into `charset_2`, and
+/// `map_2` translates each of the `count_2` characters of `charset_2` back into `charset_1`.
+/// Map entries are stored big-endian, each rounded up to a whole number of octets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TranslationTable {
+  pub charset_1: Bytes,
+  /// Size of a `charset_1` character, in bits.
+  pub size_1: u8,
+  /// Number of characters in `charset_1`. Only the low 24 bits are significant.
+  pub count_1: u32,
+  pub charset_2: Bytes,
+  /// Size of a `charset_2` character, in bits.
+  pub size_2: u8,
+  /// Number of characters in `charset_2`. Only the low 24 bits are significant.
+  pub count_2: u32,
+  pub map_1: Bytes,
+  pub map_2: Bytes,
+}
+
+impl TranslationTable {
+  /// The only translation table version defined by RFC 2066.
+  pub const VERSION: u8 = 1;
+
+  /// Translate a character of `charset_1` into `charset_2`.
+  #[must_use]
+  pub fn forward(&self, index: u32) -> Option<u32> {
+    map_entry(&self.map_1, self.size_2, self.count_1, index)
+  }
+
+  /// Translate a character of `charset_2` into `charset_1`.
+  #[must_use]
+  pub fn reverse(&self, index: u32) -> Option<u32> {
+    map_entry(&self.map_2, self.size_1, self.count_2, index)
+  }
+
+  fn decode(body: &[u8]) -> Option<Self> {
+    let (&version, rest) = body.split_first()?;
+    if version != Self::VERSION {
+      return None;
+    }
+    let (&sep, rest) = rest.split_first()?;
+    let (charset_1, rest) = split_at_separator(rest, sep)?;
+    let (size_1, count_1, rest) = decode_size_and_count(rest)?;
+    let (charset_2, rest) = split_at_separator(rest, sep)?;
+    let (size_2, count_2, rest) = decode_size_and_count(rest)?;
+
+    let map_1_len = map_len(size_2, count_1);
+    let map_2_len = map_len(size_1, count_2);
+    if rest.len() != map_1_len.checked_add(map_2_len)? {
+      return None;
+    }
+    let (map_1, map_2) = rest.split_at(map_1_len);
+
+    Some(Self {
+      charset_1: Bytes::copy_from_slice(charset_1),
+      size_1,
+      count_1,
+      charset_2: Bytes::copy_from_slice(charset_2),
+      size_2,
+      count_2,
+      map_1: Bytes::copy_from_slice(map_1),
+      map_2: Bytes::copy_from_slice(map_2),
+    })
+  }
+
+  fn encode(&self, buf: &mut BytesMut) {
+    buf.put_u8(Self::VERSION);
+    buf.put_u8(SEPARATOR);
+    buf.put(&self.charset_1[..]);
+    buf.put_u8(SEPARATOR);
+    buf.put_u8(self.size_1);
+    buf.put_uint(u64::from(self.count_1), 3);
+    buf.put(&self.charset_2[..]);
+    buf.put_u8(SEPARATOR);
+    buf.put_u8(self.size_2);
+    buf.put_uint(u64::from(self.count_2), 3);
+    buf.put(&self.map_1[..]);
+    buf.put(&self.map_2[..]);
+  }
+}
+
+fn split_at_separator(data: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+  let pos = data.iter().position(|&b| b == sep)?;
+  Some((&data[..pos], &data[pos + 1..]))
+}
+
+fn decode_size_and_count(data: &[u8]) -> Option<(u8, u32, &[u8])> {
+  match data {
+    [size, c1, c2, c3, rest @ ..] => Some((*size, u32::from_be_bytes([0, *c1, *c2, *c3]), rest)),
+    _ => None,
+  }
+}
+
+fn entry_width(size: u8) -> usize {
+  usize::from(size).div_ceil(8)
+}
+
+fn map_len(size: u8, count: u32) -> usize {
+  entry_width(size) * count as usize
+}
+
+fn map_entry(map: &[u8], size: u8, count: u32, index: u32) -> Option<u32> {
+  let width = entry_width(size);
+  if index >= count || width == 0 || width > 4 {
+    return None;
+  }
+  let start = width * index as usize;
+  let entry = map.get(start..start + width)?;
+  Some(entry.iter().fold(0, |acc, &b| (acc << 8) | u32::from(b)))
+}
+
+#[cfg(test)]
+mod test_charset {
+  use super::*;
+  use alloc::vec;
+
+  #[test]
+  fn test_request_roundtrip() {
+    let payload = b"\x01[TTABLE]\x01;UTF-8;ISO-8859-1";
+    let msg = CharsetMessage::decode(payload).unwrap();
+    assert_eq!(
+      msg,
+      CharsetMessage::Request {
+        ttable_version: Some(1),
+        charsets: vec![
+          Bytes::from_static(b"UTF-8"),
+          Bytes::from_static(b"ISO-8859-1")
+        ],
+      }
+    );
+    assert_eq!(CharsetMessage::decode(&msg.to_bytes()), Some(msg));
+
+    for ttable_version in [None, Some(1)] {
+      let empty = CharsetMessage::Request {
+        ttable_version,
+        charsets: vec![],
+      };
+      assert_eq!(CharsetMessage::decode(&empty.to_bytes()), Some(empty));
+    }
+  }
+
+  #[test]
+  fn test_ttable_roundtrip() {
+    let table = TranslationTable {
+      charset_1: Bytes::from_static(b"LEGACY"),
+      size_1: 8,
+      count_1: 3,
+      charset_2: Bytes::from_static(b"US-ASCII"),
+      size_2: 7,
+      count_2: 2,
+      map_1: Bytes::from_static(b"abc"),
+      map_2: Bytes::from_static(&[0, 2]),
+    };
+    let msg = CharsetMessage::TtableIs(table.clone());
+    assert_eq!(CharsetMessage::decode(&msg.to_bytes()), Some(msg));
+    assert_eq!(table.forward(1), Some(u32::from(b'b')));
+    assert_eq!(table.reverse(1), Some(2));
+    assert_eq!(table.forward(3), None);
+  }
+
+  #[test]
+  fn test_ttable_truncated() {
+    let msg = CharsetMessage::TtableIs(TranslationTable {
+      charset_1: Bytes::from_static(b"A"),
+      size_1: 8,
+      count_1: 2,
+      charset_2: Bytes::from_static(b"B"),
+      size_2: 8,
+      count_2: 2,
+      map_1: Bytes::from_static(&[1, 0]),
+      map_2: Bytes::from_static(&[1, 0]),
+    });
+    let encoded = msg.to_bytes();
+    assert_eq!(CharsetMessage::decode(&encoded[..encoded.len() - 1]), None);
+  }
+
+  #[test]
+  fn test_ttable_responses() {
+    assert_eq!(
+      CharsetMessage::decode(&[TTABLE_ACK]),
+      Some(CharsetMessage::TtableAck)
+    );
+    assert_eq!(
+      CharsetMessage::decode(&[TTABLE_NAK]),
+      Some(CharsetMessage::TtableNak)
+    );
+    assert_eq!(
+      CharsetMessage::decode(&[TTABLE_REJECTED]),
+      Some(CharsetMessage::TtableRejected)
+    );
+    assert_eq!(CharsetMessage::decode(&[99]), None);
+  }
+}
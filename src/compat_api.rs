@@ -0,0 +1,185 @@
+//! A shim with the API of the original `libtelnet-rs`, for code migrating from it. Enabled with the `compat_api`
+//! feature.
+//!
+//! Most of the `libtelnet-rs` API is kept by this crate under the same names, but its `TelnetEvents` has gained
+//! variants for opt-in features and is `#[non_exhaustive]`. The [`Parser`] here returns the original
+//! [`events::TelnetEvents`] instead, with only its six variants, so exhaustive matches written against `libtelnet-rs`
+//! keep compiling. Code with such matches can migrate by renaming the dependency with this feature enabled, and
+//! importing `Parser` and `events::TelnetEvents` from this module rather than the crate root:
+//!
+//! ```toml
+//! [dependencies]
+//! libtelnet-rs = { package = "libmudtelnet", version = "3", features = ["compat_api"] }
+//! ```
+//!
+//! ```
+//! use libmudtelnet::compat_api::events::TelnetEvents;
+//! use libmudtelnet::compat_api::Parser;
+//!
+//! let mut parser = Parser::new();
+//! for event in parser.receive(b"look\xff\xf9") {
+//!   match event {
+//!     TelnetEvents::DataReceive(data) => assert_eq!(&data[..], b"look"),
+//!     TelnetEvents::IAC(iac) => assert_eq!(iac.command, 249),
+//!     TelnetEvents::Negotiation(_)
+//!     | TelnetEvents::Subnegotiation(_)
+//!     | TelnetEvents::DataSend(_)
+//!     | TelnetEvents::DecompressImmediate(_) => unreachable!(),
+//!   }
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::ops::{Deref, DerefMut};
+
+use crate::bytes::Bytes;
+
+pub use crate::{bytes, compatibility, telnet};
+
+use compatibility::CompatibilityTable;
+use events::TelnetEvents;
+
+pub mod events;
+
+/// A `Parser` with the API of the original `libtelnet-rs`, returning its [`events::TelnetEvents`].
+///
+/// Dereferences to the wrapped [`crate::Parser`], for the `options` field and the methods that don't return
+/// events.
+///
+/// # Notes
+///
+/// Events for features enabled through the wrapped parser, which the original `TelnetEvents` can't represent, are
+/// left out of the events returned by `receive`.
+#[derive(Default)]
+pub struct Parser {
+  inner: crate::Parser,
+}
+
+impl Parser {
+  /// Create a default, empty Parser with an internal buffer capacity of 128 bytes.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Create an empty parser, setting the initial internal buffer capacity.
+  #[must_use]
+  pub fn with_capacity(size: usize) -> Self {
+    crate::Parser::with_capacity(size).into()
+  }
+
+  /// Create a parser, directly supplying a `CompatibilityTable`.
+  #[must_use]
+  pub fn with_support(table: CompatibilityTable) -> Self {
+    crate::Parser::with_support(table).into()
+  }
+
+  /// Create a parser, setting the initial internal buffer capacity and directly supplying a `CompatibilityTable`.
+  #[must_use]
+  pub fn with_support_and_capacity(size: usize, table: CompatibilityTable) -> Self {
+    crate::Parser::with_support_and_capacity(size, table).into()
+  }
+
+  /// Receive bytes into the internal buffer.
+  ///
+  /// # Arguments
+  ///
+  /// * `data` - The bytes to be received. This should be sourced from the remote side of a connection.
+  ///
+  /// # Returns
+  ///
+  /// `Vec<events::TelnetEvents>` - Any events parsed from the internal buffer with the new bytes.
+  pub fn receive(&mut self, data: &[u8]) -> Vec<TelnetEvents> {
+    self
+      .inner
+      .receive(data)
+      .into_iter()
+      .filter_map(|event| TelnetEvents::try_from(event).ok())
+      .collect()
+  }
+
+  /// Escape IAC bytes in data that is to be transmitted and treated as a non-IAC sequence.
+  pub fn escape_iac<T>(data: T) -> Bytes
+  where
+    Bytes: From<T>,
+  {
+    crate::Parser::escape_iac(data)
+  }
+
+  /// Reverse escaped IAC bytes for non-IAC sequences and data.
+  pub fn unescape_iac<T>(data: T) -> Bytes
+  where
+    Bytes: From<T>,
+  {
+    crate::Parser::unescape_iac(data)
+  }
+
+  /// Negotiate an option.
+  pub fn negotiate(&mut self, command: u8, option: u8) -> TelnetEvents {
+    data_send(self.inner.negotiate(command, option))
+  }
+
+  /// Indicate to the other side that you are able and wanting to utilize an option.
+  pub fn _will(&mut self, option: u8) -> Option<TelnetEvents> {
+    self.inner._will(option).map(data_send)
+  }
+
+  /// Indicate to the other side that you are not wanting to utilize an option.
+  pub fn _wont(&mut self, option: u8) -> Option<TelnetEvents> {
+    self.inner._wont(option).map(data_send)
+  }
+
+  /// Indicate to the other side that you would like them to utilize an option.
+  pub fn _do(&mut self, option: u8) -> Option<TelnetEvents> {
+    self.inner._do(option).map(data_send)
+  }
+
+  /// Indicate to the other side that you would like them to stop utilizing an option.
+  pub fn _dont(&mut self, option: u8) -> Option<TelnetEvents> {
+    self.inner._dont(option).map(data_send)
+  }
+
+  /// Send a subnegotiation for a locally supported option.
+  pub fn subnegotiation<T>(&mut self, option: u8, data: T) -> Option<TelnetEvents>
+  where
+    Bytes: From<T>,
+  {
+    self.inner.subnegotiation(option, data).map(data_send)
+  }
+
+  /// Send a subnegotiation for a locally supported option, using a string instead of raw byte values.
+  pub fn subnegotiation_text(&mut self, option: u8, text: &str) -> Option<TelnetEvents> {
+    self.inner.subnegotiation_text(option, text).map(data_send)
+  }
+
+  /// Directly send a string, with appended `\r\n`, to the remote end, along with necessary IAC escaping.
+  pub fn send_text(&mut self, text: &str) -> TelnetEvents {
+    data_send(self.inner.send_text(text))
+  }
+}
+
+impl From<crate::Parser> for Parser {
+  fn from(inner: crate::Parser) -> Self {
+    Self { inner }
+  }
+}
+
+impl Deref for Parser {
+  type Target = crate::Parser;
+
+  fn deref(&self) -> &crate::Parser {
+    &self.inner
+  }
+}
+
+impl DerefMut for Parser {
+  fn deref_mut(&mut self) -> &mut crate::Parser {
+    &mut self.inner
+  }
+}
+
+/// Convert an event returned for data to send, which is always a `DataSend` event.
+fn data_send(event: crate::events::TelnetEvents) -> TelnetEvents {
+  TelnetEvents::DataSend(event.to_bytes())
+}
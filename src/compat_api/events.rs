@@ -0,0 +1,138 @@
+//! The events of the original `libtelnet-rs`.
+
+use core::convert::TryFrom;
+
+use crate::bytes::Bytes;
+
+pub use crate::events::{TelnetIAC, TelnetNegotiation, TelnetSubnegotiation};
+
+/// An enum representing various telnet events, with only the variants of the original `libtelnet-rs`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TelnetEvents {
+  /// An IAC command sequence.
+  IAC(TelnetIAC),
+  /// An IAC negotiation sequence.
+  Negotiation(TelnetNegotiation),
+  /// An IAC subnegotiation sequence.
+  Subnegotiation(TelnetSubnegotiation),
+  /// Regular data received from the remote end.
+  DataReceive(Bytes),
+  /// Any data to be sent to the remote end.
+  DataSend(Bytes),
+  /// MCCP2/3 compatibility. MUST DECOMPRESS THIS DATA BEFORE PARSING
+  DecompressImmediate(Bytes),
+}
+
+impl From<TelnetIAC> for TelnetEvents {
+  fn from(iac: TelnetIAC) -> Self {
+    TelnetEvents::IAC(iac)
+  }
+}
+
+impl From<TelnetNegotiation> for TelnetEvents {
+  fn from(neg: TelnetNegotiation) -> Self {
+    TelnetEvents::Negotiation(neg)
+  }
+}
+
+impl From<TelnetSubnegotiation> for TelnetEvents {
+  fn from(sub: TelnetSubnegotiation) -> Self {
+    TelnetEvents::Subnegotiation(sub)
+  }
+}
+
+impl TelnetEvents {
+  /// Helper method to generate a `TelnetEvents::DataSend`.
+  #[deprecated(since = "0.2.1", note = "Construct enum variant directly or use into.")]
+  pub fn build_send(buffer: Bytes) -> Self {
+    TelnetEvents::DataSend(buffer)
+  }
+
+  /// Helper method to generate a `TelnetEvents::DataReceive`.
+  #[deprecated(since = "0.2.1", note = "Construct enum variant directly or use into.")]
+  pub fn build_receive(buffer: Bytes) -> Self {
+    TelnetEvents::DataReceive(buffer)
+  }
+
+  /// Helper method to generate a `TelnetEvents::IAC`.
+  #[must_use]
+  #[deprecated(since = "0.2.1", note = "Construct enum variant directly or use into.")]
+  pub fn build_iac(command: u8) -> TelnetEvents {
+    TelnetEvents::IAC(TelnetIAC::new(command))
+  }
+
+  /// Helper method to generate a `TelnetEvents::Negotiation`.
+  #[must_use]
+  #[deprecated(since = "0.2.1", note = "Construct enum variant directly or use into.")]
+  pub fn build_negotiation(command: u8, option: u8) -> Self {
+    TelnetEvents::Negotiation(TelnetNegotiation::new(command, option))
+  }
+
+  /// Helper method to generate a `TelnetEvents::Subnegotiation`.
+  #[deprecated(since = "0.2.1", note = "Construct enum variant directly or use into.")]
+  pub fn build_subnegotiation(option: u8, buffer: Bytes) -> Self {
+    TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(option, buffer))
+  }
+
+  #[must_use]
+  pub fn to_bytes(self) -> Bytes {
+    match self {
+      TelnetEvents::IAC(iac) => iac.to_bytes(),
+      TelnetEvents::Negotiation(neg) => neg.to_bytes(),
+      TelnetEvents::Subnegotiation(sub) => sub.to_bytes(),
+      TelnetEvents::DataReceive(data)
+      | TelnetEvents::DataSend(data)
+      | TelnetEvents::DecompressImmediate(data) => data,
+    }
+  }
+}
+
+impl TryFrom<crate::events::TelnetEvents> for TelnetEvents {
+  /// Events the original `TelnetEvents` has no variant for are given back unchanged.
+  type Error = crate::events::TelnetEvents;
+
+  fn try_from(event: crate::events::TelnetEvents) -> Result<Self, Self::Error> {
+    use crate::events::TelnetEvents as Events;
+
+    match event {
+      Events::IAC(iac) => Ok(TelnetEvents::IAC(iac)),
+      Events::Negotiation(neg) => Ok(TelnetEvents::Negotiation(neg)),
+      Events::Subnegotiation(sub) => Ok(TelnetEvents::Subnegotiation(sub)),
+      Events::DataReceive(data) => Ok(TelnetEvents::DataReceive(data)),
+      Events::DataSend(data) => Ok(TelnetEvents::DataSend(data)),
+      Events::DecompressImmediate(data) => Ok(TelnetEvents::DecompressImmediate(data)),
+      event => Err(event),
+    }
+  }
+}
+
+impl From<TelnetEvents> for crate::events::TelnetEvents {
+  fn from(event: TelnetEvents) -> Self {
+    match event {
+      TelnetEvents::IAC(iac) => Self::IAC(iac),
+      TelnetEvents::Negotiation(neg) => Self::Negotiation(neg),
+      TelnetEvents::Subnegotiation(sub) => Self::Subnegotiation(sub),
+      TelnetEvents::DataReceive(data) => Self::DataReceive(data),
+      TelnetEvents::DataSend(data) => Self::DataSend(data),
+      TelnetEvents::DecompressImmediate(data) => Self::DecompressImmediate(data),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_events {
+  use super::*;
+
+  #[test]
+  fn test_conversion() {
+    let event = TelnetEvents::IAC(TelnetIAC::new(249));
+    assert_eq!(
+      TelnetEvents::try_from(crate::events::TelnetEvents::from(event.clone())),
+      Ok(event)
+    );
+    assert_eq!(
+      TelnetEvents::try_from(crate::events::TelnetEvents::ShutdownComplete),
+      Err(crate::events::TelnetEvents::ShutdownComplete)
+    );
+  }
+}
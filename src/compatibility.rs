@@ -1,3 +1,9 @@
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(target_has_atomic = "ptr")]
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
 /// An expansion of a bitmask contained in `CompatibilityTable`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CompatibilityEntry {
@@ -61,7 +67,7 @@ pub struct CompatibilityTable {
 
 impl Default for CompatibilityTable {
   fn default() -> Self {
-    Self { options: [0; 256] }
+    Self::new()
   }
 }
 
@@ -76,8 +82,8 @@ impl CompatibilityTable {
   pub const REMOTE_STATE: u8 = 1 << 3;
 
   #[must_use]
-  pub fn new() -> Self {
-    Self::default()
+  pub const fn new() -> Self {
+    Self { options: [0; 256] }
   }
 
   /// Create a table with some option values set.
@@ -89,15 +95,59 @@ impl CompatibilityTable {
   /// # Notes
   ///
   /// An option bitmask can be generated using the `CompatibilityEntry` struct, using `entry.into_u8()`.
+  ///
+  /// This is a `const fn`, so a server's supported options can be defined once as a `static` and cloned for
+  /// each connection:
+  ///
+  /// ```
+  /// use libmudtelnet::compatibility::CompatibilityTable;
+  /// use libmudtelnet::telnet::op_option::{GMCP, NAWS};
+  /// use libmudtelnet::Parser;
+  ///
+  /// static SUPPORTED: CompatibilityTable = CompatibilityTable::from_options(&[
+  ///   (GMCP, CompatibilityTable::ENABLED_LOCAL),
+  ///   (NAWS, CompatibilityTable::ENABLED_REMOTE),
+  /// ]);
+  ///
+  /// let parser = Parser::with_support(SUPPORTED.clone());
+  /// assert!(parser.options.get_option(NAWS).remote);
+  /// ```
   #[must_use]
-  pub fn from_options(values: &[(u8, u8)]) -> Self {
+  pub const fn from_options(values: &[(u8, u8)]) -> Self {
     let mut options = [0; 256];
-    for (opt, val) in values {
-      options[*opt as usize] = *val;
+    let mut index = 0;
+    while index < values.len() {
+      let (opt, val) = values[index];
+      options[opt as usize] = val;
+      index += 1;
     }
     Self { options }
   }
 
+  /// Create a table supporting the options commonly used by MUD clients.
+  ///
+  /// # Notes
+  ///
+  /// The remote end may enable `ECHO`, `SGA`, `EOR`, `MSP` and `MXP`. `NAWS` and `TTYPE` may be enabled locally.
+  /// `GMCP`, `MSSP` and `CHARSET` may be enabled in both directions. MCCP is not included, since compressed data
+  /// must be decompressed by the application.
+  #[must_use]
+  pub fn mud_client() -> Self {
+    use crate::telnet::op_option::{CHARSET, ECHO, EOR, GMCP, MSP, MSSP, MXP, NAWS, SGA, TTYPE};
+
+    let mut table = Self::new();
+    for option in [ECHO, SGA, EOR, MSP, MXP] {
+      table.support_remote(option);
+    }
+    for option in [NAWS, TTYPE] {
+      table.support_local(option);
+    }
+    for option in [GMCP, MSSP, CHARSET] {
+      table.support(option);
+    }
+    table
+  }
+
   /// Enable local support for an option.
   pub fn support_local(&mut self, option: u8) {
     let mut opt = CompatibilityEntry::from(self.options[option as usize]);
@@ -142,6 +192,93 @@ impl CompatibilityTable {
   }
 }
 
+/// Which end of the connection an option is negotiated for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Direction {
+  /// The option is used by this end, negotiated with `WILL` and `WONT`.
+  Local,
+  /// The option is used by the remote end, negotiated with `DO` and `DONT`.
+  Remote,
+}
+
+/// The options that were enabled on a connection, as returned by `Parser::prepare_reconnect`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EnabledOptions {
+  /// Options that were enabled on this end.
+  pub local: Vec<u8>,
+  /// Options that were enabled on the remote end.
+  pub remote: Vec<u8>,
+}
+
+/// A read-only view of a parser's `CompatibilityTable` that can be shared with other threads, such as a UI thread
+/// showing which options are enabled, without borrowing the parser. Created with `Parser::shared_options`.
+///
+/// Reads are lock-free. Each option's entry is read atomically, but a change the parser makes to several options at
+/// once may be seen part way through.
+///
+/// Only available on targets with atomic pointer operations.
+#[cfg(target_has_atomic = "ptr")]
+#[derive(Clone, Debug)]
+pub struct SharedTable {
+  inner: Arc<SharedOptions>,
+}
+
+#[cfg(target_has_atomic = "ptr")]
+#[derive(Debug)]
+struct SharedOptions {
+  options: [AtomicU8; 256],
+  version: AtomicUsize,
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl SharedTable {
+  pub(crate) fn new(table: &CompatibilityTable) -> Self {
+    Self {
+      inner: Arc::new(SharedOptions {
+        options: core::array::from_fn(|option| AtomicU8::new(table.options[option])),
+        version: AtomicUsize::new(0),
+      }),
+    }
+  }
+
+  /// Copy any changed entries of `table` into the shared view.
+  pub(crate) fn publish(&self, table: &CompatibilityTable) {
+    let mut changed = false;
+    for (shared, &value) in self.inner.options.iter().zip(table.options.iter()) {
+      if shared.load(Ordering::Relaxed) != value {
+        shared.store(value, Ordering::Release);
+        changed = true;
+      }
+    }
+    if changed {
+      self.inner.version.fetch_add(1, Ordering::Release);
+    }
+  }
+
+  /// Retrieve a `CompatibilityEntry` generated from the current state of the option value.
+  #[must_use]
+  pub fn get_option(&self, option: u8) -> CompatibilityEntry {
+    CompatibilityEntry::from(self.inner.options[option as usize].load(Ordering::Acquire))
+  }
+
+  /// Copy the current state of every option into a `CompatibilityTable`.
+  #[must_use]
+  pub fn snapshot(&self) -> CompatibilityTable {
+    let mut options = [0; 256];
+    for (value, shared) in options.iter_mut().zip(self.inner.options.iter()) {
+      *value = shared.load(Ordering::Acquire);
+    }
+    CompatibilityTable { options }
+  }
+
+  /// A counter that increases whenever the parser changes the table, for cheaply checking whether anything changed
+  /// since it was last read.
+  #[must_use]
+  pub fn version(&self) -> usize {
+    self.inner.version.load(Ordering::Acquire)
+  }
+}
+
 #[cfg(test)]
 mod test_compat {
   use super::*;
@@ -163,4 +300,17 @@ mod test_compat {
     assert!(!entry.remote_state);
     assert!(!entry.local_state);
   }
+
+  #[test]
+  fn test_const_from_options() {
+    const TABLE: CompatibilityTable = CompatibilityTable::from_options(&[
+      (GMCP, CompatibilityTable::ENABLED_LOCAL),
+      (GMCP, CompatibilityTable::ENABLED_REMOTE),
+    ]);
+    assert_eq!(
+      TABLE.get_option(GMCP),
+      CompatibilityEntry::new(false, true, false, false)
+    );
+    assert_eq!(TABLE.get_option(0), CompatibilityEntry::from(0));
+  }
 }
@@ -0,0 +1,346 @@
+//! Encoding and decoding of COM-PORT-OPTION ([RFC 2217]) subnegotiation payloads.
+//!
+//! The option lets a client control the serial port behind a telnet-to-serial access server.
+//! The client sends commands, and the access server replies with the same command code plus
+//! 100, which [`ComPortMessage::decode`] reports as [`Sender::Server`].
+//!
+//! The functions in this module operate on subnegotiation payloads with telnet `IAC` escaping
+//! already removed. Baud rates regularly contain `0xFF` bytes, so register
+//! [`DecodePolicy::Unescape`] for the option with [`Parser::set_decode_policy`]. Encoded
+//! payloads are intended to be passed to [`Parser::subnegotiation`], which takes care of
+//! escaping.
+//!
+//! [RFC 2217]: https://www.rfc-editor.org/rfc/rfc2217
+//! [`DecodePolicy::Unescape`]: crate::events::DecodePolicy::Unescape
+//! [`Parser::set_decode_policy`]: crate::Parser::set_decode_policy
+//! [`Parser::subnegotiation`]: crate::Parser::subnegotiation
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Identify the sender with a free form text signature.
+pub const SIGNATURE: u8 = 0;
+/// Set the baud rate.
+pub const SET_BAUDRATE: u8 = 1;
+/// Set the number of data bits.
+pub const SET_DATASIZE: u8 = 2;
+/// Set the parity.
+pub const SET_PARITY: u8 = 3;
+/// Set the number of stop bits.
+pub const SET_STOPSIZE: u8 = 4;
+/// Set flow control, `BREAK`, `DTR` or `RTS`.
+pub const SET_CONTROL: u8 = 5;
+/// Report a change in the line state.
+pub const NOTIFY_LINESTATE: u8 = 6;
+/// Report a change in the modem state.
+pub const NOTIFY_MODEMSTATE: u8 = 7;
+/// Ask the other end to stop sending data.
+pub const FLOWCONTROL_SUSPEND: u8 = 8;
+/// Ask the other end to resume sending data.
+pub const FLOWCONTROL_RESUME: u8 = 9;
+/// Set which line state changes are reported.
+pub const SET_LINESTATE_MASK: u8 = 10;
+/// Set which modem state changes are reported.
+pub const SET_MODEMSTATE_MASK: u8 = 11;
+/// Discard buffered data.
+pub const PURGE_DATA: u8 = 12;
+
+/// Added to a command code in the access server's replies.
+pub const SERVER_OFFSET: u8 = 100;
+
+/// `SET-BAUDRATE`, `SET-DATASIZE`, `SET-PARITY`, `SET-STOPSIZE` and `SET-CONTROL` value:
+/// request the current setting without changing it.
+pub const REQUEST: u8 = 0;
+
+/// `SET-PARITY` value: no parity.
+pub const PARITY_NONE: u8 = 1;
+/// `SET-PARITY` value: odd parity.
+pub const PARITY_ODD: u8 = 2;
+/// `SET-PARITY` value: even parity.
+pub const PARITY_EVEN: u8 = 3;
+/// `SET-PARITY` value: mark parity.
+pub const PARITY_MARK: u8 = 4;
+/// `SET-PARITY` value: space parity.
+pub const PARITY_SPACE: u8 = 5;
+
+/// `SET-STOPSIZE` value: one stop bit.
+pub const STOPSIZE_1: u8 = 1;
+/// `SET-STOPSIZE` value: two stop bits.
+pub const STOPSIZE_2: u8 = 2;
+/// `SET-STOPSIZE` value: one and a half stop bits.
+pub const STOPSIZE_1_5: u8 = 3;
+
+/// `SET-CONTROL` value: request the outbound flow control setting.
+pub const CONTROL_FLOW_REQUEST: u8 = 0;
+/// `SET-CONTROL` value: no outbound flow control.
+pub const CONTROL_FLOW_NONE: u8 = 1;
+/// `SET-CONTROL` value: XON/XOFF outbound flow control.
+pub const CONTROL_FLOW_XONXOFF: u8 = 2;
+/// `SET-CONTROL` value: hardware outbound flow control.
+pub const CONTROL_FLOW_HARDWARE: u8 = 3;
+/// `SET-CONTROL` value: request the `BREAK` state.
+pub const CONTROL_BREAK_REQUEST: u8 = 4;
+/// `SET-CONTROL` value: set the `BREAK` state on.
+pub const CONTROL_BREAK_ON: u8 = 5;
+/// `SET-CONTROL` value: set the `BREAK` state off.
+pub const CONTROL_BREAK_OFF: u8 = 6;
+/// `SET-CONTROL` value: request the `DTR` signal state.
+pub const CONTROL_DTR_REQUEST: u8 = 7;
+/// `SET-CONTROL` value: set the `DTR` signal on.
+pub const CONTROL_DTR_ON: u8 = 8;
+/// `SET-CONTROL` value: set the `DTR` signal off.
+pub const CONTROL_DTR_OFF: u8 = 9;
+/// `SET-CONTROL` value: request the `RTS` signal state.
+pub const CONTROL_RTS_REQUEST: u8 = 10;
+/// `SET-CONTROL` value: set the `RTS` signal on.
+pub const CONTROL_RTS_ON: u8 = 11;
+/// `SET-CONTROL` value: set the `RTS` signal off.
+pub const CONTROL_RTS_OFF: u8 = 12;
+/// `SET-CONTROL` value: request the inbound flow control setting.
+pub const CONTROL_INBOUND_FLOW_REQUEST: u8 = 13;
+/// `SET-CONTROL` value: no inbound flow control.
+pub const CONTROL_INBOUND_FLOW_NONE: u8 = 14;
+/// `SET-CONTROL` value: XOFF/XON inbound flow control.
+pub const CONTROL_INBOUND_FLOW_XONXOFF: u8 = 15;
+/// `SET-CONTROL` value: hardware inbound flow control.
+pub const CONTROL_INBOUND_FLOW_HARDWARE: u8 = 16;
+/// `SET-CONTROL` value: `DCD` outbound flow control.
+pub const CONTROL_FLOW_DCD: u8 = 17;
+/// `SET-CONTROL` value: `DTR` inbound flow control.
+pub const CONTROL_INBOUND_FLOW_DTR: u8 = 18;
+/// `SET-CONTROL` value: `DSR` outbound flow control.
+pub const CONTROL_FLOW_DSR: u8 = 19;
+
+/// Line state bit: time-out error.
+pub const LINESTATE_TIMEOUT: u8 = 128;
+/// Line state bit: transfer shift register empty.
+pub const LINESTATE_TSRE: u8 = 64;
+/// Line state bit: transfer holding register empty.
+pub const LINESTATE_THRE: u8 = 32;
+/// Line state bit: break detected.
+pub const LINESTATE_BREAK: u8 = 16;
+/// Line state bit: framing error.
+pub const LINESTATE_FRAMING_ERROR: u8 = 8;
+/// Line state bit: parity error.
+pub const LINESTATE_PARITY_ERROR: u8 = 4;
+/// Line state bit: overrun error.
+pub const LINESTATE_OVERRUN_ERROR: u8 = 2;
+/// Line state bit: data ready.
+pub const LINESTATE_DATA_READY: u8 = 1;
+
+/// Modem state bit: carrier detect.
+pub const MODEMSTATE_CD: u8 = 128;
+/// Modem state bit: ring indicator.
+pub const MODEMSTATE_RI: u8 = 64;
+/// Modem state bit: data set ready.
+pub const MODEMSTATE_DSR: u8 = 32;
+/// Modem state bit: clear to send.
+pub const MODEMSTATE_CTS: u8 = 16;
+/// Modem state bit: carrier detect changed.
+pub const MODEMSTATE_DELTA_CD: u8 = 8;
+/// Modem state bit: trailing edge of the ring indicator.
+pub const MODEMSTATE_TRAILING_RI: u8 = 4;
+/// Modem state bit: data set ready changed.
+pub const MODEMSTATE_DELTA_DSR: u8 = 2;
+/// Modem state bit: clear to send changed.
+pub const MODEMSTATE_DELTA_CTS: u8 = 1;
+
+/// `PURGE-DATA` value: discard the receive buffer.
+pub const PURGE_RX: u8 = 1;
+/// `PURGE-DATA` value: discard the transmit buffer.
+pub const PURGE_TX: u8 = 2;
+/// `PURGE-DATA` value: discard both buffers.
+pub const PURGE_BOTH: u8 = 3;
+
+/// Which end of the connection sent a COM-PORT-OPTION message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Sender {
+  /// The client controlling the serial port.
+  Client,
+  /// The access server the serial port is attached to.
+  Server,
+}
+
+/// A decoded COM-PORT-OPTION subnegotiation message.
+///
+/// Values are the constants of this module. Sent by the client, a [`REQUEST`] value asks for
+/// the current setting. Sent by the access server, the value is the setting now in effect.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ComPortMessage {
+  /// A free form text signature. Empty when requesting the other end's signature.
+  Signature(Bytes),
+  /// The baud rate, in bits per second.
+  SetBaudRate(u32),
+  /// The number of data bits, from 5 to 8.
+  SetDataSize(u8),
+  /// One of the `PARITY_*` values.
+  SetParity(u8),
+  /// One of the `STOPSIZE_*` values.
+  SetStopSize(u8),
+  /// One of the `CONTROL_*` values.
+  SetControl(u8),
+  /// The `LINESTATE_*` bits that are set.
+  NotifyLineState(u8),
+  /// The `MODEMSTATE_*` bits that are set.
+  NotifyModemState(u8),
+  /// Ask the other end to stop sending data.
+  FlowControlSuspend,
+  /// Ask the other end to resume sending data.
+  FlowControlResume,
+  /// The `LINESTATE_*` bits to report changes of.
+  SetLineStateMask(u8),
+  /// The `MODEMSTATE_*` bits to report changes of.
+  SetModemStateMask(u8),
+  /// One of the `PURGE_*` values.
+  PurgeData(u8),
+}
+
+impl ComPortMessage {
+  /// Decode a COM-PORT-OPTION subnegotiation payload.
+  ///
+  /// Returns `None` if the payload is empty, has an unknown command code, or is malformed.
+  #[must_use]
+  pub fn decode(payload: &[u8]) -> Option<(Sender, Self)> {
+    let (&code, body) = payload.split_first()?;
+    let (sender, command) = match code.checked_sub(SERVER_OFFSET) {
+      Some(command) => (Sender::Server, command),
+      None => (Sender::Client, code),
+    };
+    let value = || match body {
+      [value] => Some(*value),
+      _ => None,
+    };
+    let message = match command {
+      SIGNATURE => ComPortMessage::Signature(Bytes::copy_from_slice(body)),
+      SET_BAUDRATE => match body {
+        [a, b, c, d] => ComPortMessage::SetBaudRate(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => return None,
+      },
+      SET_DATASIZE => ComPortMessage::SetDataSize(value()?),
+      SET_PARITY => ComPortMessage::SetParity(value()?),
+      SET_STOPSIZE => ComPortMessage::SetStopSize(value()?),
+      SET_CONTROL => ComPortMessage::SetControl(value()?),
+      NOTIFY_LINESTATE => ComPortMessage::NotifyLineState(value()?),
+      NOTIFY_MODEMSTATE => ComPortMessage::NotifyModemState(value()?),
+      FLOWCONTROL_SUSPEND if body.is_empty() => ComPortMessage::FlowControlSuspend,
+      FLOWCONTROL_RESUME if body.is_empty() => ComPortMessage::FlowControlResume,
+      SET_LINESTATE_MASK => ComPortMessage::SetLineStateMask(value()?),
+      SET_MODEMSTATE_MASK => ComPortMessage::SetModemStateMask(value()?),
+      PURGE_DATA => ComPortMessage::PurgeData(value()?),
+      _ => return None,
+    };
+    Some((sender, message))
+  }
+
+  /// Encode the message into an (unescaped) subnegotiation payload.
+  ///
+  /// # Arguments
+  ///
+  /// * `sender` - Which end of the connection is sending the message.
+  #[must_use]
+  pub fn to_bytes(&self, sender: Sender) -> Bytes {
+    let offset = match sender {
+      Sender::Client => 0,
+      Sender::Server => SERVER_OFFSET,
+    };
+    let mut buf = BytesMut::new();
+    match self {
+      ComPortMessage::Signature(signature) => {
+        buf.put_u8(SIGNATURE + offset);
+        buf.put(&signature[..]);
+      }
+      ComPortMessage::SetBaudRate(rate) => {
+        buf.put_u8(SET_BAUDRATE + offset);
+        buf.put_u32(*rate);
+      }
+      ComPortMessage::FlowControlSuspend => buf.put_u8(FLOWCONTROL_SUSPEND + offset),
+      ComPortMessage::FlowControlResume => buf.put_u8(FLOWCONTROL_RESUME + offset),
+      ComPortMessage::SetDataSize(value)
+      | ComPortMessage::SetParity(value)
+      | ComPortMessage::SetStopSize(value)
+      | ComPortMessage::SetControl(value)
+      | ComPortMessage::NotifyLineState(value)
+      | ComPortMessage::NotifyModemState(value)
+      | ComPortMessage::SetLineStateMask(value)
+      | ComPortMessage::SetModemStateMask(value)
+      | ComPortMessage::PurgeData(value) => {
+        buf.put_u8(self.command() + offset);
+        buf.put_u8(*value);
+      }
+    }
+    buf.freeze()
+  }
+
+  /// The client command code of the message.
+  #[must_use]
+  pub fn command(&self) -> u8 {
+    match self {
+      ComPortMessage::Signature(_) => SIGNATURE,
+      ComPortMessage::SetBaudRate(_) => SET_BAUDRATE,
+      ComPortMessage::SetDataSize(_) => SET_DATASIZE,
+      ComPortMessage::SetParity(_) => SET_PARITY,
+      ComPortMessage::SetStopSize(_) => SET_STOPSIZE,
+      ComPortMessage::SetControl(_) => SET_CONTROL,
+      ComPortMessage::NotifyLineState(_) => NOTIFY_LINESTATE,
+      ComPortMessage::NotifyModemState(_) => NOTIFY_MODEMSTATE,
+      ComPortMessage::FlowControlSuspend => FLOWCONTROL_SUSPEND,
+      ComPortMessage::FlowControlResume => FLOWCONTROL_RESUME,
+      ComPortMessage::SetLineStateMask(_) => SET_LINESTATE_MASK,
+      ComPortMessage::SetModemStateMask(_) => SET_MODEMSTATE_MASK,
+      ComPortMessage::PurgeData(_) => PURGE_DATA,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_comport {
+  use super::*;
+
+  #[test]
+  fn test_roundtrip() {
+    let messages = [
+      ComPortMessage::Signature(Bytes::from_static(b"ser2net")),
+      ComPortMessage::SetBaudRate(115_200),
+      ComPortMessage::SetDataSize(8),
+      ComPortMessage::SetParity(PARITY_NONE),
+      ComPortMessage::SetStopSize(STOPSIZE_1),
+      ComPortMessage::SetControl(CONTROL_FLOW_HARDWARE),
+      ComPortMessage::NotifyLineState(LINESTATE_THRE | LINESTATE_TSRE),
+      ComPortMessage::NotifyModemState(MODEMSTATE_CD | MODEMSTATE_DELTA_CD),
+      ComPortMessage::FlowControlSuspend,
+      ComPortMessage::FlowControlResume,
+      ComPortMessage::SetLineStateMask(0),
+      ComPortMessage::SetModemStateMask(255),
+      ComPortMessage::PurgeData(PURGE_BOTH),
+    ];
+    for message in messages {
+      for sender in [Sender::Client, Sender::Server] {
+        let payload = message.to_bytes(sender);
+        assert_eq!(
+          ComPortMessage::decode(&payload),
+          Some((sender, message.clone()))
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_decode() {
+    assert_eq!(
+      ComPortMessage::decode(&[SET_BAUDRATE + SERVER_OFFSET, 0, 0, 0x25, 0x80]),
+      Some((Sender::Server, ComPortMessage::SetBaudRate(9600)))
+    );
+    assert_eq!(
+      ComPortMessage::SetBaudRate(REQUEST.into()).to_bytes(Sender::Client),
+      Bytes::from_static(&[SET_BAUDRATE, 0, 0, 0, 0])
+    );
+    assert_eq!(ComPortMessage::decode(&[]), None);
+    assert_eq!(ComPortMessage::decode(&[SET_BAUDRATE, 0, 0]), None);
+    assert_eq!(ComPortMessage::decode(&[SET_PARITY]), None);
+    assert_eq!(ComPortMessage::decode(&[FLOWCONTROL_SUSPEND, 1]), None);
+    assert_eq!(ComPortMessage::decode(&[PURGE_DATA + 1]), None);
+    assert_eq!(
+      ComPortMessage::decode(&[PURGE_DATA + 1 + SERVER_OFFSET]),
+      None
+    );
+  }
+}
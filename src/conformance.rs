@@ -0,0 +1,106 @@
+//! Checks of a parser's configuration, run with `Parser::self_test`.
+//!
+//! A misconfigured `CompatibilityTable` or a conflicting combination of parser settings usually only shows up part
+//! way through a session, as an option that never settles or a client that echoes everything back. Running
+//! `Parser::self_test` once the parser is configured reports those problems up front.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::compatibility::Direction;
+
+/// A problem with a parser's configuration.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ConformanceIssue {
+  /// An option is marked enabled in the `CompatibilityTable` without being supported in that direction, a state
+  /// negotiation never produces.
+  EnabledWithoutSupport { option: u8, direction: Direction },
+  /// ECHO is supported in both directions. If both ends agree to echo, every byte is echoed back and forth.
+  EchoBothWays,
+  /// ECHO is supported locally, but `Parser::set_refuse_local_echo` refuses every request for it.
+  RefusedLocalEcho,
+  /// ECHO is supported locally without SGA. Clients expect a server echoing their input to suppress go ahead as
+  /// well, for character at a time mode.
+  EchoWithoutSga,
+  /// Half-duplex mode is enabled while SGA is supported locally. Half-duplex mode relies on `IAC GA`, which SGA
+  /// suppresses.
+  HalfDuplexWithSga,
+  /// Terminal types are set with `Parser::set_terminal_types`, but TTYPE isn't supported locally, so they're
+  /// never sent.
+  TerminalTypesWithoutTtype,
+  /// An MSDP channel preference is set with `Parser::set_msdp_preference`, but MSDP or GMCP isn't supported, so
+  /// the preference never applies.
+  UnusedMsdpPreference,
+  /// The limit set with `Parser::set_subnegotiation_limit` is below 5 bytes, the length of an empty
+  /// subnegotiation, so every subnegotiation is discarded.
+  SubnegotiationLimitTooSmall(usize),
+  /// 3270 detection is enabled, but BINARY or EOR isn't supported in both directions, which 3270 sessions require.
+  Tn3270WithoutBinaryEor,
+}
+
+impl fmt::Display for ConformanceIssue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConformanceIssue::EnabledWithoutSupport { option, direction } => {
+        write!(f, "option {option} enabled without {direction:?} support")
+      }
+      ConformanceIssue::EchoBothWays => f.write_str("ECHO supported in both directions"),
+      ConformanceIssue::RefusedLocalEcho => {
+        f.write_str("ECHO supported locally but always refused")
+      }
+      ConformanceIssue::EchoWithoutSga => f.write_str("ECHO supported locally without SGA"),
+      ConformanceIssue::HalfDuplexWithSga => {
+        f.write_str("half-duplex mode with SGA supported locally")
+      }
+      ConformanceIssue::TerminalTypesWithoutTtype => {
+        f.write_str("terminal types set without local TTYPE support")
+      }
+      ConformanceIssue::UnusedMsdpPreference => {
+        f.write_str("MSDP preference set without MSDP and GMCP support")
+      }
+      ConformanceIssue::SubnegotiationLimitTooSmall(limit) => {
+        write!(f, "subnegotiation limit of {limit} bytes is too small")
+      }
+      ConformanceIssue::Tn3270WithoutBinaryEor => {
+        f.write_str("3270 detection without BINARY and EOR support")
+      }
+    }
+  }
+}
+
+/// The result of `Parser::self_test`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConformanceReport {
+  /// The problems found, in the order they were checked.
+  pub issues: Vec<ConformanceIssue>,
+}
+
+impl ConformanceReport {
+  /// Whether no problems were found.
+  #[must_use]
+  pub fn is_ok(&self) -> bool {
+    self.issues.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod test_conformance {
+  use super::*;
+  use alloc::string::ToString;
+
+  #[test]
+  fn test_display() {
+    assert_eq!(
+      ConformanceIssue::EnabledWithoutSupport {
+        option: 1,
+        direction: Direction::Local
+      }
+      .to_string(),
+      "option 1 enabled without Local support"
+    );
+    assert_eq!(
+      ConformanceIssue::SubnegotiationLimitTooSmall(3).to_string(),
+      "subnegotiation limit of 3 bytes is too small"
+    );
+  }
+}
@@ -0,0 +1,193 @@
+//! Encoding and decoding of ENCRYPT ([RFC 2946]) subnegotiation payloads, and the [`Cipher`]
+//! extension point for decrypting received data.
+//!
+//! This crate doesn't implement any encryption types itself. Downstream crates provide them by
+//! implementing [`Cipher`], which is installed with `Parser::set_cipher`. Once the remote end
+//! sends `START`, the parser decrypts everything it receives with the cipher until the remote
+//! end sends `END`, and reports both switches with `TelnetEvents::DecryptionChanged`.
+//!
+//! The parser doesn't write to the connection, so encrypting sent data after sending `START`
+//! is left to the caller.
+//!
+//! [RFC 2946]: https://www.rfc-editor.org/rfc/rfc2946
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Report the encryption type chosen, and any data it needs.
+pub const IS: u8 = 0;
+/// List the supported encryption types.
+pub const SUPPORT: u8 = 1;
+/// Reply to an `IS`, with any data the encryption type needs.
+pub const REPLY: u8 = 2;
+/// Everything the sender sends after this is encrypted.
+pub const START: u8 = 3;
+/// Everything the sender sends after this is no longer encrypted.
+pub const END: u8 = 4;
+/// Ask the remote end to start encrypting what it sends.
+pub const REQUEST_START: u8 = 5;
+/// Ask the remote end to stop encrypting what it sends.
+pub const REQUEST_END: u8 = 6;
+/// Verify the key identifier used for encrypting.
+pub const ENC_KEYID: u8 = 7;
+/// Verify the key identifier used for decrypting.
+pub const DEC_KEYID: u8 = 8;
+
+/// Encryption type: no encryption.
+pub const TYPE_NULL: u8 = 0;
+/// Encryption type: DES 64 bit cipher feedback.
+pub const TYPE_DES_CFB64: u8 = 1;
+/// Encryption type: DES 64 bit output feedback.
+pub const TYPE_DES_OFB64: u8 = 2;
+/// Encryption type: triple DES 64 bit cipher feedback.
+pub const TYPE_DES3_CFB64: u8 = 3;
+/// Encryption type: triple DES 64 bit output feedback.
+pub const TYPE_DES3_OFB64: u8 = 4;
+/// Encryption type: 40 bit CAST5 64 bit cipher feedback.
+pub const TYPE_CAST5_40_CFB64: u8 = 8;
+/// Encryption type: 40 bit CAST5 64 bit output feedback.
+pub const TYPE_CAST5_40_OFB64: u8 = 9;
+/// Encryption type: 128 bit CAST 64 bit cipher feedback.
+pub const TYPE_CAST128_CFB64: u8 = 10;
+/// Encryption type: 128 bit CAST 64 bit output feedback.
+pub const TYPE_CAST128_OFB64: u8 = 11;
+
+/// Decrypts the data received from the remote end while it's encrypting.
+pub trait Cipher: Send + Sync {
+  /// Decrypt received data in place.
+  ///
+  /// Data is passed in the order it was received, but not necessarily in the chunks it was read in, so the
+  /// cipher must carry its state over between calls.
+  fn decrypt(&mut self, data: &mut [u8]);
+}
+
+/// A decoded ENCRYPT subnegotiation message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EncryptMessage {
+  /// The encryption type chosen, one of the `TYPE_*` values, and its data.
+  Is { kind: u8, data: Bytes },
+  /// The supported encryption types, in order of preference.
+  Support(Bytes),
+  /// The encryption type replied to, one of the `TYPE_*` values, and its data.
+  Reply { kind: u8, data: Bytes },
+  /// Encryption starts, using the given key identifier.
+  Start(Bytes),
+  /// Encryption ends.
+  End,
+  /// Ask for encryption to start. The key identifier may be empty.
+  RequestStart(Bytes),
+  /// Ask for encryption to end.
+  RequestEnd,
+  /// Verify a key identifier used for encrypting.
+  EncKeyId(Bytes),
+  /// Verify a key identifier used for decrypting.
+  DecKeyId(Bytes),
+}
+
+impl EncryptMessage {
+  /// Decode an ENCRYPT subnegotiation payload.
+  ///
+  /// Returns `None` if the payload is empty, has an unknown command code, or is malformed.
+  #[must_use]
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    let (&code, body) = payload.split_first()?;
+    let data = || Bytes::copy_from_slice(body);
+    match code {
+      IS => {
+        let (&kind, data) = body.split_first()?;
+        let data = Bytes::copy_from_slice(data);
+        Some(EncryptMessage::Is { kind, data })
+      }
+      REPLY => {
+        let (&kind, data) = body.split_first()?;
+        let data = Bytes::copy_from_slice(data);
+        Some(EncryptMessage::Reply { kind, data })
+      }
+      SUPPORT => Some(EncryptMessage::Support(data())),
+      START => Some(EncryptMessage::Start(data())),
+      END if body.is_empty() => Some(EncryptMessage::End),
+      REQUEST_START => Some(EncryptMessage::RequestStart(data())),
+      REQUEST_END if body.is_empty() => Some(EncryptMessage::RequestEnd),
+      ENC_KEYID => Some(EncryptMessage::EncKeyId(data())),
+      DEC_KEYID => Some(EncryptMessage::DecKeyId(data())),
+      _ => None,
+    }
+  }
+
+  /// Encode the message into an (unescaped) subnegotiation payload.
+  #[must_use]
+  pub fn to_bytes(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    match self {
+      EncryptMessage::Is { kind, data } => {
+        buf.put_u8(IS);
+        buf.put_u8(*kind);
+        buf.put(&data[..]);
+      }
+      EncryptMessage::Support(kinds) => {
+        buf.put_u8(SUPPORT);
+        buf.put(&kinds[..]);
+      }
+      EncryptMessage::Reply { kind, data } => {
+        buf.put_u8(REPLY);
+        buf.put_u8(*kind);
+        buf.put(&data[..]);
+      }
+      EncryptMessage::Start(keyid) => {
+        buf.put_u8(START);
+        buf.put(&keyid[..]);
+      }
+      EncryptMessage::End => buf.put_u8(END),
+      EncryptMessage::RequestStart(keyid) => {
+        buf.put_u8(REQUEST_START);
+        buf.put(&keyid[..]);
+      }
+      EncryptMessage::RequestEnd => buf.put_u8(REQUEST_END),
+      EncryptMessage::EncKeyId(keyid) => {
+        buf.put_u8(ENC_KEYID);
+        buf.put(&keyid[..]);
+      }
+      EncryptMessage::DecKeyId(keyid) => {
+        buf.put_u8(DEC_KEYID);
+        buf.put(&keyid[..]);
+      }
+    }
+    buf.freeze()
+  }
+}
+
+#[cfg(test)]
+mod test_encrypt {
+  use super::*;
+
+  #[test]
+  fn test_roundtrip() {
+    let messages = [
+      EncryptMessage::Is {
+        kind: TYPE_DES_CFB64,
+        data: Bytes::from_static(b"\x01iv"),
+      },
+      EncryptMessage::Support(Bytes::from_static(&[TYPE_DES_CFB64, TYPE_DES_OFB64])),
+      EncryptMessage::Reply {
+        kind: TYPE_DES_CFB64,
+        data: Bytes::new(),
+      },
+      EncryptMessage::Start(Bytes::from_static(&[0])),
+      EncryptMessage::End,
+      EncryptMessage::RequestStart(Bytes::new()),
+      EncryptMessage::RequestEnd,
+      EncryptMessage::EncKeyId(Bytes::from_static(&[1])),
+      EncryptMessage::DecKeyId(Bytes::from_static(&[1])),
+    ];
+    for message in messages {
+      assert_eq!(EncryptMessage::decode(&message.to_bytes()), Some(message));
+    }
+  }
+
+  #[test]
+  fn test_decode_malformed() {
+    assert_eq!(EncryptMessage::decode(&[]), None);
+    assert_eq!(EncryptMessage::decode(&[IS]), None);
+    assert_eq!(EncryptMessage::decode(&[END, 0]), None);
+    assert_eq!(EncryptMessage::decode(&[DEC_KEYID + 1]), None);
+  }
+}
@@ -1,8 +1,15 @@
-use alloc::vec::Vec;
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
 
 use bytes::{BufMut, Bytes, BytesMut};
 
-use crate::telnet::op_command::{IAC, SB, SE};
+use crate::compatibility::{CompatibilityEntry, Direction};
+use crate::gmcp::GmcpMessage;
+use crate::telnet::op_command::{AO, AYT, BRK, DM, EC, EL, EOR, GA, IAC, IP, NOP, SB, SE};
+use crate::telnet::{Command, KnownOption};
+use crate::tn3270::Tn3270Mode;
+use crate::transfer::TransferProtocol;
 use crate::Parser;
 
 /// A struct representing a 2 byte IAC sequence.
@@ -18,6 +25,12 @@ impl TelnetIAC {
     Self { command }
   }
 
+  /// The command as an `IacCommand`.
+  #[must_use]
+  pub fn kind(&self) -> IacCommand {
+    IacCommand::from(self.command)
+  }
+
   /// Consume the sequence struct and return the bytes.
   #[must_use]
   pub fn to_bytes(self) -> Bytes {
@@ -31,6 +44,75 @@ impl TelnetIAC {
   }
 }
 
+impl From<IacCommand> for TelnetIAC {
+  fn from(command: IacCommand) -> Self {
+    Self::new(command.into())
+  }
+}
+
+/// The command of a 2 byte IAC sequence.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum IacCommand {
+  /// No operation.
+  Nop,
+  /// The data stream portion of a Synch.
+  DataMark,
+  /// The break or attention key was pressed.
+  Break,
+  /// Interrupt the process the remote end is running.
+  InterruptProcess,
+  /// Discard output that hasn't been displayed yet.
+  AbortOutput,
+  /// Request visible evidence that the remote end is still alive.
+  AreYouThere,
+  /// Erase the previous character.
+  EraseChar,
+  /// Erase the current line.
+  EraseLine,
+  /// Go ahead. Commonly used by MUDs to mark the end of a prompt.
+  GoAhead,
+  /// End of record. Marks the end of a prompt once the EOR option is enabled.
+  Eor,
+  /// Any other command.
+  Other(u8),
+}
+
+impl From<u8> for IacCommand {
+  fn from(command: u8) -> Self {
+    match command {
+      NOP => IacCommand::Nop,
+      DM => IacCommand::DataMark,
+      BRK => IacCommand::Break,
+      IP => IacCommand::InterruptProcess,
+      AO => IacCommand::AbortOutput,
+      AYT => IacCommand::AreYouThere,
+      EC => IacCommand::EraseChar,
+      EL => IacCommand::EraseLine,
+      GA => IacCommand::GoAhead,
+      EOR => IacCommand::Eor,
+      other => IacCommand::Other(other),
+    }
+  }
+}
+
+impl From<IacCommand> for u8 {
+  fn from(command: IacCommand) -> u8 {
+    match command {
+      IacCommand::Nop => NOP,
+      IacCommand::DataMark => DM,
+      IacCommand::Break => BRK,
+      IacCommand::InterruptProcess => IP,
+      IacCommand::AbortOutput => AO,
+      IacCommand::AreYouThere => AYT,
+      IacCommand::EraseChar => EC,
+      IacCommand::EraseLine => EL,
+      IacCommand::GoAhead => GA,
+      IacCommand::Eor => EOR,
+      IacCommand::Other(other) => other,
+    }
+  }
+}
+
 /// A struct representing a 3 byte IAC sequence.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
@@ -82,6 +164,12 @@ impl TelnetSubnegotiation {
     Self { option, buffer }
   }
 
+  /// Consume the sequence struct and return the bytes, with IAC bytes in the payload escaped.
+  ///
+  /// # Notes
+  ///
+  /// Under the default `DecodePolicy::Raw`, a received payload keeps the IAC escaping it was sent with, which is
+  /// then escaped a second time here. `TelnetEvents::to_bytes` leaves such payloads as they are.
   #[must_use]
   pub fn to_bytes(self) -> Bytes {
     let head = [IAC, SB, self.option];
@@ -101,8 +189,116 @@ impl TelnetSubnegotiation {
   }
 }
 
+/// Metadata describing where in the received stream an event was parsed from.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct EventMeta {
+  /// The offset of the event's first byte, counted from the first byte the parser received.
+  pub offset: u64,
+}
+
+/// Common keepalive traffic to leave out of the events emitted by the parser. Everything is emitted by default.
+///
+/// Filtered traffic still updates the parser's state, it just isn't reported.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct KeepaliveFilter {
+  /// Leave out `IAC NOP` commands.
+  pub nop: bool,
+  /// Leave out received data consisting only of line endings (`\r`, `\n` and `NUL` bytes), unless it ends a line of
+  /// data received before it.
+  pub empty_lines: bool,
+  /// Leave out `WONT` and `DONT` notifications that don't change an option's state, which some servers repeat
+  /// periodically.
+  pub repeated_negotiations: bool,
+}
+
+impl KeepaliveFilter {
+  /// A filter leaving out all of the recognized keepalive traffic.
+  #[must_use]
+  pub fn all() -> Self {
+    Self {
+      nop: true,
+      empty_lines: true,
+      repeated_negotiations: true,
+    }
+  }
+}
+
+/// How `NUL` bytes in received data are treated. RFC 854 defines `NUL` as a no-op, and some servers use it as
+/// padding, for example after a bare carriage return (`\r\0`).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum NulPolicy {
+  /// Leave `NUL` bytes in the data. This is the default.
+  #[default]
+  Keep,
+  /// Remove `NUL` bytes from the data.
+  Strip,
+  /// Remove `NUL` bytes from the data, and emit a `ProtocolError::UnexpectedNul` event for each data event they
+  /// were removed from.
+  Error,
+}
+
+/// How IAC bytes in the subnegotiation payloads of an option are decoded.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum DecodePolicy {
+  /// Leave the payload exactly as received. This is the default, and suits options whose implementations don't
+  /// double IAC bytes inside subnegotiations.
+  #[default]
+  Raw,
+  /// The remote end doubles IAC bytes in the payload, remove that escaping before the payload is emitted.
+  Unescape,
+}
+
+/// What `Parser::subnegotiation` does with a payload over the limit set with
+/// `Parser::set_outgoing_subnegotiation_limit`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum OversizedSubnegotiation {
+  /// Send nothing, `Parser::try_subnegotiation` reports `SubnegotiationRefused::TooLong`. This is the default.
+  #[default]
+  Refuse,
+  /// Split the payload into several subnegotiations between variables, for MSDP and MSSP, whose receivers merge
+  /// them. Payloads of other options, and single variables over the limit, are refused.
+  Split,
+}
+
+/// Malformed input from the remote end, reported once enabled with `Parser::set_diagnostics`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ProtocolError {
+  /// An `IAC SE` was received outside of a subnegotiation. It's skipped.
+  UnexpectedSe,
+  /// Received data contained `NUL` bytes, which were removed. Only reported with `NulPolicy::Error`, whether or
+  /// not diagnostics are enabled.
+  UnexpectedNul,
+  /// A subnegotiation was received for an option that isn't enabled in either direction, and refused. Only
+  /// reported once enabled with `Parser::set_refuse_unexpected_subnegotiations`, whether or not diagnostics are
+  /// enabled.
+  UnexpectedSubnegotiation(u8),
+  /// The connection ended during a subnegotiation for the option, which was discarded. Reported by
+  /// `Parser::finish`, whether or not diagnostics are enabled.
+  UnterminatedSubnegotiation(u8),
+  /// The connection ended part way through an IAC command, which was discarded. This includes an `IAC SB` whose
+  /// option byte never arrived. Reported by `Parser::finish`, whether or not diagnostics are enabled.
+  TruncatedCommand,
+  /// A subnegotiation for the option was longer than the limit set with `Parser::set_subnegotiation_limit`, and
+  /// was discarded. Reported whether or not diagnostics are enabled.
+  SubnegotiationTooLong(u8),
+}
+
+/// A subnegotiation payload a decoder couldn't decode. See `TelnetEvents::OptionDecodeError`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OptionDecodeError {
+  /// The byte representing the option.
+  pub option: u8,
+  /// The decoder's description of the problem.
+  pub error: String,
+  /// The payload, as it would appear in a `Subnegotiation` event.
+  pub raw: Bytes,
+}
+
 /// An enum representing various telnet events.
+///
+/// New events may be added in minor releases, so matches on it need a wildcard arm.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum TelnetEvents {
   /// An IAC command sequence.
   IAC(TelnetIAC),
@@ -116,6 +312,49 @@ pub enum TelnetEvents {
   DataSend(Bytes),
   /// MCCP2/3 compatibility. MUST DECOMPRESS THIS DATA BEFORE PARSING
   DecompressImmediate(Bytes),
+  /// The complete list of terminal types reported by the remote end, in the order they were
+  /// received. Emitted once a cycle started with `Parser::request_terminal_types` finishes.
+  TerminalTypes(Vec<String>),
+  /// A GMCP message, produced in place of a `Subnegotiation` event once `gmcp::decode` is registered with
+  /// `Parser::set_subnegotiation_decoder`. Boxed to keep `TelnetEvents` small.
+  Gmcp(Box<GmcpMessage>),
+  /// The start of an in-band file transfer was found in received data. Emitted once enabled with
+  /// `Parser::set_transfer_detection`, after which the parser is in passthrough mode.
+  FileTransferDetected(TransferProtocol),
+  /// Received data started (true) or stopped (false) being decrypted, following an ENCRYPT `START` or `END`
+  /// subnegotiation from the remote end. Only emitted once a cipher is installed with `Parser::set_cipher`.
+  DecryptionChanged(bool),
+  /// A 3270 session was detected, or ended. Emitted once enabled with `Parser::set_tn3270_detection`.
+  Tn3270(Tn3270Mode),
+  /// A complete record received in record mode, with IAC escaping removed and without the terminating `IAC EOR`.
+  /// See `Parser::set_record_mode`.
+  Record(Bytes),
+  /// Malformed input the parser recovered from. Emitted once enabled with `Parser::set_diagnostics`, or
+  /// `Parser::set_nul_policy` for `NUL` bytes.
+  ProtocolError(ProtocolError),
+  /// A decoder registered with `Parser::set_fallible_subnegotiation_decoder` couldn't decode a subnegotiation.
+  /// Emitted in place of the `Subnegotiation` event, so the payload can still be handled raw. Boxed to keep
+  /// `TelnetEvents` small.
+  OptionDecodeError(Box<OptionDecodeError>),
+  /// The remote end answered an offer made with `Parser::_will` (in the `Local` direction) or `Parser::_do` (in
+  /// the `Remote` direction). Emitted after any `Negotiation` event for the answer, once enabled with
+  /// `Parser::set_offer_tracking`.
+  NegotiationComplete {
+    option: u8,
+    direction: Direction,
+    /// Whether the remote end agreed to the offer.
+    accepted: bool,
+  },
+  /// An option's entry in the `CompatibilityTable` changed while processing received data. Emitted after the
+  /// `Negotiation` event that caused it, once enabled with `Parser::set_option_change_events`.
+  OptionStateChanged {
+    option: u8,
+    before: CompatibilityEntry,
+    after: CompatibilityEntry,
+  },
+  /// Marks the end of the teardown events returned by `Parser::begin_shutdown`. The connection can be closed once
+  /// the events before it are sent.
+  ShutdownComplete,
 }
 
 impl From<TelnetIAC> for TelnetEvents {
@@ -169,19 +408,408 @@ impl TelnetEvents {
     TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(option, buffer))
   }
 
+  /// The data carried by a `DataReceive`, `DataSend`, `DecompressImmediate` or `Record` event, as a slice.
+  ///
+  /// # Returns
+  ///
+  /// `Option<&[u8]>` - The data, or None for other events. Subnegotiation payloads are available from the
+  /// `TelnetSubnegotiation::buffer` field, which dereferences to `&[u8]`.
+  #[must_use]
+  pub fn data(&self) -> Option<&[u8]> {
+    match self {
+      TelnetEvents::DataReceive(data)
+      | TelnetEvents::DataSend(data)
+      | TelnetEvents::DecompressImmediate(data)
+      | TelnetEvents::Record(data) => Some(data),
+      _ => None,
+    }
+  }
+
+  /// Consume the event and return its bytes, which parse back to the same event.
+  ///
+  /// # Notes
+  ///
+  /// A `Subnegotiation` payload in which every IAC is already escaped, as received under the default
+  /// `DecodePolicy::Raw`, is sent as is, while any other payload is escaped. A payload unescaped with
+  /// `DecodePolicy::Unescape` that holds two adjacent IAC bytes therefore loses one of them. Use
+  /// `TelnetSubnegotiation::to_bytes` to always escape the payload.
   #[must_use]
   pub fn to_bytes(self) -> Bytes {
     match self {
       TelnetEvents::IAC(iac) => iac.to_bytes(),
       TelnetEvents::Negotiation(neg) => neg.to_bytes(),
-      TelnetEvents::Subnegotiation(sub) => sub.to_bytes(),
+      TelnetEvents::Subnegotiation(TelnetSubnegotiation { option, buffer }) => {
+        received_subnegotiation(option, buffer)
+      }
+      TelnetEvents::OptionDecodeError(error) => received_subnegotiation(error.option, error.raw),
       TelnetEvents::DataReceive(data)
       | TelnetEvents::DataSend(data)
       | TelnetEvents::DecompressImmediate(data) => data,
+      TelnetEvents::TerminalTypes(_)
+      | TelnetEvents::FileTransferDetected(_)
+      | TelnetEvents::DecryptionChanged(_)
+      | TelnetEvents::Tn3270(_)
+      | TelnetEvents::ProtocolError(_)
+      | TelnetEvents::NegotiationComplete { .. }
+      | TelnetEvents::OptionStateChanged { .. }
+      | TelnetEvents::ShutdownComplete => Bytes::new(),
+      TelnetEvents::Record(data) => {
+        let mut record = BytesMut::from(&Parser::escape_iac(data)[..]);
+        record.put(&[IAC, EOR][..]);
+        record.freeze()
+      }
+      TelnetEvents::Gmcp(message) => {
+        TelnetSubnegotiation::new(crate::telnet::op_option::GMCP, message.to_bytes()).to_bytes()
+      }
     }
   }
 }
 
+/// The number of data bytes `Display` implementations show before eliding the rest. The alternate flag (`{:#}`)
+/// shows everything.
+const DISPLAY_LIMIT: usize = 32;
+
+fn displayed<'a>(f: &fmt::Formatter<'_>, data: &'a [u8]) -> (&'a [u8], bool) {
+  if f.alternate() || data.len() <= DISPLAY_LIMIT {
+    (data, false)
+  } else {
+    (&data[..DISPLAY_LIMIT], true)
+  }
+}
+
+/// Write a command by name, or by value if it's unknown.
+fn write_command(f: &mut fmt::Formatter<'_>, command: u8) -> fmt::Result {
+  match Command::try_from(command) {
+    Ok(command) => write!(f, "{command}"),
+    Err(value) => write!(f, "{value}"),
+  }
+}
+
+/// Write an option by name, or by value if it's unknown.
+fn write_option(f: &mut fmt::Formatter<'_>, option: u8) -> fmt::Result {
+  match KnownOption::try_from(option) {
+    Ok(option) => write!(f, "{option}"),
+    Err(value) => write!(f, "{value}"),
+  }
+}
+
+/// Write data as a hex and ASCII dump, e.g. `2 bytes: 68 0a |h.|`.
+fn write_dump(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+  let (shown, elided) = displayed(f, data);
+  write!(f, "{} bytes:", data.len())?;
+  for byte in shown {
+    write!(f, " {byte:02x}")?;
+  }
+  if elided {
+    f.write_str(" ...")?;
+  }
+  f.write_str(" |")?;
+  for &byte in shown {
+    f.write_char(if byte == b' ' || byte.is_ascii_graphic() {
+      char::from(byte)
+    } else {
+      '.'
+    })?;
+  }
+  f.write_char('|')
+}
+
+/// Write data as a quoted string, escaping quotes, backslashes and bytes that aren't printable ASCII.
+fn write_quoted(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+  let (shown, elided) = displayed(f, data);
+  f.write_char('"')?;
+  for &byte in shown {
+    match byte {
+      b'"' | b'\\' => write!(f, "\\{}", char::from(byte))?,
+      b' '..=b'~' => f.write_char(char::from(byte))?,
+      _ => write!(f, "\\x{byte:02x}")?,
+    }
+  }
+  f.write_char('"')?;
+  if elided {
+    f.write_str("...")?;
+  }
+  Ok(())
+}
+
+/// Renders the sequence symbolically, e.g. `IAC GA`.
+impl fmt::Display for TelnetIAC {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("IAC ")?;
+    write_command(f, self.command)
+  }
+}
+
+/// Renders the sequence symbolically, e.g. `IAC WILL GMCP`.
+impl fmt::Display for TelnetNegotiation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("IAC ")?;
+    write_command(f, self.command)?;
+    f.write_char(' ')?;
+    write_option(f, self.option)
+  }
+}
+
+/// Renders the sequence symbolically with a quoted payload, e.g. `IAC SB GMCP "Core.Ping" IAC SE`. Long payloads
+/// are elided unless the alternate flag (`{:#}`) is used.
+impl fmt::Display for TelnetSubnegotiation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("IAC SB ")?;
+    write_option(f, self.option)?;
+    f.write_char(' ')?;
+    write_quoted(f, &self.buffer)?;
+    f.write_str(" IAC SE")
+  }
+}
+
+/// Renders protocol sequences symbolically, and data as a hex and ASCII dump, e.g.
+/// `DataReceive 3 bytes: 68 70 0a |hp.|`. Long data is elided unless the alternate flag (`{:#}`) is used.
+impl fmt::Display for TelnetEvents {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TelnetEvents::IAC(iac) => write!(f, "{iac}"),
+      TelnetEvents::Negotiation(neg) => write!(f, "{neg}"),
+      TelnetEvents::Subnegotiation(sub) => fmt::Display::fmt(sub, f),
+      TelnetEvents::DataReceive(data) => {
+        f.write_str("DataReceive ")?;
+        write_dump(f, data)
+      }
+      TelnetEvents::DataSend(data) => {
+        f.write_str("DataSend ")?;
+        write_dump(f, data)
+      }
+      TelnetEvents::DecompressImmediate(data) => {
+        f.write_str("DecompressImmediate ")?;
+        write_dump(f, data)
+      }
+      TelnetEvents::Record(data) => {
+        f.write_str("Record ")?;
+        write_dump(f, data)
+      }
+      TelnetEvents::TerminalTypes(types) => write!(f, "TerminalTypes {types:?}"),
+      TelnetEvents::Gmcp(message) => {
+        f.write_str("IAC SB GMCP ")?;
+        write_quoted(f, &message.to_bytes())?;
+        f.write_str(" IAC SE")
+      }
+      TelnetEvents::FileTransferDetected(protocol) => {
+        write!(f, "FileTransferDetected {protocol:?}")
+      }
+      TelnetEvents::DecryptionChanged(decrypting) => {
+        write!(f, "DecryptionChanged {decrypting}")
+      }
+      TelnetEvents::Tn3270(mode) => write!(f, "Tn3270 {mode:?}"),
+      TelnetEvents::ProtocolError(error) => write!(f, "ProtocolError {error:?}"),
+      TelnetEvents::OptionDecodeError(error) => {
+        f.write_str("OptionDecodeError ")?;
+        write_option(f, error.option)?;
+        write!(f, " ({}) ", error.error)?;
+        write_quoted(f, &error.raw)
+      }
+      TelnetEvents::NegotiationComplete {
+        option,
+        direction,
+        accepted,
+      } => {
+        f.write_str("NegotiationComplete ")?;
+        write_option(f, *option)?;
+        let answer = if *accepted { "accepted" } else { "refused" };
+        write!(f, " {direction:?} {answer}")
+      }
+      TelnetEvents::OptionStateChanged {
+        option,
+        before,
+        after,
+      } => {
+        f.write_str("OptionStateChanged ")?;
+        write_option(f, *option)?;
+        let states = [
+          ("local", before.local_state, after.local_state),
+          ("remote", before.remote_state, after.remote_state),
+        ];
+        for (side, was, now) in states {
+          if was != now {
+            let state = if now { "enabled" } else { "disabled" };
+            write!(f, " {side} {state}")?;
+          }
+        }
+        Ok(())
+      }
+      TelnetEvents::ShutdownComplete => f.write_str("ShutdownComplete"),
+    }
+  }
+}
+
+/// The result of a caller-initiated negotiation, describing when the `CompatibilityTable` reflects it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NegotiationOutcome {
+  /// The table was updated when the request was made.
+  Immediate(TelnetEvents),
+  /// The table will be updated once the remote end acknowledges the request.
+  OnAcknowledgement(TelnetEvents),
+}
+
+impl NegotiationOutcome {
+  /// Whether the table update is waiting on the remote end.
+  #[must_use]
+  pub fn is_pending(&self) -> bool {
+    matches!(self, NegotiationOutcome::OnAcknowledgement(_))
+  }
+
+  /// The `DataSend` event to be processed.
+  #[must_use]
+  pub fn event(&self) -> &TelnetEvents {
+    match self {
+      NegotiationOutcome::Immediate(event) | NegotiationOutcome::OnAcknowledgement(event) => event,
+    }
+  }
+
+  /// Consume the outcome, returning the `DataSend` event to be processed.
+  #[must_use]
+  pub fn into_event(self) -> TelnetEvents {
+    match self {
+      NegotiationOutcome::Immediate(event) | NegotiationOutcome::OnAcknowledgement(event) => event,
+    }
+  }
+}
+
+/// The reason a caller-initiated negotiation produced nothing to send.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum NegotiationRefused {
+  /// The option isn't supported locally in the `CompatibilityTable`.
+  UnsupportedLocally,
+  /// The option isn't supported remotely in the `CompatibilityTable`.
+  UnsupportedRemotely,
+  /// The option is already enabled in the requested direction.
+  AlreadyEnabled,
+  /// The option is already disabled in the requested direction.
+  AlreadyDisabled,
+}
+
+impl fmt::Display for NegotiationRefused {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      NegotiationRefused::UnsupportedLocally => "option not supported locally",
+      NegotiationRefused::UnsupportedRemotely => "option not supported remotely",
+      NegotiationRefused::AlreadyEnabled => "option already enabled",
+      NegotiationRefused::AlreadyDisabled => "option already disabled",
+    })
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NegotiationRefused {}
+
+/// The reason a subnegotiation produced nothing to send.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum SubnegotiationRefused {
+  /// The option isn't supported, or isn't enabled, locally.
+  NotEnabled,
+  /// The payload is longer than the limit set with `Parser::set_outgoing_subnegotiation_limit`, and couldn't be
+  /// split.
+  TooLong {
+    /// The length of the payload, before IAC escaping.
+    length: usize,
+    /// The limit it's over.
+    limit: usize,
+  },
+}
+
+impl fmt::Display for SubnegotiationRefused {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SubnegotiationRefused::NotEnabled => f.write_str("option not enabled locally"),
+      SubnegotiationRefused::TooLong { length, limit } => {
+        write!(
+          f,
+          "subnegotiation of {length} bytes is over the limit of {limit}"
+        )
+      }
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SubnegotiationRefused {}
+
+/// The events produced by a single `Parser::receive_batch` call.
+///
+/// A batch upholds the ordering guarantees documented on `Parser::receive`. In particular, a
+/// `DecompressImmediate` event can only be the final event, and is available separately through
+/// `decompress_immediate` and `into_parts`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EventBatch {
+  events: Vec<TelnetEvents>,
+}
+
+impl EventBatch {
+  pub(crate) fn new(events: Vec<TelnetEvents>) -> Self {
+    debug_assert!(events
+      .iter()
+      .rev()
+      .skip(1)
+      .all(|event| !matches!(event, TelnetEvents::DecompressImmediate(_))));
+    Self { events }
+  }
+
+  /// The events, in the order they were parsed.
+  #[must_use]
+  pub fn events(&self) -> &[TelnetEvents] {
+    &self.events
+  }
+
+  /// Iterate over the events, in the order they were parsed.
+  pub fn iter(&self) -> core::slice::Iter<'_, TelnetEvents> {
+    self.events.iter()
+  }
+
+  /// The data following a compression start, which must be decompressed before it's received.
+  #[must_use]
+  pub fn decompress_immediate(&self) -> Option<&Bytes> {
+    match self.events.last() {
+      Some(TelnetEvents::DecompressImmediate(data)) => Some(data),
+      _ => None,
+    }
+  }
+
+  /// Consume the batch, returning the events.
+  #[must_use]
+  pub fn into_events(self) -> Vec<TelnetEvents> {
+    self.events
+  }
+
+  /// Consume the batch, separating the data to decompress from the other events.
+  #[must_use]
+  pub fn into_parts(mut self) -> (Vec<TelnetEvents>, Option<Bytes>) {
+    match self.events.pop() {
+      Some(TelnetEvents::DecompressImmediate(data)) => (self.events, Some(data)),
+      Some(event) => {
+        self.events.push(event);
+        (self.events, None)
+      }
+      None => (self.events, None),
+    }
+  }
+}
+
+impl IntoIterator for EventBatch {
+  type Item = TelnetEvents;
+  type IntoIter = alloc::vec::IntoIter<TelnetEvents>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.events.into_iter()
+  }
+}
+
+impl<'a> IntoIterator for &'a EventBatch {
+  type Item = &'a TelnetEvents;
+  type IntoIter = core::slice::Iter<'a, TelnetEvents>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.events.iter()
+  }
+}
+
 /*
 TODO(@cpu): remove/retool this stuff in breaking release.
 */
@@ -226,3 +854,71 @@ impl Into<Vec<u8>> for TelnetSubnegotiation {
     self.to_bytes().into()
   }
 }
+
+/// The event's bytes, as produced by `TelnetEvents::to_bytes`.
+impl From<TelnetEvents> for Bytes {
+  fn from(event: TelnetEvents) -> Self {
+    event.to_bytes()
+  }
+}
+
+/// The event's bytes, as produced by `TelnetEvents::to_bytes`.
+impl From<TelnetEvents> for Vec<u8> {
+  fn from(event: TelnetEvents) -> Self {
+    event.to_bytes().into()
+  }
+}
+
+/// Build a `DataSend` event for a subnegotiation whose payload is already IAC escaped, sending it as is.
+///
+/// # Arguments
+///
+/// `option` - A `u8` representing the telnet option code for the subnegotiation.
+///
+/// `already_escaped` - The payload, with any IAC bytes already doubled.
+///
+/// # Notes
+///
+/// This is intended for experimental or private options whose payloads are built elsewhere. Unlike
+/// `TelnetSubnegotiation::to_bytes` and `Parser::subnegotiation`, nothing is escaped or checked: an unescaped
+/// `IAC SE` in the payload ends the subnegotiation early, and the option doesn't have to be enabled.
+#[must_use]
+pub fn raw_subnegotiation(option: u8, already_escaped: Bytes) -> TelnetEvents {
+  let mut buf = BytesMut::with_capacity(already_escaped.len() + 5);
+  buf.put(&[IAC, SB, option][..]);
+  buf.put(already_escaped);
+  buf.put(&[IAC, SE][..]);
+  TelnetEvents::DataSend(buf.freeze())
+}
+
+/// The bytes of a received subnegotiation, escaping the payload unless every IAC in it is already escaped.
+fn received_subnegotiation(option: u8, payload: Bytes) -> Bytes {
+  let mut unpaired = false;
+  for &byte in &payload[..] {
+    if byte == IAC {
+      unpaired = !unpaired;
+    } else if unpaired {
+      break;
+    }
+  }
+  if unpaired {
+    TelnetSubnegotiation::new(option, payload).to_bytes()
+  } else {
+    raw_subnegotiation(option, payload).to_bytes()
+  }
+}
+
+/// Build a `DataSend` event for an arbitrary command sequence, sending the bytes as is.
+///
+/// # Arguments
+///
+/// `bytes` - The complete sequence, including the leading `IAC`. Example: `[IAC, 238]`
+///
+/// # Notes
+///
+/// This is intended for experimental commands that `TelnetIAC` and `TelnetNegotiation` don't describe. Nothing is
+/// escaped or checked, and the `CompatibilityTable` isn't updated for any negotiation sent this way.
+#[must_use]
+pub fn raw_command(bytes: &[u8]) -> TelnetEvents {
+  TelnetEvents::DataSend(Bytes::copy_from_slice(bytes))
+}
@@ -0,0 +1,366 @@
+//! Helpers for Generic MUD Communication Protocol (GMCP) messages.
+//!
+//! A GMCP subnegotiation payload is a package name, optionally followed by whitespace and a
+//! JSON body. Some messages (e.g. `Core.Ping`) omit the body entirely.
+
+use alloc::{
+  boxed::Box,
+  collections::BTreeMap,
+  string::{String, ToString},
+  vec::Vec,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::events::TelnetEvents;
+
+#[cfg(feature = "serde_json")]
+pub mod packages;
+
+/// A GMCP message split into its package name and (optional) body.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GmcpMessage {
+  /// The package and message name. Example: `Char.Vitals`
+  pub package: String,
+  /// The raw JSON body, if one was present.
+  pub data: Option<Bytes>,
+}
+
+impl GmcpMessage {
+  #[must_use]
+  pub fn new(package: &str, data: Option<Bytes>) -> Self {
+    Self {
+      package: package.to_string(),
+      data,
+    }
+  }
+
+  /// Split a GMCP subnegotiation payload into a message.
+  ///
+  /// Returns `None` if the payload is empty or the package name isn't valid UTF-8.
+  #[must_use]
+  pub fn parse(payload: &Bytes) -> Option<Self> {
+    let package_end = payload
+      .iter()
+      .position(u8::is_ascii_whitespace)
+      .unwrap_or(payload.len());
+    if package_end == 0 {
+      return None;
+    }
+    let package = core::str::from_utf8(&payload[..package_end]).ok()?;
+    let data_start = payload[package_end..]
+      .iter()
+      .position(|b| !b.is_ascii_whitespace())
+      .map(|pos| package_end + pos);
+    Some(Self::new(
+      package,
+      data_start.map(|start| payload.slice(start..)),
+    ))
+  }
+
+  /// Assemble the message into a GMCP subnegotiation payload.
+  #[must_use]
+  pub fn to_bytes(&self) -> Bytes {
+    let data_len = self.data.as_ref().map_or(0, |data| data.len() + 1);
+    let mut buf = BytesMut::with_capacity(self.package.len() + data_len);
+    buf.put(self.package.as_bytes());
+    if let Some(data) = &self.data {
+      buf.put_u8(b' ');
+      buf.put(&data[..]);
+    }
+    buf.freeze()
+  }
+
+  /// Deserialize the JSON body.
+  ///
+  /// A message without a body is treated as having a JSON `null` body, so bodiless messages
+  /// can be deserialized as `()` or `Option<T>`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the body isn't valid JSON for `T`.
+  #[cfg(feature = "serde_json")]
+  pub fn parse_json<T>(&self) -> Result<T, serde_json::Error>
+  where
+    T: serde::de::DeserializeOwned,
+  {
+    match &self.data {
+      Some(data) => serde_json::from_slice(data),
+      None => serde_json::from_value(serde_json::Value::Null),
+    }
+  }
+
+  /// Best-effort conversion of the body into a `serde_json::Value`.
+  ///
+  /// A missing body becomes `Value::Null`. A body that isn't valid JSON (some servers send bare
+  /// words) becomes a `Value::String` of its text.
+  #[cfg(feature = "serde_json")]
+  #[must_use]
+  pub fn as_value(&self) -> serde_json::Value {
+    match &self.data {
+      Some(data) => serde_json::from_slice(data)
+        .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(data).into_owned())),
+      None => serde_json::Value::Null,
+    }
+  }
+}
+
+/// A subnegotiation decoder producing `TelnetEvents::Gmcp` events. Register it for the GMCP option with
+/// `Parser::set_subnegotiation_decoder`.
+///
+/// Payloads that can't be parsed as a GMCP message are left as `Subnegotiation` events.
+#[must_use]
+pub fn decode(payload: &Bytes) -> Option<TelnetEvents> {
+  GmcpMessage::parse(payload).map(|message| TelnetEvents::Gmcp(Box::new(message)))
+}
+
+/// A subnegotiation decoder producing `TelnetEvents::Gmcp` events, for
+/// `Parser::set_fallible_subnegotiation_decoder`.
+///
+/// # Errors
+///
+/// Returns an error describing the problem if the payload can't be parsed as a GMCP message. With the
+/// `serde_json` feature, a body that isn't valid JSON is an error too.
+pub fn try_decode(payload: &Bytes) -> Result<TelnetEvents, String> {
+  let message = GmcpMessage::parse(payload).ok_or_else(|| {
+    if payload.is_empty() || payload[0].is_ascii_whitespace() {
+      String::from("missing package name")
+    } else {
+      String::from("package name isn't valid UTF-8")
+    }
+  })?;
+  #[cfg(feature = "serde_json")]
+  if let Some(data) = &message.data {
+    serde_json::from_slice::<serde::de::IgnoredAny>(data).map_err(|error| error.to_string())?;
+  }
+  Ok(TelnetEvents::Gmcp(Box::new(message)))
+}
+
+/// The GMCP packages one end declared with `Core.Supports.Set`, `Core.Supports.Add` and `Core.Supports.Remove`.
+///
+/// Package names are compared case-insensitively, and are kept with the case they were first declared in.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Supports {
+  packages: BTreeMap<String, (String, u32)>,
+}
+
+impl Supports {
+  /// Update the set from a `Core.Supports.*` message.
+  ///
+  /// # Returns
+  ///
+  /// `bool` - True if the message was a `Core.Supports.Set`, `Core.Supports.Add` or `Core.Supports.Remove` message
+  /// with a body that could be read, false if it was ignored.
+  ///
+  /// # Notes
+  ///
+  /// The body is expected to be a JSON array of `"Package version"` strings, e.g. `["Char 1", "Room 1"]`. A
+  /// package declared without a version is treated as version 1.
+  pub fn apply(&mut self, message: &GmcpMessage) -> bool {
+    let package = message.package.as_str();
+    let is = |name: &str| package.eq_ignore_ascii_case(name);
+    if !(is("Core.Supports.Set") || is("Core.Supports.Add") || is("Core.Supports.Remove")) {
+      return false;
+    }
+    let Some(specs) = message.data.as_deref().and_then(parse_string_array) else {
+      return false;
+    };
+    if is("Core.Supports.Set") {
+      self.packages.clear();
+    }
+    for spec in specs {
+      let Some((name, version)) = parse_spec(&spec) else {
+        continue;
+      };
+      let key = name.to_ascii_lowercase();
+      if is("Core.Supports.Remove") {
+        self.packages.remove(&key);
+      } else {
+        self
+          .packages
+          .insert(key, (name.to_string(), version.unwrap_or(1)));
+      }
+    }
+    true
+  }
+
+  /// Whether a package was declared.
+  ///
+  /// # Arguments
+  ///
+  /// `spec` - A `&str` naming the package, optionally followed by a version. Example: `Char 1`
+  ///
+  /// # Returns
+  ///
+  /// `bool` - True if the package was declared, with the given version if there is one.
+  #[must_use]
+  pub fn contains(&self, spec: &str) -> bool {
+    match parse_spec(spec) {
+      Some((name, None)) => self.version(name).is_some(),
+      Some((name, version)) => self.version(name) == version,
+      None => false,
+    }
+  }
+
+  /// The version a package was declared with, if it was declared.
+  #[must_use]
+  pub fn version(&self, package: &str) -> Option<u32> {
+    self
+      .packages
+      .get(&package.to_ascii_lowercase())
+      .map(|&(_, version)| version)
+  }
+
+  /// The declared `(package, version)` pairs, ordered by package name.
+  pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> + '_ {
+    self
+      .packages
+      .values()
+      .map(|(name, version)| (name.as_str(), *version))
+  }
+
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.packages.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.packages.clear();
+  }
+}
+
+// Split a `"Package version"` string into its name and version, if it has one.
+fn parse_spec(spec: &str) -> Option<(&str, Option<u32>)> {
+  let mut parts = spec.split_whitespace();
+  let name = parts.next()?;
+  let version = match parts.next() {
+    Some(version) => Some(version.parse().ok()?),
+    None => None,
+  };
+  parts.next().is_none().then_some((name, version))
+}
+
+// Read a JSON array of strings, without depending on `serde_json`. Escapes other than `\uXXXX` are kept as the
+// escaped character, which is all package names need.
+fn parse_string_array(body: &[u8]) -> Option<Vec<String>> {
+  let text = core::str::from_utf8(body).ok()?.trim();
+  let mut chars = text
+    .strip_prefix('[')?
+    .strip_suffix(']')?
+    .chars()
+    .peekable();
+  let mut strings = Vec::new();
+  loop {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    match chars.next() {
+      None if strings.is_empty() => return Some(strings),
+      Some('"') => {}
+      _ => return None,
+    }
+    let mut string = String::new();
+    loop {
+      match chars.next()? {
+        '"' => break,
+        '\\' => match chars.next()? {
+          'u' => return None,
+          escaped => string.push(escaped),
+        },
+        c => string.push(c),
+      }
+    }
+    strings.push(string);
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    match chars.next() {
+      None => return Some(strings),
+      Some(',') => {}
+      _ => return None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_gmcp {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let msg = GmcpMessage::parse(&Bytes::from_static(b"Char.Vitals {\"hp\": 10}")).unwrap();
+    assert_eq!(msg.package, "Char.Vitals");
+    assert_eq!(msg.data, Some(Bytes::from_static(b"{\"hp\": 10}")));
+    assert_eq!(GmcpMessage::parse(&msg.to_bytes()), Some(msg));
+
+    let msg = GmcpMessage::parse(&Bytes::from_static(b"Core.Ping")).unwrap();
+    assert_eq!(msg.package, "Core.Ping");
+    assert_eq!(msg.data, None);
+    assert_eq!(msg.to_bytes(), Bytes::from_static(b"Core.Ping"));
+
+    assert_eq!(GmcpMessage::parse(&Bytes::from_static(b" {}")), None);
+  }
+
+  #[test]
+  fn test_try_decode() {
+    assert!(try_decode(&Bytes::from_static(b"Char.Vitals {\"hp\": 10}")).is_ok());
+    assert_eq!(
+      try_decode(&Bytes::from_static(b" {}")),
+      Err(String::from("missing package name"))
+    );
+    assert_eq!(
+      try_decode(&Bytes::from_static(b"Char\xff {}")),
+      Err(String::from("package name isn't valid UTF-8"))
+    );
+    // Bare words are only rejected once they're checked as JSON.
+    assert_eq!(
+      try_decode(&Bytes::from_static(b"Core.Goodbye bye")).is_err(),
+      cfg!(feature = "serde_json")
+    );
+  }
+
+  #[cfg(feature = "serde_json")]
+  #[test]
+  fn test_json() {
+    let msg = GmcpMessage::parse(&Bytes::from_static(b"Char.Vitals {\"hp\": 10}")).unwrap();
+    assert_eq!(msg.as_value()["hp"], 10);
+    let vitals: serde_json::Map<String, serde_json::Value> = msg.parse_json().unwrap();
+    assert_eq!(vitals["hp"], 10);
+    assert!(msg.parse_json::<u32>().is_err());
+
+    let ping = GmcpMessage::parse(&Bytes::from_static(b"Core.Ping")).unwrap();
+    assert_eq!(ping.as_value(), serde_json::Value::Null);
+    ping.parse_json::<()>().unwrap();
+    assert_eq!(ping.parse_json::<Option<u32>>().unwrap(), None);
+
+    let bare = GmcpMessage::parse(&Bytes::from_static(b"Comm.Tick now")).unwrap();
+    assert_eq!(bare.as_value(), serde_json::Value::String("now".into()));
+  }
+
+  #[test]
+  fn test_supports() {
+    let message =
+      |payload: &'static [u8]| GmcpMessage::parse(&Bytes::from_static(payload)).unwrap();
+    let mut supports = Supports::default();
+    assert!(supports.apply(&message(b"Core.Supports.Set [ \"Char 1\", \"Room 2\" ]")));
+    assert!(supports.contains("Char 1"));
+    assert!(supports.contains("char"));
+    assert!(!supports.contains("Char 2"));
+    assert_eq!(supports.version("ROOM"), Some(2));
+
+    assert!(supports.apply(&message(
+      b"core.supports.add [\"Comm.Channel 1\", \"IRE.Rift\"]"
+    )));
+    assert!(supports.apply(&message(b"Core.Supports.Remove [\"Room\"]")));
+    assert_eq!(
+      supports.iter().collect::<Vec<_>>(),
+      [("Char", 1), ("Comm.Channel", 1), ("IRE.Rift", 1)]
+    );
+
+    assert!(supports.apply(&message(b"Core.Supports.Set [\"Room 1\"]")));
+    assert_eq!(supports.iter().collect::<Vec<_>>(), [("Room", 1)]);
+    assert!(supports.apply(&message(b"Core.Supports.Set []")));
+    assert!(supports.is_empty());
+
+    assert!(!supports.apply(&message(b"Core.Hello {\"client\": \"test\"}")));
+    assert!(!supports.apply(&message(b"Core.Supports.Set [\"Char 1\"")));
+    assert!(!supports.apply(&message(b"Core.Supports.Set [\"Char 1\",]")));
+    assert!(!supports.apply(&message(b"Core.Supports.Add")));
+  }
+}
@@ -0,0 +1,269 @@
+//! Typed models of the de-facto standard GMCP packages.
+//!
+//! The field names follow the IRE specification that most MUDs and clients implement. Servers disagree on whether
+//! numbers are sent as JSON numbers or strings, so numeric fields accept both. Fields a server sends that aren't
+//! modelled are kept in `extra`.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::GmcpMessage;
+
+/// `Char.Vitals`: the character's current and maximum stats.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CharVitals {
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub hp: Option<i64>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub maxhp: Option<i64>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub mp: Option<i64>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub maxmp: Option<i64>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub ep: Option<i64>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub maxep: Option<i64>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub wp: Option<i64>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub maxwp: Option<i64>,
+  /// Progress towards the next level, as a percentage.
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub nl: Option<i64>,
+  #[serde(flatten)]
+  pub extra: BTreeMap<String, Value>,
+}
+
+/// `Char.Status`: descriptive information about the character.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CharStatus {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub fullname: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub race: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub class: Option<String>,
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub level: Option<i64>,
+  #[serde(flatten)]
+  pub extra: BTreeMap<String, Value>,
+}
+
+/// `Room.Info`: the room the character is in.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoomInfo {
+  /// The unique room number.
+  #[serde(
+    default,
+    deserialize_with = "lenient_int",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub num: Option<i64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub area: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub environment: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub coords: Option<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub details: Vec<String>,
+  /// The room number each exit leads to, keyed by direction.
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub exits: BTreeMap<String, i64>,
+  #[serde(flatten)]
+  pub extra: BTreeMap<String, Value>,
+}
+
+/// `Comm.Channel.Text`: a message spoken on a channel.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommChannelText {
+  pub channel: String,
+  #[serde(default)]
+  pub talker: String,
+  pub text: String,
+}
+
+/// An entry of `Comm.Channel.List`: a channel the character can use.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommChannel {
+  pub name: String,
+  #[serde(default)]
+  pub caption: String,
+  #[serde(default)]
+  pub command: String,
+}
+
+/// A GMCP message decoded into one of the modelled packages.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Package {
+  CharVitals(CharVitals),
+  CharStatus(CharStatus),
+  RoomInfo(RoomInfo),
+  CommChannelText(CommChannelText),
+  CommChannelList(Vec<CommChannel>),
+  /// A package that isn't modelled, with its body as a `Value`.
+  Other(String, Value),
+}
+
+impl Package {
+  /// Decode a GMCP message into a typed package.
+  ///
+  /// Package names are matched case-insensitively. Messages for packages that aren't modelled are returned as
+  /// `Package::Other`, using `GmcpMessage::as_value` for the body.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the body of a modelled package doesn't match the model.
+  pub fn decode(message: &GmcpMessage) -> Result<Self, serde_json::Error> {
+    let package = message.package.as_str();
+    let is = |name: &str| package.eq_ignore_ascii_case(name);
+    Ok(if is("Char.Vitals") {
+      Package::CharVitals(message.parse_json()?)
+    } else if is("Char.Status") {
+      Package::CharStatus(message.parse_json()?)
+    } else if is("Room.Info") {
+      Package::RoomInfo(message.parse_json()?)
+    } else if is("Comm.Channel.Text") {
+      Package::CommChannelText(message.parse_json()?)
+    } else if is("Comm.Channel.List") {
+      Package::CommChannelList(message.parse_json()?)
+    } else {
+      Package::Other(message.package.clone(), message.as_value())
+    })
+  }
+}
+
+// Accept an integer sent as either a JSON number or a string.
+fn lenient_int<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  use serde::de::Error;
+
+  match Option::<Value>::deserialize(deserializer)? {
+    None | Some(Value::Null) => Ok(None),
+    Some(Value::Number(number)) => number
+      .as_i64()
+      .map(Some)
+      .ok_or_else(|| D::Error::custom("expected an integer")),
+    Some(Value::String(text)) if text.trim().is_empty() => Ok(None),
+    Some(Value::String(text)) => text
+      .trim()
+      .parse()
+      .map(Some)
+      .map_err(|_| D::Error::custom("expected an integer string")),
+    Some(_) => Err(D::Error::custom("expected an integer")),
+  }
+}
+
+#[cfg(test)]
+mod test_packages {
+  use super::*;
+  use bytes::Bytes;
+
+  fn decode(payload: &'static [u8]) -> Package {
+    Package::decode(&GmcpMessage::parse(&Bytes::from_static(payload)).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn test_char_vitals() {
+    let Package::CharVitals(vitals) =
+      decode(br#"char.vitals {"hp": "4500", "maxhp": 5000, "nl": "12", "string": "H:4500/5000"}"#)
+    else {
+      panic!("not Char.Vitals");
+    };
+    assert_eq!(vitals.hp, Some(4500));
+    assert_eq!(vitals.maxhp, Some(5000));
+    assert_eq!(vitals.nl, Some(12));
+    assert_eq!(vitals.mp, None);
+    assert_eq!(vitals.extra["string"], "H:4500/5000");
+  }
+
+  #[test]
+  fn test_room_info() {
+    let Package::RoomInfo(room) = decode(
+      br#"Room.Info {"num": 12345, "name": "A hall", "area": "Keep", "details": ["shop"], "exits": {"n": 12344}}"#,
+    ) else {
+      panic!("not Room.Info");
+    };
+    assert_eq!(room.num, Some(12345));
+    assert_eq!(room.name.as_deref(), Some("A hall"));
+    assert_eq!(room.exits["n"], 12344);
+    assert_eq!(room.details, ["shop"]);
+  }
+
+  #[test]
+  fn test_comm_channel() {
+    assert_eq!(
+      decode(br#"Comm.Channel.Text {"channel": "ooc", "talker": "Bob", "text": "hi"}"#),
+      Package::CommChannelText(CommChannelText {
+        channel: "ooc".into(),
+        talker: "Bob".into(),
+        text: "hi".into(),
+      })
+    );
+    let Package::CommChannelList(channels) =
+      decode(br#"Comm.Channel.List [{"name": "ooc", "caption": "OOC", "command": "ooc"}]"#)
+    else {
+      panic!("not Comm.Channel.List");
+    };
+    assert_eq!(channels.len(), 1);
+  }
+
+  #[test]
+  fn test_other() {
+    assert_eq!(
+      decode(b"Core.Ping"),
+      Package::Other("Core.Ping".into(), Value::Null)
+    );
+    let message = GmcpMessage::parse(&Bytes::from_static(br#"Char.Vitals {"hp": true}"#)).unwrap();
+    assert!(Package::decode(&message).is_err());
+  }
+}
@@ -0,0 +1,216 @@
+//! Tracking of the initial option negotiation, to tell when it has settled.
+//!
+//! Servers usually send a burst of negotiations when a connection opens, and clients often want to wait for that
+//! to finish before automating a login. [`Handshake`] watches the negotiations sent and received and reports once
+//! none are outstanding and the connection has been quiet for a while, or once a maximum duration has passed.
+//!
+//! Like the [`Parser`], it does no I/O and reads no clock. Every call takes the current time as a
+//! `Duration` measured from any fixed point, such as when the connection was opened.
+
+use alloc::{collections::BTreeSet, vec::Vec};
+use core::time::Duration;
+
+use crate::events::TelnetEvents;
+use crate::telnet::op_command::{DO, DONT, IAC, WILL, WONT};
+use crate::Parser;
+
+/// The options enabled once the handshake settled.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HandshakeSummary {
+  /// Options enabled on this end.
+  pub local: Vec<u8>,
+  /// Options enabled on the remote end.
+  pub remote: Vec<u8>,
+  /// Whether the maximum duration passed before every negotiation was answered.
+  pub timed_out: bool,
+}
+
+/// Drives the initial negotiation phase to quiescence.
+#[derive(Clone, Debug)]
+pub struct Handshake {
+  quiet: Duration,
+  deadline: Duration,
+  last_activity: Duration,
+  // Negotiations awaiting an answer, as (option, true if the remote end was asked).
+  pending: BTreeSet<(u8, bool)>,
+}
+
+impl Handshake {
+  /// Start tracking a handshake.
+  ///
+  /// # Arguments
+  ///
+  /// * `now` - The current time.
+  ///
+  /// * `quiet` - How long the connection must go without negotiation once nothing is outstanding.
+  ///
+  /// * `max` - The longest the handshake may take, regardless of outstanding negotiations.
+  #[must_use]
+  pub fn new(now: Duration, quiet: Duration, max: Duration) -> Self {
+    Self {
+      quiet,
+      deadline: now + max,
+      last_activity: now,
+      pending: BTreeSet::new(),
+    }
+  }
+
+  /// Record a negotiation sent to the remote end.
+  ///
+  /// # Notes
+  ///
+  /// Pass the `DataSend` events returned by `Parser::_will`, `_wont`, `_do`, `_dont` and `negotiate`. Replies the
+  /// parser generates from `receive` answer the remote end and must not be passed here, or the handshake will
+  /// wait for an answer that never comes.
+  pub fn sent(&mut self, now: Duration, event: &TelnetEvents) {
+    if let TelnetEvents::DataSend(data) = event {
+      for window in data.windows(3) {
+        if let [IAC, command, option] = *window {
+          match command {
+            WILL | WONT => self.pending.insert((option, false)),
+            DO | DONT => self.pending.insert((option, true)),
+            _ => continue,
+          };
+          self.last_activity = now;
+        }
+      }
+    }
+  }
+
+  /// Record an event returned by the parser.
+  pub fn received(&mut self, now: Duration, event: &TelnetEvents) {
+    match event {
+      TelnetEvents::Negotiation(neg) => {
+        match neg.command {
+          WILL | WONT => self.pending.remove(&(neg.option, true)),
+          DO | DONT => self.pending.remove(&(neg.option, false)),
+          _ => false,
+        };
+        self.last_activity = now;
+      }
+      TelnetEvents::Subnegotiation(_) => self.last_activity = now,
+      _ => {}
+    }
+  }
+
+  /// Whether any negotiation sent is still awaiting an answer.
+  #[must_use]
+  pub fn is_pending(&self) -> bool {
+    !self.pending.is_empty()
+  }
+
+  /// Check whether the handshake has settled.
+  ///
+  /// # Returns
+  ///
+  /// `Option<HandshakeSummary>` - The options enabled in `parser`, once nothing is outstanding and the connection
+  /// has been quiet long enough, or the maximum duration has passed. None while negotiation is still under way.
+  #[must_use]
+  pub fn poll(&self, now: Duration, parser: &Parser) -> Option<HandshakeSummary> {
+    let settled = !self.is_pending() && now >= self.last_activity + self.quiet;
+    let timed_out = now >= self.deadline;
+    if !settled && !timed_out {
+      return None;
+    }
+    let mut summary = HandshakeSummary {
+      timed_out: !settled,
+      ..HandshakeSummary::default()
+    };
+    for option in 0..=u8::MAX {
+      let entry = parser.options.get_option(option);
+      if entry.local_state {
+        summary.local.push(option);
+      }
+      if entry.remote_state {
+        summary.remote.push(option);
+      }
+    }
+    Some(summary)
+  }
+}
+
+#[cfg(test)]
+mod test_handshake {
+  use super::*;
+  use crate::compatibility::{CompatibilityEntry, CompatibilityTable};
+  use crate::telnet::op_option::{GMCP, NAWS};
+  use alloc::vec;
+
+  fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+  }
+
+  fn parser() -> Parser {
+    Parser::with_support(CompatibilityTable::from_options(&[
+      (
+        NAWS,
+        CompatibilityEntry::new(true, false, false, false).into_u8(),
+      ),
+      (
+        GMCP,
+        CompatibilityEntry::new(false, true, false, false).into_u8(),
+      ),
+    ]))
+  }
+
+  #[test]
+  fn test_settles() {
+    let mut parser = parser();
+    let mut handshake = Handshake::new(ms(0), ms(100), ms(1000));
+    handshake.sent(ms(0), &parser._do(GMCP).unwrap());
+    assert!(handshake.is_pending());
+    assert_eq!(handshake.poll(ms(200), &parser), None);
+
+    for event in parser.receive(&[IAC, WILL, GMCP, IAC, DO, NAWS]) {
+      handshake.received(ms(250), &event);
+    }
+    assert!(!handshake.is_pending());
+    assert_eq!(handshake.poll(ms(300), &parser), None);
+    assert_eq!(
+      handshake.poll(ms(350), &parser),
+      Some(HandshakeSummary {
+        local: vec![NAWS],
+        remote: vec![GMCP],
+        timed_out: false,
+      })
+    );
+  }
+
+  #[test]
+  fn test_settles_after_will() {
+    let mut parser = parser();
+    let mut handshake = Handshake::new(ms(0), ms(100), ms(1000));
+    handshake.sent(ms(0), &parser._will(NAWS).unwrap());
+    handshake.sent(ms(0), &parser._do(GMCP).unwrap());
+    assert!(handshake.is_pending());
+
+    // The DO acknowledging the offer is reported, although the offer already enabled NAWS.
+    for event in parser.receive(&[IAC, DO, NAWS, IAC, WILL, GMCP]) {
+      handshake.received(ms(50), &event);
+    }
+    assert!(!handshake.is_pending());
+    assert_eq!(
+      handshake.poll(ms(150), &parser),
+      Some(HandshakeSummary {
+        local: vec![NAWS],
+        remote: vec![GMCP],
+        timed_out: false,
+      })
+    );
+  }
+
+  #[test]
+  fn test_times_out() {
+    let mut parser = parser();
+    let mut handshake = Handshake::new(ms(0), ms(100), ms(1000));
+    handshake.sent(ms(0), &parser._do(GMCP).unwrap());
+    assert_eq!(handshake.poll(ms(999), &parser), None);
+    assert_eq!(
+      handshake.poll(ms(1000), &parser),
+      Some(HandshakeSummary {
+        timed_out: true,
+        ..HandshakeSummary::default()
+      })
+    );
+  }
+}
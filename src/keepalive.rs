@@ -0,0 +1,110 @@
+//! Scheduling of `IAC NOP` keepalives for idle connections.
+//!
+//! Servers often send an `IAC NOP` now and then so that NAT gateways and firewalls don't drop a connection that's
+//! gone quiet, and so a dead client is noticed when the write fails. [`Keepalive`] watches the data sent and only
+//! produces a NOP once nothing has been sent for the configured interval, so a busy connection carries no extra
+//! traffic.
+//!
+//! Like the [`Parser`](crate::Parser), it does no I/O and reads no clock. Every call takes the current time as a
+//! `Duration` measured from any fixed point, such as when the connection was opened.
+
+use core::time::Duration;
+
+use bytes::Bytes;
+
+use crate::events::TelnetEvents;
+use crate::telnet::op_command::{IAC, NOP};
+
+/// Decides when an idle connection needs an `IAC NOP`.
+#[derive(Clone, Debug)]
+pub struct Keepalive {
+  interval: Duration,
+  last_sent: Duration,
+}
+
+impl Keepalive {
+  /// Start scheduling keepalives.
+  ///
+  /// # Arguments
+  ///
+  /// * `now` - The current time.
+  ///
+  /// * `interval` - How long the connection may go without sending anything before a NOP is due.
+  #[must_use]
+  pub fn new(now: Duration, interval: Duration) -> Self {
+    Self {
+      interval,
+      last_sent: now,
+    }
+  }
+
+  /// Record an event about to be sent to the remote end.
+  ///
+  /// # Notes
+  ///
+  /// Pass every `DataSend` event that's written, including the parser's negotiation replies. Other events and
+  /// empty data are ignored.
+  pub fn sent(&mut self, now: Duration, event: &TelnetEvents) {
+    if matches!(event, TelnetEvents::DataSend(data) if !data.is_empty()) {
+      self.last_sent = now;
+    }
+  }
+
+  /// The time the next NOP is due, unless something else is sent first. Useful for setting a timer.
+  #[must_use]
+  pub fn next_due(&self) -> Duration {
+    self.last_sent + self.interval
+  }
+
+  /// Check whether a NOP is due.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event containing `IAC NOP`, once nothing has been sent for the
+  /// interval. It's recorded as sent, so the next one is due an interval later. None otherwise.
+  pub fn poll(&mut self, now: Duration) -> Option<TelnetEvents> {
+    if now < self.next_due() {
+      return None;
+    }
+    self.last_sent = now;
+    Some(TelnetEvents::DataSend(Bytes::from_static(&[IAC, NOP])))
+  }
+}
+
+#[cfg(test)]
+mod test_keepalive {
+  use super::*;
+
+  fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+  }
+
+  #[test]
+  fn test_idle() {
+    let nop = Some(TelnetEvents::DataSend(Bytes::from_static(&[IAC, NOP])));
+    let mut keepalive = Keepalive::new(secs(0), secs(60));
+    assert_eq!(keepalive.poll(secs(59)), None);
+    assert_eq!(keepalive.poll(secs(60)), nop);
+    assert_eq!(keepalive.next_due(), secs(120));
+    assert_eq!(keepalive.poll(secs(119)), None);
+    assert_eq!(keepalive.poll(secs(125)), nop);
+  }
+
+  #[test]
+  fn test_traffic_postpones() {
+    let mut keepalive = Keepalive::new(secs(0), secs(60));
+    keepalive.sent(
+      secs(30),
+      &TelnetEvents::DataSend(Bytes::from_static(b"hello\r\n")),
+    );
+    // Empty data and received events don't count.
+    keepalive.sent(secs(50), &TelnetEvents::DataSend(Bytes::new()));
+    keepalive.sent(
+      secs(50),
+      &TelnetEvents::DataReceive(Bytes::from_static(b"hi")),
+    );
+    assert_eq!(keepalive.next_due(), secs(90));
+    assert_eq!(keepalive.poll(secs(60)), None);
+    assert!(keepalive.poll(secs(90)).is_some());
+  }
+}
@@ -4,6 +4,7 @@
   clippy::module_name_repetitions,
   clippy::fn_params_excessive_bools,
   clippy::struct_excessive_bools,
+  clippy::used_underscore_items,
   deprecated
 )]
 
@@ -12,18 +13,60 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std as alloc;
 
-use alloc::{format, vec, vec::Vec};
+use alloc::{
+  boxed::Box,
+  collections::{BTreeMap, BTreeSet, VecDeque},
+  format,
+  string::String,
+  sync::Arc,
+  vec::Vec,
+};
 
 use bytes::{BufMut, Bytes, BytesMut};
 
 pub use bytes;
+pub mod addr;
+pub mod broadcast;
+pub mod charset;
+#[cfg(feature = "compat_api")]
+pub mod compat_api;
 pub mod compatibility;
+pub mod comport;
+pub mod conformance;
+pub mod encrypt;
 pub mod events;
+pub mod gmcp;
+pub mod handshake;
+pub mod keepalive;
+pub mod linemode;
+pub mod msdp;
+pub mod newenviron;
+pub mod pool;
 pub mod telnet;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tn3270;
+#[cfg(feature = "std")]
+pub mod transcript;
+pub mod transfer;
 
-use compatibility::{CompatibilityEntry, CompatibilityTable};
-use events::{TelnetEvents, TelnetNegotiation, TelnetSubnegotiation};
-use telnet::op_command::{DO, DONT, EOR, GA, IAC, NOP, SB, SE, WILL, WONT};
+#[cfg(target_has_atomic = "ptr")]
+use compatibility::SharedTable;
+use compatibility::{CompatibilityEntry, CompatibilityTable, Direction, EnabledOptions};
+use conformance::{ConformanceIssue, ConformanceReport};
+use encrypt::Cipher;
+use events::{
+  DecodePolicy, EventBatch, EventMeta, KeepaliveFilter, NegotiationOutcome, NegotiationRefused,
+  NulPolicy, OptionDecodeError, OversizedSubnegotiation, ProtocolError, SubnegotiationRefused,
+  TelnetEvents, TelnetNegotiation, TelnetSubnegotiation,
+};
+use msdp::MsdpChannel;
+use pool::BufferPool;
+use telnet::op_command::{
+  AO, AYT, BRK, DM, DO, DONT, EC, EL, EOR, GA, IAC, IP, IS, NOP, SB, SE, SEND, WILL, WONT,
+};
+use tn3270::Tn3270Mode;
+use transfer::{TransferDetector, ZMODEM_SIGNATURE_LEN};
 
 enum EventType {
   None(Bytes),
@@ -32,6 +75,22 @@ enum EventType {
   Neg(Bytes),
 }
 
+type EventCtor = fn(Bytes) -> EventType;
+
+/// A function converting the payload of a subnegotiation into an event. See `Parser::set_subnegotiation_decoder`.
+pub type SubnegotiationDecoder = Box<dyn Fn(&Bytes) -> Option<TelnetEvents> + Send + Sync>;
+
+/// A function converting the payload of a subnegotiation into an event, or describing why it can't. See
+/// `Parser::set_fallible_subnegotiation_decoder`.
+pub type FallibleSubnegotiationDecoder =
+  Box<dyn Fn(&Bytes) -> Result<TelnetEvents, String> + Send + Sync>;
+
+/// A decoder registered for an option's subnegotiations.
+enum Decoder {
+  Optional(SubnegotiationDecoder),
+  Fallible(FallibleSubnegotiationDecoder),
+}
+
 #[deprecated(
   since = "0.2.1",
   note = "Use `Bytes::copy_from_slice` directly instead."
@@ -47,7 +106,69 @@ macro_rules! vbytes {
 /// A telnet parser that handles the main parts of the protocol.
 pub struct Parser {
   pub options: CompatibilityTable,
+  #[cfg(target_has_atomic = "ptr")]
+  shared_options: Option<SharedTable>,
   buffer: BytesMut,
+  terminal_types: Option<Vec<String>>,
+  local_terminal_types: Vec<String>,
+  terminal_type_index: usize,
+  environ_requested: bool,
+  // Options offered with `_will` that the remote end hasn't answered yet.
+  will_offers: BTreeSet<u8>,
+  // The GMCP packages declared with `Core.Supports.*` messages, by us and by the remote end.
+  local_gmcp: gmcp::Supports,
+  remote_gmcp: gmcp::Supports,
+  // Queued events, with the raw bytes they were parsed from while raw capture is enabled.
+  pending: VecDeque<(EventMeta, TelnetEvents, Bytes)>,
+  raw_capture: bool,
+  // The raw bytes of the event being processed, while raw capture is enabled.
+  raw: Bytes,
+  offset: u64,
+  meta: EventMeta,
+  decoders: BTreeMap<u8, Decoder>,
+  decode_policies: BTreeMap<u8, DecodePolicy>,
+  journal: VecDeque<(EventMeta, TelnetEvents)>,
+  journal_capacity: usize,
+  pool: Option<Arc<dyn BufferPool>>,
+  #[cfg(feature = "std")]
+  last_activity: BTreeMap<u8, std::time::Instant>,
+  #[cfg(feature = "std")]
+  reply_window: Option<std::time::Duration>,
+  // When each refusal was last sent, keyed by (command, option).
+  #[cfg(feature = "std")]
+  recent_replies: BTreeMap<(u8, u8), std::time::Instant>,
+  no_carry_over: BTreeSet<u8>,
+  subnegotiation_bytes: BTreeMap<u8, u64>,
+  track_offers: bool,
+  option_change_events: bool,
+  strip_go_ahead: bool,
+  // Offers made with `_will` or `_do` that the remote end hasn't answered yet, while tracking offers.
+  offers: BTreeSet<(u8, Direction)>,
+  keepalive_filter: KeepaliveFilter,
+  // Whether the data received so far ended with a complete line, so that an empty line would be keepalive traffic.
+  line_ended: bool,
+  passthrough: bool,
+  transfer_detector: Option<TransferDetector>,
+  diagnostics: bool,
+  refuse_unexpected_subnegotiations: bool,
+  refuse_local_echo: bool,
+  subnegotiation_limit: Option<usize>,
+  // Whether the start of the buffered subnegotiation was discarded for being over the limit.
+  truncated_subnegotiation: bool,
+  outgoing_subnegotiation_limit: Option<usize>,
+  oversized_subnegotiations: OversizedSubnegotiation,
+  msdp_preference: Option<MsdpChannel>,
+  nul_policy: NulPolicy,
+  cipher: Option<Box<dyn Cipher>>,
+  decrypting: bool,
+  decrypted: usize,
+  tn3270_detection: bool,
+  tn3270_mode: Tn3270Mode,
+  record: Option<BytesMut>,
+  // Output queued while waiting for the remote end's GA, while in half-duplex mode.
+  half_duplex: Option<BytesMut>,
+  // Whether the remote end has the turn to send, while in half-duplex mode.
+  remote_turn: bool,
 }
 
 impl Default for Parser {
@@ -56,6 +177,14 @@ impl Default for Parser {
   }
 }
 
+impl Drop for Parser {
+  fn drop(&mut self) {
+    if let Some(pool) = &self.pool {
+      pool.release(core::mem::take(&mut self.buffer));
+    }
+  }
+}
+
 impl Parser {
   /// Create a default, empty Parser with an internal buffer capacity of 128 bytes.
   #[must_use]
@@ -83,10 +212,80 @@ impl Parser {
   pub fn with_support_and_capacity(size: usize, table: CompatibilityTable) -> Self {
     Self {
       options: table,
+      #[cfg(target_has_atomic = "ptr")]
+      shared_options: None,
       buffer: BytesMut::with_capacity(size),
+      terminal_types: None,
+      local_terminal_types: Vec::new(),
+      terminal_type_index: 0,
+      environ_requested: false,
+      will_offers: BTreeSet::new(),
+      local_gmcp: gmcp::Supports::default(),
+      remote_gmcp: gmcp::Supports::default(),
+      pending: VecDeque::new(),
+      raw_capture: false,
+      raw: Bytes::new(),
+      offset: 0,
+      meta: EventMeta::default(),
+      decoders: BTreeMap::new(),
+      decode_policies: BTreeMap::new(),
+      journal: VecDeque::new(),
+      journal_capacity: 0,
+      pool: None,
+      #[cfg(feature = "std")]
+      last_activity: BTreeMap::new(),
+      #[cfg(feature = "std")]
+      reply_window: None,
+      #[cfg(feature = "std")]
+      recent_replies: BTreeMap::new(),
+      no_carry_over: BTreeSet::new(),
+      subnegotiation_bytes: BTreeMap::new(),
+      track_offers: false,
+      option_change_events: false,
+      strip_go_ahead: false,
+      offers: BTreeSet::new(),
+      keepalive_filter: KeepaliveFilter::default(),
+      line_ended: true,
+      passthrough: false,
+      transfer_detector: None,
+      diagnostics: false,
+      refuse_unexpected_subnegotiations: false,
+      refuse_local_echo: false,
+      subnegotiation_limit: None,
+      truncated_subnegotiation: false,
+      outgoing_subnegotiation_limit: None,
+      oversized_subnegotiations: OversizedSubnegotiation::default(),
+      msdp_preference: None,
+      nul_policy: NulPolicy::default(),
+      cipher: None,
+      decrypting: false,
+      decrypted: 0,
+      tn3270_detection: false,
+      tn3270_mode: Tn3270Mode::default(),
+      record: None,
+      half_duplex: None,
+      remote_turn: false,
     }
   }
 
+  /// Create a parser that takes its internal buffers from a `BufferPool`, directly supplying a
+  /// `CompatibilityTable`.
+  ///
+  /// # Arguments
+  ///
+  /// * `table` - The options supported by the parser.
+  ///
+  /// * `pool` - The pool to acquire buffers from. A buffer is acquired whenever received data doesn't fit in the
+  ///   current one, and the old buffer is released back to the pool. The final buffer is released when the parser
+  ///   is dropped.
+  #[must_use]
+  pub fn with_pool(table: CompatibilityTable, pool: Arc<dyn BufferPool>) -> Self {
+    let mut parser = Self::with_support_and_capacity(0, table);
+    parser.buffer = pool.acquire(128);
+    parser.pool = Some(pool);
+    parser
+  }
+
   /// Receive bytes into the internal buffer.
   ///
   /// # Arguments
@@ -97,9 +296,377 @@ impl Parser {
   ///
   /// `Vec<TelnetEvents>` - Any events parsed from the internal buffer with the new bytes.
   ///
+  /// # Notes
+  ///
+  /// Events are returned in the order their bytes were received, with these guarantees:
+  ///
+  /// * A `DataSend` event replying to a received negotiation comes immediately before the `Negotiation` event for
+  ///   it. Refusals of unsupported options are not followed by a `Negotiation` event.
+  /// * A `DecompressImmediate` event is always the final event. It holds all of the data received after the
+  ///   compression start, none of which has been parsed.
   pub fn receive(&mut self, data: &[u8]) -> Vec<TelnetEvents> {
+    // Most reads produce no more than a couple of events, and many none at all, so allocate only once one is parsed.
+    let mut events = Vec::new();
+    self.receive_into(data, &mut events);
+    events
+  }
+
+  /// Receive bytes into the internal buffer, appending the events to a collection supplied by the caller.
+  ///
+  /// # Arguments
+  ///
+  /// * `data` - The bytes to be received. This should be sourced from the remote side of a connection.
+  ///
+  /// * `events` - The collection parsed events are appended to, in the order described by `receive`.
+  ///
+  /// # Notes
+  ///
+  /// This avoids allocating a `Vec` of events per read. Pass a `Vec` that's cleared and reused between reads, or
+  /// a stack-allocated collection such as a `SmallVec<[TelnetEvents; 4]>` sized for the common case.
+  pub fn receive_into<E>(&mut self, data: &[u8], events: &mut E)
+  where
+    E: Extend<TelnetEvents>,
+  {
+    self.feed(data);
+    events.extend(core::iter::from_fn(|| self.next_event()));
+  }
+
+  /// Receive bytes into the internal buffer, returning the events as an `EventBatch`.
+  ///
+  /// # Arguments
+  ///
+  /// * `data` - The bytes to be received. This should be sourced from the remote side of a connection.
+  ///
+  /// # Returns
+  ///
+  /// `EventBatch` - Any events parsed from the internal buffer with the new bytes, in the order described by
+  /// `receive`.
+  pub fn receive_batch(&mut self, data: &[u8]) -> EventBatch {
+    EventBatch::new(self.receive(data))
+  }
+
+  /// Append bytes to the internal buffer without processing them.
+  ///
+  /// # Arguments
+  ///
+  /// * `data` - The bytes to be received. This should be sourced from the remote side of a connection.
+  ///
+  /// # Notes
+  ///
+  /// Use `next_event` to pull the parsed events out one at a time. Together these form a pull-based alternative to
+  /// `receive` that doesn't allocate a `Vec` of events per read, suited to completion-based runtimes that hand
+  /// back filled buffers.
+  pub fn feed(&mut self, data: &[u8]) {
+    if let Some(pool) = &self.pool {
+      if self.buffer.capacity() - self.buffer.len() < data.len() {
+        let mut buffer = pool.acquire(self.buffer.len() + data.len());
+        buffer.put(&self.buffer[..]);
+        pool.release(core::mem::replace(&mut self.buffer, buffer));
+      }
+    }
     self.buffer.put(data);
-    self.process()
+  }
+
+  /// Finish parsing once the connection has closed, flushing anything left buffered.
+  ///
+  /// # Returns
+  ///
+  /// `Vec<TelnetEvents>` - The events still to be parsed from fed data, followed by an incomplete record in record
+  /// mode as a `DataReceive` event, and a `ProtocolError` event if the data ended part way through a subnegotiation
+  /// or IAC command.
+  ///
+  /// # Notes
+  ///
+  /// Received data is emitted as soon as it arrives, so trailing prompt text without a line ending has already been
+  /// returned by `receive`. The internal buffer is empty afterwards.
+  pub fn finish(&mut self) -> Vec<TelnetEvents> {
+    let mut events: Vec<TelnetEvents> = core::iter::from_fn(|| self.next_event()).collect();
+    let record = self.record.as_mut().map(|record| record.split().freeze());
+    if let Some(record) = record.filter(|record| !record.is_empty()) {
+      events.push(TelnetEvents::DataReceive(record));
+    }
+    let rest = self.buffer.split().freeze();
+    self.truncated_subnegotiation = false;
+    match (rest.first(), rest.get(1), rest.get(2)) {
+      (None, _, _) => {}
+      (Some(&IAC), Some(&SB), Some(&option)) => events.push(TelnetEvents::ProtocolError(
+        ProtocolError::UnterminatedSubnegotiation(option),
+      )),
+      (Some(&IAC), _, _) => {
+        events.push(TelnetEvents::ProtocolError(ProtocolError::TruncatedCommand));
+      }
+      _ => events.push(TelnetEvents::DataReceive(rest)),
+    }
+    events
+  }
+
+  /// Parse and return the next event from the internal buffer.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents>` - The next event, or None once the buffered data has been fully processed. An
+  /// unterminated subnegotiation or IAC command stays buffered until more data is fed in.
+  ///
+  /// # Notes
+  ///
+  /// Feeding all of the data for a read and then calling `next_event` until it returns None produces the same
+  /// events as `receive`.
+  pub fn next_event(&mut self) -> Option<TelnetEvents> {
+    self.next_event_with_meta().map(|(_, event)| event)
+  }
+
+  /// Parse and return the next event from the internal buffer, along with metadata describing where in the
+  /// received stream it came from.
+  ///
+  /// # Returns
+  ///
+  /// `Option<(EventMeta, TelnetEvents)>` - The next event and its metadata, or None once the buffered data has been
+  /// fully processed.
+  ///
+  /// # Notes
+  ///
+  /// Events sent in reply to received data (e.g. a `DataSend` answering a negotiation) share the metadata of the
+  /// event they answer.
+  pub fn next_event_with_meta(&mut self) -> Option<(EventMeta, TelnetEvents)> {
+    self
+      .next_event_with_raw()
+      .map(|(meta, event, _)| (meta, event))
+  }
+
+  /// Parse and return the next event from the internal buffer, along with its metadata and the raw bytes it was
+  /// parsed from.
+  ///
+  /// # Returns
+  ///
+  /// `Option<(EventMeta, TelnetEvents, Bytes)>` - The next event, its metadata and its raw bytes, or None once the
+  /// buffered data has been fully processed. The raw bytes are empty unless enabled with `set_raw_capture`.
+  ///
+  /// # Notes
+  ///
+  /// As with the metadata, events sent in reply to received data share the raw bytes of the event they answer.
+  pub fn next_event_with_raw(&mut self) -> Option<(EventMeta, TelnetEvents, Bytes)> {
+    loop {
+      if let Some(event) = self.pending.pop_front() {
+        return Some(event);
+      }
+      if self.passthrough {
+        let data = self.next_passthrough_data()?;
+        self.capture_raw(&data);
+        self.emit(TelnetEvents::DataReceive(data));
+        self.raw = Bytes::new();
+        continue;
+      }
+      let data = self.next_event_data()?;
+      let complete = self.process_event(data);
+      self.raw = Bytes::new();
+      if !complete {
+        return self.pending.pop_front();
+      }
+    }
+  }
+
+  /// The number of received bytes that have been processed into events so far.
+  ///
+  /// Bytes held in the internal buffer waiting for the rest of a subnegotiation or IAC command are not counted
+  /// until it is complete.
+  #[must_use]
+  pub fn stream_offset(&self) -> u64 {
+    self.offset
+  }
+
+  /// Register a decoder for the subnegotiations of an option.
+  ///
+  /// # Arguments
+  ///
+  /// * `option` - The byte representing the option.
+  ///
+  /// * `decoder` - Called with the payload of each subnegotiation for the option, as it would appear in a
+  ///   `TelnetEvents::Subnegotiation` event. The event it returns is emitted in place of the `Subnegotiation`
+  ///   event. If it returns None, the `Subnegotiation` event is emitted as usual.
+  ///
+  /// # Notes
+  ///
+  /// Registering a decoder for an option replaces any decoder previously registered for it. Decoders only see
+  /// subnegotiations that would otherwise be emitted, so the option must still be locally supported and enabled.
+  ///
+  /// `gmcp::decode` is a ready-made decoder producing `TelnetEvents::Gmcp` events.
+  pub fn set_subnegotiation_decoder<F>(&mut self, option: u8, decoder: F)
+  where
+    F: Fn(&Bytes) -> Option<TelnetEvents> + Send + Sync + 'static,
+  {
+    self
+      .decoders
+      .insert(option, Decoder::Optional(Box::new(decoder)));
+  }
+
+  /// Register a decoder for the subnegotiations of an option, which reports the payloads it can't decode.
+  ///
+  /// # Arguments
+  ///
+  /// * `option` - The byte representing the option.
+  ///
+  /// * `decoder` - Called with the payload of each subnegotiation for the option, as it would appear in a
+  ///   `TelnetEvents::Subnegotiation` event. The event it returns is emitted in place of the `Subnegotiation`
+  ///   event. If it returns an error, a `TelnetEvents::OptionDecodeError` event carrying the error and the payload
+  ///   is emitted instead.
+  ///
+  /// # Notes
+  ///
+  /// This replaces any decoder previously registered for the option, with either method. `gmcp::try_decode` is a
+  /// ready-made decoder producing `TelnetEvents::Gmcp` events.
+  pub fn set_fallible_subnegotiation_decoder<F>(&mut self, option: u8, decoder: F)
+  where
+    F: Fn(&Bytes) -> Result<TelnetEvents, String> + Send + Sync + 'static,
+  {
+    self
+      .decoders
+      .insert(option, Decoder::Fallible(Box::new(decoder)));
+  }
+
+  /// Remove the decoder registered for the subnegotiations of an option, if any.
+  pub fn clear_subnegotiation_decoder(&mut self, option: u8) {
+    self.decoders.remove(&option);
+  }
+
+  /// Set how IAC bytes in the subnegotiation payloads of an option are decoded. See `DecodePolicy`.
+  ///
+  /// # Arguments
+  ///
+  /// * `option` - The byte representing the option.
+  ///
+  /// * `policy` - The policy to use. Options default to `DecodePolicy::Raw`.
+  ///
+  /// # Notes
+  ///
+  /// The policy is applied before any decoder registered with `set_subnegotiation_decoder` sees the payload.
+  pub fn set_decode_policy(&mut self, option: u8, policy: DecodePolicy) {
+    if policy == DecodePolicy::default() {
+      self.decode_policies.remove(&option);
+    } else {
+      self.decode_policies.insert(option, policy);
+    }
+  }
+
+  /// Keep a copy of the most recent events the parser emits.
+  ///
+  /// # Arguments
+  ///
+  /// * `capacity` - The number of events to keep. Once the journal is full, the oldest event is dropped for each
+  ///   new one. A capacity of 0, the default, disables the journal.
+  ///
+  /// # Notes
+  ///
+  /// This is intended for debugging views of recent protocol traffic. Events are recorded as they're parsed, so
+  /// the journal can run ahead of the events pulled with `next_event`. Shrinking the capacity drops the oldest
+  /// entries.
+  pub fn set_journal_capacity(&mut self, capacity: usize) {
+    self.journal_capacity = capacity;
+    while self.journal.len() > capacity {
+      self.journal.pop_front();
+    }
+    self.journal.shrink_to(capacity);
+  }
+
+  /// The most recent events emitted by the parser, oldest first. Empty unless enabled with
+  /// `set_journal_capacity`.
+  #[must_use]
+  pub fn journal(&self) -> &VecDeque<(EventMeta, TelnetEvents)> {
+    &self.journal
+  }
+
+  /// Remove all events from the journal, keeping its capacity.
+  pub fn clear_journal(&mut self) {
+    self.journal.clear();
+  }
+
+  /// When a negotiation or subnegotiation for an option was last received.
+  ///
+  /// # Returns
+  ///
+  /// `Option<Instant>` - The time the most recent traffic for the option was parsed, or None if none has been
+  /// received.
+  ///
+  /// # Notes
+  ///
+  /// This can be used to detect options that have gone quiet, e.g. GMCP silently stopping after a server reboot,
+  /// and renegotiate them. Subnegotiations are counted even if they aren't emitted because the option is disabled.
+  #[cfg(feature = "std")]
+  #[must_use]
+  pub fn last_activity(&self, option: u8) -> Option<std::time::Instant> {
+    self.last_activity.get(&option).copied()
+  }
+
+  /// The number of subnegotiation bytes received for an option.
+  ///
+  /// # Returns
+  ///
+  /// `u64` - The total size of the complete subnegotiations received for the option, including the `IAC SB` and
+  /// `IAC SE` framing, since the parser was created or the counters were last reset.
+  ///
+  /// # Notes
+  ///
+  /// Subnegotiations are counted even if they aren't emitted because the option is disabled, so this can show
+  /// which protocols are using the connection's bandwidth, e.g. GMCP room updates compared to text.
+  #[must_use]
+  pub fn subnegotiation_bytes(&self, option: u8) -> u64 {
+    self.subnegotiation_bytes.get(&option).copied().unwrap_or(0)
+  }
+
+  /// The number of subnegotiation bytes received for every option that has received any, as
+  /// `(option, bytes)` pairs in option order. See `subnegotiation_bytes`.
+  pub fn subnegotiation_byte_counts(&self) -> impl Iterator<Item = (u8, u64)> + '_ {
+    self
+      .subnegotiation_bytes
+      .iter()
+      .map(|(&option, &bytes)| (option, bytes))
+  }
+
+  /// Reset the subnegotiation byte counters of all options to zero.
+  pub fn reset_subnegotiation_bytes(&mut self) {
+    self.subnegotiation_bytes.clear();
+  }
+
+  #[cfg(feature = "std")]
+  fn record_activity(&mut self, option: u8) {
+    self.last_activity.insert(option, std::time::Instant::now());
+  }
+
+  /// Emit a reply to a negotiation, unless an identical reply was sent within the suppression window.
+  fn emit_reply(&mut self, data: &[u8]) {
+    #[cfg(feature = "std")]
+    if let (Some(window), WONT | DONT) = (self.reply_window, data[1]) {
+      // Only refusals are suppressed, and `set_option` forgets them once the option's state changes.
+      let now = std::time::Instant::now();
+      let key = (data[1], data[2]);
+      match self.recent_replies.get(&key) {
+        Some(sent) if now.duration_since(*sent) < window => return,
+        _ => {
+          self.recent_replies.insert(key, now);
+        }
+      }
+    }
+    self.emit(TelnetEvents::build_send(Bytes::copy_from_slice(data)));
+  }
+
+  fn emit(&mut self, event: TelnetEvents) {
+    self.emit_with_meta(self.meta, event);
+  }
+
+  fn emit_with_meta(&mut self, meta: EventMeta, event: TelnetEvents) {
+    if self.journal_capacity > 0 {
+      if self.journal.len() == self.journal_capacity {
+        self.journal.pop_front();
+      }
+      self.journal.push_back((meta, event.clone()));
+    }
+    self.pending.push_back((meta, event, self.raw.clone()));
+  }
+
+  /// Keep the raw bytes of the event being processed, if raw capture is enabled.
+  fn capture_raw(&mut self, data: &Bytes) {
+    if self.raw_capture {
+      self.raw = data.clone();
+    }
   }
 
   /// Get whether the remote end supports and is using linemode.
@@ -199,22 +766,71 @@ impl Parser {
   ///
   /// # Notes
   ///
-  /// This method will do nothing if the option is not "supported" locally via the `CompatibilityTable`.
+  /// This method will do nothing if the option is not "supported" locally via the `CompatibilityTable`. Use
+  /// `try_will` to find out why nothing was produced.
   pub fn _will(&mut self, option: u8) -> Option<TelnetEvents> {
+    self.try_will(option).ok()
+  }
+
+  /// Indicate to the other side that you are able and wanting to utilize an option, explaining any refusal.
+  ///
+  /// # Arguments
+  ///
+  /// `option` - A `u8` representing the telnet option code that you want to enable locally.
+  ///
+  /// # Errors
+  ///
+  /// `NegotiationRefused::UnsupportedLocally` if the option is not "supported" locally via the
+  /// `CompatibilityTable`, or `NegotiationRefused::AlreadyEnabled` if it's already enabled locally.
+  pub fn try_will(&mut self, option: u8) -> Result<TelnetEvents, NegotiationRefused> {
     match self.options.get_option(option) {
-      mut opt @ CompatibilityEntry {
-        local: true,
-        local_state: false,
-        ..
-      } => {
+      CompatibilityEntry { local: false, .. } => Err(NegotiationRefused::UnsupportedLocally),
+      CompatibilityEntry {
+        local_state: true, ..
+      } => Err(NegotiationRefused::AlreadyEnabled),
+      mut opt => {
         opt.local_state = true;
-        self.options.set_option(option, opt);
-        Some(self.negotiate(WILL, option))
+        self.set_option(option, opt);
+        if self.track_offers {
+          self.offers.insert((option, Direction::Local));
+        }
+        self.will_offers.insert(option);
+        Ok(self.negotiate(WILL, option))
       }
-      _ => None,
     }
   }
 
+  /// Offer the MUD Sound Protocol (MSP) to the remote end, adding local support for it if necessary.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - The `DataSend` event to be processed, or None if MSP is already enabled.
+  ///
+  /// # Notes
+  ///
+  /// This is intended for use when acting as a server. Once enabled, MSP triggers are sent in-band as regular data.
+  pub fn enable_msp(&mut self) -> Option<TelnetEvents> {
+    self.options.support_local(telnet::op_option::MSP);
+    self.publish_options();
+    self._will(telnet::op_option::MSP)
+  }
+
+  /// Offer the MUD eXtension Protocol (MXP) to the remote end, adding local support for it if necessary.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - The `DataSend` event to be processed, or None if MXP is already enabled.
+  ///
+  /// # Notes
+  ///
+  /// This is intended for use when acting as a server. When the remote end accepts MXP, the parser replies with
+  /// the empty `IAC SB MXP IAC SE` subnegotiation that starts MXP mode.
+  pub fn enable_mxp(&mut self) -> Option<TelnetEvents> {
+    self.options.support_local(telnet::op_option::MXP);
+    self.publish_options();
+    self._will(telnet::op_option::MXP)
+  }
+
   /// Indicate to the other side that you are not wanting to utilize an option.
   ///
   /// # Arguments
@@ -226,15 +842,30 @@ impl Parser {
   /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the option is already disabled.
   ///
   pub fn _wont(&mut self, option: u8) -> Option<TelnetEvents> {
+    self.try_wont(option).ok()
+  }
+
+  /// Indicate to the other side that you are not wanting to utilize an option, explaining any refusal.
+  ///
+  /// # Arguments
+  ///
+  /// `option` - A `u8` representing the telnet option code that you want to disable locally.
+  ///
+  /// # Errors
+  ///
+  /// `NegotiationRefused::AlreadyDisabled` if the option is already disabled locally.
+  pub fn try_wont(&mut self, option: u8) -> Result<TelnetEvents, NegotiationRefused> {
     match self.options.get_option(option) {
       mut opt @ CompatibilityEntry {
         local_state: true, ..
       } => {
         opt.local_state = false;
-        self.options.set_option(option, opt);
-        Some(self.negotiate(WONT, option))
+        self.set_option(option, opt);
+        self.offers.remove(&(option, Direction::Local));
+        self.will_offers.remove(&option);
+        Ok(self.negotiate(WONT, option))
       }
-      _ => None,
+      _ => Err(NegotiationRefused::AlreadyDisabled),
     }
   }
 
@@ -250,65 +881,188 @@ impl Parser {
   ///
   /// # Notes
   ///
-  /// This method will do nothing if the option is not "supported" remotely via the `CompatibilityTable`.
+  /// This method will do nothing if the option is not "supported" remotely via the `CompatibilityTable`. Use
+  /// `try_do` to find out why nothing was produced.
   pub fn _do(&mut self, option: u8) -> Option<TelnetEvents> {
-    match self.options.get_option(option) {
-      CompatibilityEntry {
-        remote: true,
-        remote_state: false,
-        ..
-      } => Some(self.negotiate(DO, option)),
-      _ => None,
-    }
+    self.try_do(option).ok()
   }
 
-  /// Indicate to the other side that you would like them to stop utilizing an option.
+  /// Indicate to the other side that you would like them to utilize an option, explaining any refusal.
   ///
   /// # Arguments
   ///
-  /// `option` - A `u8` representing the telnet option code that you want to disable remotely.
+  /// `option` - A `u8` representing the telnet option code that you want to enable remotely.
   ///
-  /// # Returns
+  /// # Errors
   ///
-  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the option is already disabled.
+  /// `NegotiationRefused::UnsupportedRemotely` if the option is not "supported" remotely via the
+  /// `CompatibilityTable`, or `NegotiationRefused::AlreadyEnabled` if it's already enabled remotely.
   ///
-  pub fn _dont(&mut self, option: u8) -> Option<TelnetEvents> {
+  /// # Notes
+  ///
+  /// As with `_do`, the table isn't updated until the remote end answers with WILL, so repeated calls before then
+  /// each produce a DO.
+  pub fn try_do(&mut self, option: u8) -> Result<TelnetEvents, NegotiationRefused> {
     match self.options.get_option(option) {
+      CompatibilityEntry { remote: false, .. } => Err(NegotiationRefused::UnsupportedRemotely),
       CompatibilityEntry {
         remote_state: true, ..
-      } => Some(self.negotiate(DONT, option)),
-      _ => None,
+      } => Err(NegotiationRefused::AlreadyEnabled),
+      _ => {
+        if self.track_offers {
+          self.offers.insert((option, Direction::Remote));
+        }
+        Ok(self.negotiate(DO, option))
+      }
     }
   }
 
-  /// Send a subnegotiation for a locally supported option.
+  /// Indicate to the other side that you would like them to stop utilizing an option.
   ///
   /// # Arguments
   ///
-  /// `option` - A `u8` representing the telnet option code for the negotiation.
-  ///
-  /// `data` - A `Bytes` containing the data to be sent in the subnegotiation. This data will have all IAC (255) byte values escaped.
+  /// `option` - A `u8` representing the telnet option code that you want to disable remotely.
   ///
   /// # Returns
   ///
-  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the option is not supported or is currently disabled.
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the option is already disabled.
   ///
   /// # Notes
   ///
-  /// This method will do nothing if the option is not "supported" locally via the `CompatibilityTable`.
-  pub fn subnegotiation<T>(&mut self, option: u8, data: T) -> Option<TelnetEvents>
-  where
-    Bytes: From<T>,
+  /// Like `_wont`, the option is marked as disabled immediately since the remote end must honour the request.
+  pub fn _dont(&mut self, option: u8) -> Option<TelnetEvents> {
+    self.try_dont(option).ok()
+  }
+
+  /// Indicate to the other side that you would like them to stop utilizing an option, explaining any refusal.
+  ///
+  /// # Arguments
+  ///
+  /// `option` - A `u8` representing the telnet option code that you want to disable remotely.
+  ///
+  /// # Errors
+  ///
+  /// `NegotiationRefused::AlreadyDisabled` if the option is already disabled remotely.
+  pub fn try_dont(&mut self, option: u8) -> Result<TelnetEvents, NegotiationRefused> {
+    match self.options.get_option(option) {
+      mut opt @ CompatibilityEntry {
+        remote_state: true, ..
+      } => {
+        opt.remote_state = false;
+        self.set_option(option, opt);
+        self.offers.remove(&(option, Direction::Remote));
+        Ok(self.negotiate(DONT, option))
+      }
+      _ => Err(NegotiationRefused::AlreadyDisabled),
+    }
+  }
+
+  /// Request a change to an option's state, describing when the `CompatibilityTable` reflects the change.
+  ///
+  /// # Arguments
+  ///
+  /// `command` - A `u8` representing the telnet command code to be negotiated with. Example: WILL (251), WONT (252), DO (253), DONT (254)
+  ///
+  /// `option` - A `u8` representing the telnet option code that is being negotiated.
+  ///
+  /// # Returns
+  ///
+  /// `Option<NegotiationOutcome>` - The `DataSend` event to be processed, wrapped to describe when the table is
+  /// updated. None if `command` isn't a negotiation command, or the corresponding `_will`, `_wont`, `_do` or
+  /// `_dont` call produced nothing.
+  ///
+  /// # Notes
+  ///
+  /// Disabling an option with WONT or DONT, and enabling a local option with WILL, updates the table immediately.
+  /// Enabling a remote option with DO only updates the table once the remote end answers with WILL.
+  pub fn request_option(&mut self, command: u8, option: u8) -> Option<NegotiationOutcome> {
+    match command {
+      WILL => self._will(option).map(NegotiationOutcome::Immediate),
+      WONT => self._wont(option).map(NegotiationOutcome::Immediate),
+      DO => self._do(option).map(NegotiationOutcome::OnAcknowledgement),
+      DONT => self._dont(option).map(NegotiationOutcome::Immediate),
+      _ => None,
+    }
+  }
+
+  /// Send a subnegotiation for a locally supported option.
+  ///
+  /// # Arguments
+  ///
+  /// `option` - A `u8` representing the telnet option code for the negotiation.
+  ///
+  /// `data` - A `Bytes` containing the data to be sent in the subnegotiation. This data will have all IAC (255) byte values escaped.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the option is not supported or is currently disabled.
+  ///
+  /// # Notes
+  ///
+  /// This method will do nothing if the option is not "supported" locally via the `CompatibilityTable`, or if the
+  /// data is over the limit set with `set_outgoing_subnegotiation_limit`. Use `try_subnegotiation` to find out why
+  /// nothing was produced.
+  pub fn subnegotiation<T>(&mut self, option: u8, data: T) -> Option<TelnetEvents>
+  where
+    Bytes: From<T>,
+  {
+    self.try_subnegotiation(option, data).ok()
+  }
+
+  /// Send a subnegotiation for a locally supported option, explaining any refusal.
+  ///
+  /// # Arguments
+  ///
+  /// `option` - A `u8` representing the telnet option code for the negotiation.
+  ///
+  /// `data` - A `Bytes` containing the data to be sent in the subnegotiation. This data will have all IAC (255) byte values escaped.
+  ///
+  /// # Errors
+  ///
+  /// `SubnegotiationRefused::NotEnabled` if the option is not "supported" or is currently disabled locally, or
+  /// `SubnegotiationRefused::TooLong` if the data is over the limit set with `set_outgoing_subnegotiation_limit`
+  /// and couldn't be split.
+  pub fn try_subnegotiation<T>(
+    &mut self,
+    option: u8,
+    data: T,
+  ) -> Result<TelnetEvents, SubnegotiationRefused>
+  where
+    Bytes: From<T>,
   {
     match self.options.get_option(option) {
       CompatibilityEntry {
         local: true,
         local_state: true,
         ..
-      } => Some(TelnetEvents::build_send(
-        TelnetSubnegotiation::new(option, Bytes::from(data)).to_bytes(),
-      )),
-      _ => None,
+      } => {
+        let data = Bytes::from(data);
+        let bytes = match self.outgoing_subnegotiation_limit {
+          Some(limit) if data.len() > limit => {
+            let parts = match self.oversized_subnegotiations {
+              OversizedSubnegotiation::Split => Self::split_subnegotiation(option, &data, limit),
+              OversizedSubnegotiation::Refuse => None,
+            };
+            let parts = parts.ok_or(SubnegotiationRefused::TooLong {
+              length: data.len(),
+              limit,
+            })?;
+            let mut bytes = BytesMut::new();
+            for part in parts {
+              bytes.extend_from_slice(&TelnetSubnegotiation::new(option, part).to_bytes());
+            }
+            bytes.freeze()
+          }
+          _ => TelnetSubnegotiation::new(option, data.clone()).to_bytes(),
+        };
+        if option == telnet::op_option::GMCP {
+          if let Some(message) = gmcp::GmcpMessage::parse(&data) {
+            self.local_gmcp.apply(&message);
+          }
+        }
+        Ok(TelnetEvents::build_send(bytes))
+      }
+      _ => Err(SubnegotiationRefused::NotEnabled),
     }
   }
 
@@ -331,6 +1085,756 @@ impl Parser {
     self.subnegotiation(option, Bytes::copy_from_slice(text.as_bytes()))
   }
 
+  /// Send a GMCP message, serializing `value` as the JSON body.
+  ///
+  /// # Arguments
+  ///
+  /// `package` - A `&str` naming the GMCP package and message. Example: `Core.Hello`
+  ///
+  /// `value` - The value to serialize as the message body.
+  ///
+  /// # Returns
+  ///
+  /// `Result<Option<TelnetEvents::DataSend>, serde_json::Error>` - A `DataSend` event to be processed, or None if
+  /// GMCP is not supported or is currently disabled.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `value` can't be serialized as JSON.
+  ///
+  /// # Notes
+  ///
+  /// The assembled subnegotiation will have all IAC (255) byte values escaped.
+  #[cfg(feature = "serde_json")]
+  pub fn send_gmcp<T>(
+    &mut self,
+    package: &str,
+    value: &T,
+  ) -> Result<Option<TelnetEvents>, serde_json::Error>
+  where
+    T: serde::Serialize + ?Sized,
+  {
+    let body = Bytes::from(serde_json::to_vec(value)?);
+    let message = gmcp::GmcpMessage::new(package, Some(body));
+    Ok(self.subnegotiation(telnet::op_option::GMCP, message.to_bytes()))
+  }
+
+  /// Whether the remote end declared support for a GMCP package with `Core.Supports.*` messages.
+  ///
+  /// # Arguments
+  ///
+  /// `spec` - A `&str` naming the package, optionally followed by a version. Example: `Char 1`
+  ///
+  /// # Returns
+  ///
+  /// `bool` - True if the remote end declared the package, with the given version if there is one.
+  ///
+  /// # Notes
+  ///
+  /// This is intended for use when acting as a server, to avoid sending packages the client didn't ask for. The
+  /// packages are tracked while GMCP is enabled in either direction, and forgotten by `prepare_reconnect`.
+  #[must_use]
+  pub fn gmcp_supports(&self, spec: &str) -> bool {
+    self.remote_gmcp.contains(spec)
+  }
+
+  /// The GMCP packages declared with `Core.Supports.*` messages.
+  ///
+  /// # Arguments
+  ///
+  /// `direction` - `Direction::Local` for the packages declared in subnegotiations sent with this parser, or
+  /// `Direction::Remote` for the packages the remote end declared.
+  #[must_use]
+  pub fn gmcp_packages(&self, direction: Direction) -> &gmcp::Supports {
+    match direction {
+      Direction::Local => &self.local_gmcp,
+      Direction::Remote => &self.remote_gmcp,
+    }
+  }
+
+  /// Ask the remote end for its terminal type, collecting every type it reports.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event containing a `TTYPE SEND` subnegotiation, or None if the
+  /// remote end has not enabled the TTYPE option.
+  ///
+  /// # Notes
+  ///
+  /// This is intended for use when acting as a server. Following RFC 1091, each `TTYPE IS` response received while
+  /// the cycle is in progress is answered with another `TTYPE SEND` until the remote end repeats a terminal type it
+  /// already reported. At that point a single `TelnetEvents::TerminalTypes` event is emitted with every type seen.
+  ///
+  /// While a cycle is in progress the individual `TTYPE IS` responses are consumed by the parser and are not
+  /// emitted as `TelnetEvents::Subnegotiation` events.
+  pub fn request_terminal_types(&mut self) -> Option<TelnetEvents> {
+    match self.options.get_option(telnet::op_option::TTYPE) {
+      CompatibilityEntry {
+        remote_state: true, ..
+      } => {
+        self.terminal_types = Some(Vec::new());
+        Some(Self::terminal_type_send())
+      }
+      _ => None,
+    }
+  }
+
+  /// Set the terminal types reported to the remote end, in order, or stop answering with an empty list.
+  ///
+  /// # Arguments
+  ///
+  /// `types` - The terminal types. Example: `["BLIGHTMUD", "XTERM-256COLOR", "MTTS 2831"]`
+  ///
+  /// # Notes
+  ///
+  /// This is intended for use when acting as a client. While TTYPE is enabled locally, each `TTYPE SEND` is
+  /// answered with the next type in the list, as the MTTS handshake expects. Following RFC 1091, the last type is
+  /// repeated once to mark the end of the list, after which the cycle starts over. The cycle also starts over when
+  /// TTYPE is enabled again. Answered `TTYPE SEND` requests are consumed by the parser and are not emitted as
+  /// `TelnetEvents::Subnegotiation` events.
+  pub fn set_terminal_types(&mut self, types: &[&str]) {
+    self.local_terminal_types = types.iter().map(|&name| String::from(name)).collect();
+    self.terminal_type_index = 0;
+  }
+
+  /// Ask the remote end for some of its environment variables, using NEW-ENVIRON.
+  ///
+  /// # Arguments
+  ///
+  /// * `names` - The variables to request. An empty name requests every variable of its kind, and an empty slice
+  ///   requests every variable.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event containing a `NEW-ENVIRON SEND` subnegotiation, or None
+  /// if the remote end has not enabled the NEW-ENVIRON option.
+  ///
+  /// # Notes
+  ///
+  /// This is intended for use when acting as a server. Once called, the `IS` and `INFO` subnegotiations the remote
+  /// end sends are emitted as `TelnetEvents::Subnegotiation` events, though the option is only enabled remotely.
+  /// Decode them with `newenviron::EnvironMessage::decode`.
+  pub fn request_environ(
+    &mut self,
+    names: &[(newenviron::VarKind, &[u8])],
+  ) -> Option<TelnetEvents> {
+    if !self
+      .options
+      .get_option(telnet::op_option::NEWENVIRON)
+      .remote_state
+    {
+      return None;
+    }
+    self.environ_requested = true;
+    let names = names
+      .iter()
+      .map(|&(kind, name)| (kind, Bytes::copy_from_slice(name)))
+      .collect();
+    let payload = newenviron::EnvironMessage::Send(names).to_bytes();
+    Some(TelnetEvents::build_send(
+      TelnetSubnegotiation::new(telnet::op_option::NEWENVIRON, payload).to_bytes(),
+    ))
+  }
+
+  fn terminal_type_send() -> TelnetEvents {
+    TelnetEvents::build_send(vbytes!(&[IAC, SB, telnet::op_option::TTYPE, SEND, IAC, SE]))
+  }
+
+  /// Answer a `TTYPE SEND` with the next terminal type set with `set_terminal_types`.
+  fn next_terminal_type(&mut self) -> Option<TelnetEvents> {
+    let count = self.local_terminal_types.len();
+    let name = self
+      .local_terminal_types
+      .get(self.terminal_type_index.min(count.checked_sub(1)?))?;
+    let payload = [&[IS][..], name.as_bytes()].concat();
+    self.terminal_type_index = if self.terminal_type_index < count {
+      self.terminal_type_index + 1
+    } else {
+      0
+    };
+    Some(TelnetEvents::build_send(
+      TelnetSubnegotiation::new(telnet::op_option::TTYPE, payload.into()).to_bytes(),
+    ))
+  }
+
+  /// Record a `TTYPE IS` response for an in-progress terminal type cycle.
+  fn process_terminal_type(&mut self, data: &[u8]) -> Option<TelnetEvents> {
+    let name = match data.split_first() {
+      Some((&IS, name)) => {
+        String::from_utf8_lossy(&Parser::unescape_iac(vbytes!(name))).into_owned()
+      }
+      _ => return None,
+    };
+    let types = self.terminal_types.as_mut()?;
+    if types.contains(&name) {
+      return self.terminal_types.take().map(TelnetEvents::TerminalTypes);
+    }
+    types.push(name);
+    Some(Self::terminal_type_send())
+  }
+
+  /// Set whether received bytes bypass Telnet interpretation.
+  ///
+  /// # Arguments
+  ///
+  /// * `passthrough` - While true, every received byte is emitted untouched in `DataReceive` events, with no IAC
+  ///   handling or unescaping, until passthrough is disabled again.
+  ///
+  /// # Notes
+  ///
+  /// This is intended for binary streams embedded in the connection, such as file transfers, once they've been
+  /// negotiated. Bytes already buffered but not yet parsed, like an incomplete subnegotiation, are affected too.
+  pub fn set_passthrough(&mut self, passthrough: bool) {
+    self.passthrough = passthrough;
+  }
+
+  /// Whether received bytes currently bypass Telnet interpretation. See `set_passthrough`.
+  #[must_use]
+  pub fn passthrough(&self) -> bool {
+    self.passthrough
+  }
+
+  /// Set whether received data is checked for the start of an in-band file transfer. Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// When a transfer is detected, a `TelnetEvents::FileTransferDetected` event is emitted and passthrough mode is
+  /// enabled, so the transfer reaches the caller untouched. Call `set_passthrough(false)` once the transfer is over.
+  ///
+  /// Data preceding the transfer is emitted as usual before the event. The `DataReceive` event following it starts
+  /// with the complete transfer signature, even when part of the signature arrived in an earlier read and was
+  /// already emitted as ordinary data.
+  pub fn set_transfer_detection(&mut self, enabled: bool) {
+    self.transfer_detector = enabled.then(TransferDetector::default);
+  }
+
+  /// Set whether the raw bytes each event was parsed from are kept, to be returned by `next_event_with_raw`.
+  /// Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// The raw bytes are zero-copy slices of the received data, after any decryption and before any unescaping.
+  /// Bytes that produce no event, such as traffic left out by the `KeepaliveFilter`, aren't returned. A `Record`
+  /// event's raw bytes are only its terminating `IAC EOR`.
+  pub fn set_raw_capture(&mut self, enabled: bool) {
+    self.raw_capture = enabled;
+  }
+
+  /// Set whether `TelnetEvents::ProtocolError` events are emitted for malformed input from the remote end.
+  /// Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// The parser recovers from these errors either way, they're only reported for logging and debugging.
+  pub fn set_diagnostics(&mut self, enabled: bool) {
+    self.diagnostics = enabled;
+  }
+
+  /// Set whether a subnegotiation for an option that isn't enabled in either direction is answered with a
+  /// refusal. Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// Some servers skip negotiation and start sending subnegotiations straight away. While enabled, the parser
+  /// answers these with `IAC WONT` and `IAC DONT` for the option, to make the server stop, and emits a
+  /// `ProtocolError::UnexpectedSubnegotiation` event naming it. The refusals are subject to
+  /// `set_reply_suppression`, so a server repeating the subnegotiation isn't answered every time.
+  pub fn set_refuse_unexpected_subnegotiations(&mut self, enabled: bool) {
+    self.refuse_unexpected_subnegotiations = enabled;
+  }
+
+  /// Set whether `IAC DO ECHO` is always refused, whatever the `CompatibilityTable` says. Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// Clients should never echo what the server sends. Enabling this when acting as a client keeps a table that
+  /// mistakenly supports ECHO locally from agreeing to echo, which would send everything the server sends straight
+  /// back to it. Leave it disabled when acting as a server, which is the end that echoes.
+  pub fn set_refuse_local_echo(&mut self, enabled: bool) {
+    self.refuse_local_echo = enabled;
+  }
+
+  /// Set the longest subnegotiation payload sent with `subnegotiation`, in bytes, and what to do with longer ones.
+  /// None, for no limit, by default.
+  ///
+  /// # Arguments
+  ///
+  /// `limit` - The longest payload to send, before IAC escaping.
+  ///
+  /// `policy` - Whether a longer payload is refused, or split into several subnegotiations where the option allows.
+  ///
+  /// # Notes
+  ///
+  /// Some clients drop or truncate very large subnegotiations, such as a GMCP message listing a whole room. The
+  /// limit reports them as `SubnegotiationRefused::TooLong` from `try_subnegotiation`, instead of producing a
+  /// single huge `DataSend` event.
+  pub fn set_outgoing_subnegotiation_limit(
+    &mut self,
+    limit: Option<usize>,
+    policy: OversizedSubnegotiation,
+  ) {
+    self.outgoing_subnegotiation_limit = limit;
+    self.oversized_subnegotiations = policy;
+  }
+
+  /// Set the longest subnegotiation accepted from the remote end, in bytes. None, for no limit, by default.
+  ///
+  /// # Notes
+  ///
+  /// The limit counts every byte from `IAC SB` to `IAC SE`, including IAC escaping. A longer subnegotiation is
+  /// discarded, and a `ProtocolError::SubnegotiationTooLong` event naming the option is emitted instead. Once an
+  /// unterminated subnegotiation passes the limit, only its first few bytes are kept buffered while waiting for its
+  /// `IAC SE`, so a remote end that never terminates one can't grow the internal buffer without bound.
+  pub fn set_subnegotiation_limit(&mut self, limit: Option<usize>) {
+    self.subnegotiation_limit = limit;
+  }
+
+  /// Set the channel MSDP variables are expected on when both MSDP and GMCP are enabled. None by default.
+  ///
+  /// # Notes
+  ///
+  /// While set and both options are enabled, MSDP received on the other channel is dropped: MSDP subnegotiations
+  /// when preferring `MsdpChannel::Gmcp`, and GMCP messages of the `MSDP` package when preferring
+  /// `MsdpChannel::Msdp`. This suppresses the duplicate stream from a remote end that sends both. When None, both
+  /// are emitted. See `msdp_channel` for the channel to send MSDP variables on.
+  pub fn set_msdp_preference(&mut self, preference: Option<MsdpChannel>) {
+    self.msdp_preference = preference;
+  }
+
+  /// The channel to send MSDP variables on, given the enabled options and the preference set with
+  /// `set_msdp_preference`.
+  ///
+  /// # Returns
+  ///
+  /// `Option<MsdpChannel>` - `MsdpChannel::Msdp` if only MSDP is enabled, `MsdpChannel::Gmcp` if only GMCP is
+  /// enabled, the preference (or `MsdpChannel::Msdp` if none is set) if both are, or None if neither is.
+  #[must_use]
+  pub fn msdp_channel(&self) -> Option<MsdpChannel> {
+    let enabled = |option| {
+      let entry = self.options.get_option(option);
+      entry.local_state || entry.remote_state
+    };
+    MsdpChannel::choose(
+      enabled(telnet::op_option::MSDP),
+      enabled(telnet::op_option::GMCP),
+      self.msdp_preference.unwrap_or(MsdpChannel::Msdp),
+    )
+  }
+
+  /// Set how `NUL` bytes in received data are treated. See `NulPolicy`.
+  ///
+  /// # Notes
+  ///
+  /// The policy doesn't apply to subnegotiation payloads, or to data received in passthrough mode.
+  pub fn set_nul_policy(&mut self, policy: NulPolicy) {
+    self.nul_policy = policy;
+  }
+
+  /// Install the cipher used to decrypt received data once the remote end starts encrypting with the ENCRYPT
+  /// option. See the `encrypt` module.
+  ///
+  /// # Arguments
+  ///
+  /// * `cipher` - The cipher for the encryption type negotiated with the remote end, or None to remove it.
+  ///
+  /// # Notes
+  ///
+  /// Without a cipher, `START` and `END` subnegotiations are passed on as usual, and received data is never
+  /// decrypted. Removing the cipher also ends any decryption in progress.
+  pub fn set_cipher(&mut self, cipher: Option<Box<dyn Cipher>>) {
+    if cipher.is_none() {
+      self.decrypting = false;
+    }
+    self.cipher = cipher;
+  }
+
+  /// Whether received data is currently being decrypted. See `set_cipher`.
+  #[must_use]
+  pub fn decrypting(&self) -> bool {
+    self.decrypting
+  }
+
+  /// Set whether the parser watches for a 3270 session being negotiated. Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// While enabled, a `TelnetEvents::Tn3270` event is emitted when the remote end reports an `IBM-3278` or
+  /// `IBM-3279` terminal type, and when the TN3270E option is enabled or disabled. See the `tn3270` module.
+  pub fn set_tn3270_detection(&mut self, enabled: bool) {
+    self.tn3270_detection = enabled;
+  }
+
+  /// The kind of session detected. Always `Tn3270Mode::Nvt` unless enabled with `set_tn3270_detection`.
+  #[must_use]
+  pub fn tn3270_mode(&self) -> Tn3270Mode {
+    self.tn3270_mode
+  }
+
+  /// Set whether received data is framed into records terminated by `IAC EOR`, as used by 3270 data streams.
+  ///
+  /// # Notes
+  ///
+  /// While enabled, received data is collected, with IAC escaping removed, until an `IAC EOR` arrives. The whole
+  /// record is then emitted in a `TelnetEvents::Record` event, in place of `DataReceive` events and the `EOR`
+  /// command. Other commands and negotiations are emitted as usual. The keepalive filter, `NUL` policy and file
+  /// transfer detection don't apply to records.
+  ///
+  /// Disabling record mode emits an incomplete record as a `DataReceive` event, with the next events.
+  pub fn set_record_mode(&mut self, enabled: bool) {
+    match (enabled, self.record.take()) {
+      (true, record) => self.record = Some(record.unwrap_or_default()),
+      (false, Some(record)) if !record.is_empty() => {
+        self.emit(TelnetEvents::DataReceive(record.freeze()));
+      }
+      (false, _) => {}
+    }
+  }
+
+  /// Set whether output sent with `send_output` follows the half-duplex `GA` discipline of early telnet services.
+  /// Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// While enabled, each `send_output` ends with `IAC GA`, handing the turn to the remote end. Output sent before
+  /// the remote end answers with its own `IAC GA` is queued, and emitted as a single `DataSend` event, again ending
+  /// with `IAC GA`, when the answer is received. `flush_output` sends the queued output straight away.
+  ///
+  /// This is intended for use when acting as a server, with SUPPRESS-GO-AHEAD disabled. Disabling half-duplex mode
+  /// emits any queued output as a `DataSend` event, with the next events.
+  pub fn set_half_duplex(&mut self, enabled: bool) {
+    match (enabled, self.half_duplex.take()) {
+      (true, queue) => self.half_duplex = Some(queue.unwrap_or_default()),
+      (false, Some(queue)) if !queue.is_empty() => {
+        self.emit(TelnetEvents::DataSend(queue.freeze()));
+      }
+      (false, _) => {}
+    }
+    self.remote_turn = false;
+  }
+
+  /// The number of bytes of output queued by `send_output`, waiting for the remote end's `IAC GA`.
+  #[must_use]
+  pub fn queued_output(&self) -> usize {
+    self.half_duplex.as_ref().map_or(0, BytesMut::len)
+  }
+
+  /// A read-only view of the `CompatibilityTable` that can be shared with other threads.
+  ///
+  /// # Notes
+  ///
+  /// Every call returns a handle to the same view. The parser updates it whenever it changes an option, such as when
+  /// negotiating. Changes made directly to the `options` field are published with the parser's next change, or by
+  /// calling `shared_options` again.
+  ///
+  /// Only available on targets with atomic pointer operations.
+  #[cfg(target_has_atomic = "ptr")]
+  pub fn shared_options(&mut self) -> SharedTable {
+    let options = &self.options;
+    let shared = self
+      .shared_options
+      .get_or_insert_with(|| SharedTable::new(options));
+    shared.publish(&self.options);
+    shared.clone()
+  }
+
+  /// Set whether a `TelnetEvents::OptionStateChanged` event is emitted whenever received data changes the
+  /// `CompatibilityTable`. Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// This gives a single place to react to options being enabled or disabled, instead of working it out from
+  /// `Negotiation` events, which are also emitted for negotiations that don't change anything. Changes made by
+  /// calling the parser's own methods, such as `_will`, aren't reported, since the caller already knows of them.
+  pub fn set_option_change_events(&mut self, enabled: bool) {
+    self.option_change_events = enabled;
+  }
+
+  /// Set whether offers made with `_will` and `_do` are tracked, so that a `TelnetEvents::NegotiationComplete`
+  /// event is emitted when the remote end answers one. Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// Offers made through other methods, e.g. `offer_all` or `request_option`, are tracked as well. Disabling
+  /// tracking forgets any unanswered offers.
+  pub fn set_offer_tracking(&mut self, enabled: bool) {
+    self.track_offers = enabled;
+    if !enabled {
+      self.offers.clear();
+    }
+  }
+
+  /// Set whether `IAC GA` and `IAC EOR` commands are left out of the emitted events. Disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// Both commands mark the end of a prompt. Display layers that don't use them can enable this instead of
+  /// filtering them out, so a stray command can't be rendered. The parser still acts on them: `IAC GA` hands the
+  /// turn back in half-duplex mode, and `IAC EOR` ends a record in record mode, which delivers prompts ending with
+  /// it as `TelnetEvents::Record` events.
+  pub fn set_strip_go_ahead(&mut self, enabled: bool) {
+    self.strip_go_ahead = enabled;
+  }
+
+  /// Set which keepalive traffic is left out of the emitted events. See `KeepaliveFilter`.
+  pub fn set_keepalive_filter(&mut self, filter: KeepaliveFilter) {
+    self.keepalive_filter = filter;
+  }
+
+  /// Suppress refusals (`DONT` and `WONT` replies) identical to one sent within `window`, or disable suppression
+  /// with None. It's disabled by default.
+  ///
+  /// # Notes
+  ///
+  /// This protects against remote ends that repeat the same negotiation, such as a server re-offering an
+  /// unsupported option with `WILL` in a loop, which would otherwise be answered with a `DONT` every time.
+  /// Suppressed replies aren't emitted at all, while the `Negotiation` events that accompany them still are. A
+  /// refusal is only suppressed while the option's state hasn't changed since the earlier one, and replies that
+  /// enable an option are never suppressed.
+  #[cfg(feature = "std")]
+  pub fn set_reply_suppression(&mut self, window: Option<std::time::Duration>) {
+    self.reply_window = window;
+    self.recent_replies.clear();
+  }
+
+  /// Set whether an option is included in the options returned by `prepare_reconnect`. All options are included by
+  /// default.
+  ///
+  /// # Notes
+  ///
+  /// Exclude options whose negotiation has side effects that shouldn't be repeated on a new connection.
+  pub fn set_carry_over(&mut self, option: u8, carry_over: bool) {
+    if carry_over {
+      self.no_carry_over.remove(&option);
+    } else {
+      self.no_carry_over.insert(option);
+    }
+  }
+
+  /// Check the `CompatibilityTable` and the parser's settings for misconfiguration.
+  ///
+  /// # Returns
+  ///
+  /// `ConformanceReport` - The problems found, such as conflicting settings or an option enabled without being
+  /// supported. See `conformance::ConformanceIssue`.
+  ///
+  /// # Notes
+  ///
+  /// This is intended to be run once the parser is configured, before the connection is opened. It only reads the
+  /// configuration, and produces no events.
+  #[must_use]
+  pub fn self_test(&self) -> ConformanceReport {
+    use telnet::op_option::{BINARY, ECHO, EOR, GMCP, MSDP, SGA, TTYPE};
+
+    let mut issues = Vec::new();
+    for option in 0..=u8::MAX {
+      let entry = self.options.get_option(option);
+      if entry.local_state && !entry.local {
+        issues.push(ConformanceIssue::EnabledWithoutSupport {
+          option,
+          direction: Direction::Local,
+        });
+      }
+      if entry.remote_state && !entry.remote {
+        issues.push(ConformanceIssue::EnabledWithoutSupport {
+          option,
+          direction: Direction::Remote,
+        });
+      }
+    }
+    let echo = self.options.get_option(ECHO);
+    let sga = self.options.get_option(SGA);
+    if echo.local && echo.remote {
+      issues.push(ConformanceIssue::EchoBothWays);
+    }
+    if echo.local && self.refuse_local_echo {
+      issues.push(ConformanceIssue::RefusedLocalEcho);
+    }
+    if echo.local && !sga.local {
+      issues.push(ConformanceIssue::EchoWithoutSga);
+    }
+    if self.half_duplex.is_some() && sga.local {
+      issues.push(ConformanceIssue::HalfDuplexWithSga);
+    }
+    if !self.local_terminal_types.is_empty() && !self.options.get_option(TTYPE).local {
+      issues.push(ConformanceIssue::TerminalTypesWithoutTtype);
+    }
+    let supported = |option| {
+      let entry = self.options.get_option(option);
+      entry.local || entry.remote
+    };
+    if self.msdp_preference.is_some() && !(supported(MSDP) && supported(GMCP)) {
+      issues.push(ConformanceIssue::UnusedMsdpPreference);
+    }
+    if let Some(limit) = self.subnegotiation_limit.filter(|&limit| limit < 5) {
+      issues.push(ConformanceIssue::SubnegotiationLimitTooSmall(limit));
+    }
+    let both = |option| {
+      let entry = self.options.get_option(option);
+      entry.local && entry.remote
+    };
+    if self.tn3270_detection && !(both(BINARY) && both(EOR)) {
+      issues.push(ConformanceIssue::Tn3270WithoutBinaryEor);
+    }
+    ConformanceReport { issues }
+  }
+
+  /// Reset the parser for a new connection to the same remote end.
+  ///
+  /// # Returns
+  ///
+  /// `EnabledOptions` - The options that were enabled, except those excluded with `set_carry_over`. Pass this to
+  /// `reoffer` once the new connection is open.
+  ///
+  /// # Notes
+  ///
+  /// All negotiated option states are reset, passthrough and decryption are disabled, and any partially received
+  /// data is discarded. Supported options, subnegotiation decoders, the cipher and other configuration are kept.
+  pub fn prepare_reconnect(&mut self) -> EnabledOptions {
+    let mut enabled = EnabledOptions::default();
+    for option in 0..=u8::MAX {
+      if self.no_carry_over.contains(&option) {
+        continue;
+      }
+      let entry = self.options.get_option(option);
+      if entry.local_state {
+        enabled.local.push(option);
+      }
+      if entry.remote_state {
+        enabled.remote.push(option);
+      }
+    }
+    self.options.reset_states();
+    self.publish_options();
+    self.buffer.clear();
+    self.pending.clear();
+    self.terminal_types = None;
+    self.terminal_type_index = 0;
+    self.environ_requested = false;
+    self.will_offers.clear();
+    self.truncated_subnegotiation = false;
+    self.local_gmcp.clear();
+    self.remote_gmcp.clear();
+    self.offers.clear();
+    self.offset = 0;
+    self.meta = EventMeta::default();
+    self.line_ended = true;
+    self.passthrough = false;
+    self.decrypting = false;
+    self.decrypted = 0;
+    self.tn3270_mode = Tn3270Mode::default();
+    if let Some(record) = &mut self.record {
+      record.clear();
+    }
+    if let Some(queue) = &mut self.half_duplex {
+      queue.clear();
+    }
+    self.remote_turn = false;
+    if let Some(detector) = &mut self.transfer_detector {
+      detector.reset();
+    }
+    #[cfg(feature = "std")]
+    {
+      self.last_activity.clear();
+      self.recent_replies.clear();
+    }
+    enabled
+  }
+
+  /// Offer options again after a reconnect.
+  ///
+  /// # Arguments
+  ///
+  /// * `options` - The options returned by `prepare_reconnect`.
+  ///
+  /// # Returns
+  ///
+  /// `Vec<TelnetEvents::DataSend>` - A `WILL` for each local option and a `DO` for each remote option, to be
+  /// processed.
+  pub fn reoffer(&mut self, options: &EnabledOptions) -> Vec<TelnetEvents> {
+    let local = options.local.iter().filter_map(|&opt| self._will(opt));
+    let mut events: Vec<TelnetEvents> = local.collect();
+    events.extend(options.remote.iter().filter_map(|&opt| self._do(opt)));
+    events
+  }
+
+  /// Offer several options at once, calling `_will` for each local option and `_do` for each remote option.
+  ///
+  /// # Arguments
+  ///
+  /// * `options` - The options to offer, and the direction each is offered in.
+  ///
+  /// # Returns
+  ///
+  /// `Vec<TelnetEvents::DataSend>` - The `DataSend` events produced, in order. Options that produced nothing are
+  /// skipped.
+  ///
+  /// # Notes
+  ///
+  /// The events can be concatenated into a single write, e.g. when setting up a connection.
+  pub fn offer_all(&mut self, options: &[(u8, Direction)]) -> Vec<TelnetEvents> {
+    options
+      .iter()
+      .filter_map(|&(option, direction)| match direction {
+        Direction::Local => self._will(option),
+        Direction::Remote => self._do(option),
+      })
+      .collect()
+  }
+
+  /// Refuse several options at once, calling `_wont` for each local option and `_dont` for each remote option.
+  ///
+  /// # Arguments
+  ///
+  /// * `options` - The options to refuse, and the direction each is refused in.
+  ///
+  /// # Returns
+  ///
+  /// `Vec<TelnetEvents::DataSend>` - The `DataSend` events produced, in order. Options that were already disabled
+  /// are skipped.
+  pub fn refuse_all(&mut self, options: &[(u8, Direction)]) -> Vec<TelnetEvents> {
+    options
+      .iter()
+      .filter_map(|&(option, direction)| match direction {
+        Direction::Local => self._wont(option),
+        Direction::Remote => self._dont(option),
+      })
+      .collect()
+  }
+
+  /// Start closing the connection politely, by disabling the enabled options and flushing output.
+  ///
+  /// # Returns
+  ///
+  /// `Vec<TelnetEvents>` - The events to process before closing the connection, ending with
+  /// `TelnetEvents::ShutdownComplete`: any output queued in half-duplex mode, `WONT` and `DONT` negotiations for
+  /// the options enabled in either direction, and a final `IAC GA` unless SUPPRESS-GO-AHEAD is enabled locally.
+  ///
+  /// # Notes
+  ///
+  /// MCCP2 and MCCP3 are left enabled, since compression ends with the connection. The options are marked as
+  /// disabled straight away, so further `send_*` and `subnegotiation` calls for them return None.
+  pub fn begin_shutdown(&mut self) -> Vec<TelnetEvents> {
+    let mut events: Vec<TelnetEvents> = self.flush_output().into_iter().collect();
+    let suppress_go_ahead = self.options.get_option(telnet::op_option::SGA).local_state;
+    let enabled: Vec<(u8, Direction)> = (0..=u8::MAX)
+      .filter(|&option| option != telnet::op_option::MCCP2 && option != telnet::op_option::MCCP3)
+      .flat_map(|option| {
+        let entry = self.options.get_option(option);
+        [
+          entry.local_state.then_some((option, Direction::Local)),
+          entry.remote_state.then_some((option, Direction::Remote)),
+        ]
+      })
+      .flatten()
+      .collect();
+    events.extend(self.refuse_all(&enabled));
+    if !suppress_go_ahead {
+      events.push(TelnetEvents::build_send(Bytes::from_static(&[IAC, GA])));
+    }
+    events.push(TelnetEvents::ShutdownComplete);
+    events
+  }
+
   /// Directly send a string, with appended `\r\n`, to the remote end, along with an `IAC (255) GOAHEAD (249)` sequence.
   ///
   /// # Returns
@@ -344,8 +1848,51 @@ impl Parser {
     TelnetEvents::build_send(Parser::escape_iac(format!("{text}\r\n")))
   }
 
-  /// Extract sub-buffers from the current buffer
-  fn extract_event_data(&mut self) -> Vec<EventType> {
+  /// Send output to the remote end, following the half-duplex discipline if enabled with `set_half_duplex`.
+  ///
+  /// # Arguments
+  ///
+  /// `data` - A `&[u8]` of the output to send. IAC (255) byte values will be escaped.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the output was queued until
+  /// the remote end's `IAC GA` is received.
+  pub fn send_output(&mut self, data: &[u8]) -> Option<TelnetEvents> {
+    let data = Parser::escape_iac(Bytes::copy_from_slice(data));
+    let Some(queue) = &mut self.half_duplex else {
+      return Some(TelnetEvents::build_send(data));
+    };
+    queue.put(data);
+    if self.remote_turn {
+      None
+    } else {
+      self.flush_output()
+    }
+  }
+
+  /// Send the output queued by `send_output` without waiting for the remote end's `IAC GA`.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event with the queued output followed by `IAC GA`, or None if
+  /// nothing is queued.
+  pub fn flush_output(&mut self) -> Option<TelnetEvents> {
+    let queue = self
+      .half_duplex
+      .as_mut()
+      .filter(|queue| !queue.is_empty())?;
+    queue.put(&[IAC, GA][..]);
+    self.remote_turn = true;
+    Some(TelnetEvents::DataSend(queue.split().freeze()))
+  }
+
+  /// Split the next complete event's bytes off the front of the internal buffer.
+  ///
+  /// Returns `None` once the buffer is empty, or holds only the start of an IAC command. Other trailing bytes that
+  /// don't form a complete event are split off as well; an unterminated subnegotiation is put back into the buffer
+  /// by `process_event`.
+  fn next_event_data(&mut self) -> Option<EventType> {
     #[derive(Copy, Clone)]
     enum State {
       Normal,
@@ -356,120 +1903,502 @@ impl Parser {
       SubIac { opt: u8 },
     }
 
-    let mut events = Vec::with_capacity(4);
-    let mut iter_state = State::Normal;
-    let mut cmd_begin = 0;
+    let subnegotiation: EventCtor = |buf| EventType::SubNegotiation(buf, None);
+    let mut state = State::Normal;
+    let mut found: Option<(usize, EventCtor)> = None;
+    let mut compressed = false;
 
-    // Empty self.buffer into an immutable Bytes we can process.
-    // We'll create views of this buffer to pass to the events using 'buf.slice'.
-    // Splitting is O(1) and doesn't copy the data. Freezing is zero-cost. Taking a slice is O(1).
-    let buf = self.buffer.split().freeze();
-    for (index, &val) in buf.iter().enumerate() {
-      (iter_state, cmd_begin) = match (&iter_state, val) {
+    for index in 0..self.buffer.len() {
+      if self.decrypting && index >= self.decrypted {
+        // Decrypt as the bytes are scanned, so nothing following an END is decrypted.
+        if let Some(cipher) = &mut self.cipher {
+          cipher.decrypt(&mut self.buffer[index..=index]);
+        }
+        self.decrypted = index + 1;
+      }
+      let val = self.buffer[index];
+      state = match (state, val) {
         (State::Normal, IAC) => {
-          if cmd_begin != index {
-            events.push(EventType::None(buf.slice(cmd_begin..index)));
+          if index != 0 {
+            found = Some((index, EventType::None));
+            break;
           }
-          (State::Iac, index)
+          State::Iac
         }
-        (State::Iac, IAC) => (State::Normal, cmd_begin), // Double IAC, ignore,
-        (State::Iac, GA | EOR | NOP) => {
-          events.push(EventType::Iac(buf.slice(cmd_begin..=index)));
-          (State::Normal, index + 1)
+        (State::Iac, IAC) => State::Normal, // Double IAC, ignore,
+        (State::Iac, GA | EOR | NOP | SE | DM | BRK | IP | AO | AYT | EC | EL) => {
+          found = Some((index + 1, EventType::Iac));
+          break;
         }
-        (State::Iac, SB) => (State::Sub, cmd_begin),
-        (State::Iac, _) => (State::Neg, cmd_begin), // WILL | WONT | DO | DONT | IS | SEND
+        (State::Iac, SB) => State::Sub,
+        (State::Iac, _) => State::Neg, // WILL | WONT | DO | DONT | IS | SEND
         (State::Neg, _) => {
-          events.push(EventType::Neg(buf.slice(cmd_begin..=index)));
-          (State::Normal, index + 1)
-        }
-        (State::Sub, opt) => (State::SubOpt { opt }, cmd_begin),
-        (State::SubOpt { opt } | State::SubIac { opt }, IAC) => {
-          (State::SubIac { opt: *opt }, cmd_begin)
-        }
-        (State::SubIac { opt }, SE)
-          if *opt == telnet::op_option::MCCP2 || *opt == telnet::op_option::MCCP3 =>
-        {
-          // MCCP2/MCCP3 MUST DECOMPRESS DATA AFTER THIS!
-          events.push(EventType::SubNegotiation(
-            buf.slice(cmd_begin..=index),
-            Some(buf.slice(index + 1..)),
-          ));
-          cmd_begin = buf.len();
+          found = Some((index + 1, EventType::Neg));
           break;
         }
-        (State::SubIac { .. }, SE) => {
-          events.push(EventType::SubNegotiation(
-            buf.slice(cmd_begin..=index),
-            None,
-          ));
-          (State::Normal, index + 1)
+        // An IAC following IAC is escaped, and returns to the payload below.
+        (State::SubOpt { opt }, IAC) => State::SubIac { opt },
+        (State::SubIac { opt }, SE) => {
+          // MCCP2/MCCP3 MUST DECOMPRESS DATA AFTER THIS! Unless the option isn't enabled, in which case the
+          // subnegotiation is ignored and the data after it isn't compressed.
+          let entry = self.options.get_option(opt);
+          compressed = (opt == telnet::op_option::MCCP2 || opt == telnet::op_option::MCCP3)
+            && entry.local
+            && entry.local_state;
+          found = Some((index + 1, subnegotiation));
+          break;
         }
-        (State::SubIac { opt }, _) => (State::SubOpt { opt: *opt }, cmd_begin),
-        (cur_state, _) => (*cur_state, cmd_begin),
+        (State::Sub, opt) | (State::SubIac { opt }, _) => State::SubOpt { opt },
+        (cur_state, _) => cur_state,
       };
     }
 
-    if cmd_begin < buf.len() {
-      match iter_state {
+    let (end, kind): (usize, EventCtor) = match found {
+      Some(found) => found,
+      None if self.buffer.is_empty() => return None,
+      None => match state {
+        // A command split across reads. Leave it buffered until the rest arrives.
+        State::Iac | State::Neg => return None,
         State::Sub | State::SubOpt { .. } | State::SubIac { .. } => {
-          events.push(EventType::SubNegotiation(buf.slice(cmd_begin..), None));
+          (self.buffer.len(), subnegotiation)
         }
-        _ => events.push(EventType::None(buf.slice(cmd_begin..))),
-      }
+        State::Normal => (self.buffer.len(), EventType::None),
+      },
+    };
+
+    // Splitting is O(1) and doesn't copy the data. Freezing is zero-cost.
+    let data = self.buffer.split_to(end).freeze();
+    self.capture_raw(&data);
+    self.decrypted = self.decrypted.saturating_sub(end);
+    self.meta = EventMeta {
+      offset: self.offset,
+    };
+    self.offset += end as u64;
+    if compressed {
+      self.offset += self.buffer.len() as u64;
+      return Some(EventType::SubNegotiation(
+        data,
+        Some(self.buffer.split().freeze()),
+      ));
     }
+    Some(kind(data))
+  }
 
-    events
+  /// Split all of the buffered bytes off for passthrough mode.
+  fn next_passthrough_data(&mut self) -> Option<Bytes> {
+    if self.buffer.is_empty() {
+      return None;
+    }
+    if self.decrypting {
+      let decrypted = self.decrypted;
+      if let Some(cipher) = &mut self.cipher {
+        cipher.decrypt(&mut self.buffer[decrypted..]);
+      }
+      self.decrypted = 0;
+    }
+    let data = self.buffer.split().freeze();
+    self.meta = EventMeta {
+      offset: self.offset,
+    };
+    self.offset += data.len() as u64;
+    Some(data)
   }
 
-  /// The internal parser method that takes the current buffer and generates the corresponding events.
-  fn process(&mut self) -> Vec<TelnetEvents> {
-    let mut event_list = Vec::with_capacity(2);
-    let events = self.extract_event_data();
-    for event in events {
-      match event {
-        EventType::None(buffer) | EventType::Iac(buffer) | EventType::Neg(buffer) => {
-          match (buffer.first(), buffer.get(1), buffer.get(2)) {
-            (Some(&IAC), Some(command), None) if *command != SE => {
-              // IAC command
-              event_list.push(TelnetEvents::build_iac(*command));
+  /// Process the bytes of a single event, queueing any resulting `TelnetEvents`.
+  ///
+  /// Returns false if the event was an incomplete subnegotiation that was put back into the buffer to wait for
+  /// more data.
+  fn process_event(&mut self, event: EventType) -> bool {
+    match event {
+      EventType::None(buffer) | EventType::Iac(buffer) | EventType::Neg(buffer) => {
+        match (buffer.first(), buffer.get(1), buffer.get(2)) {
+          (Some(&first), second, _)
+            if self.record.is_some() && (first != IAC || second == Some(&IAC)) =>
+          {
+            // Data belonging to a record.
+            let data = Parser::unescape_iac(buffer);
+            if let Some(record) = &mut self.record {
+              record.put(data);
+            }
+          }
+          (Some(&IAC), Some(&EOR), None) if self.record.is_some() => {
+            // End of a record.
+            if let Some(record) = self.record.as_mut().map(|record| record.split().freeze()) {
+              self.emit(TelnetEvents::Record(record));
+            }
+          }
+          (Some(&IAC), Some(&IAC), _) => {
+            // Data starting with an escaped IAC byte.
+            self.emit_data(buffer);
+          }
+          (Some(&IAC), Some(&SE), None) if self.diagnostics => {
+            // SE without a preceding SB, which is skipped unless diagnostics are enabled.
+            self.emit(TelnetEvents::ProtocolError(ProtocolError::UnexpectedSe));
+          }
+          (Some(&IAC), Some(&NOP), None) if self.keepalive_filter.nop => {}
+          (Some(&IAC), Some(command), None) if *command != SE => {
+            // IAC command
+            if !(matches!(*command, GA | EOR) && self.strip_go_ahead) {
+              self.emit(TelnetEvents::build_iac(*command));
             }
-            (Some(&IAC), Some(command), Some(opt)) => {
-              // Negotiation command
-              event_list.extend(self.process_negotiation(*command, *opt));
+            if *command == GA && self.remote_turn {
+              // The remote end hands the turn back in half-duplex mode.
+              self.remote_turn = false;
+              if let Some(event) = self.flush_output() {
+                self.emit(event);
+              }
+            }
+          }
+          (Some(&IAC), Some(command), Some(opt)) => {
+            // Negotiation command
+            self.process_negotiation(*command, *opt);
+            if *opt == telnet::op_option::TN3270E {
+              let entry = self.options.get_option(*opt);
+              if entry.local_state || entry.remote_state {
+                self.set_tn3270_mode(Tn3270Mode::Tn3270E);
+              } else if self.tn3270_mode == Tn3270Mode::Tn3270E {
+                self.set_tn3270_mode(Tn3270Mode::Nvt);
+              }
             }
-            (Some(c), _, _) if *c != IAC => {
-              // Not an iac sequence, it's data!
-              event_list.push(TelnetEvents::build_receive(buffer));
+          }
+          (Some(c), _, _) if *c != IAC => {
+            // Not an iac sequence, it's data!
+            // Line endings that terminate a line of earlier data aren't an empty line.
+            let empty_line =
+              self.line_ended && buffer.iter().all(|b| matches!(b, b'\r' | b'\n' | 0));
+            if !(empty_line && self.keepalive_filter.empty_lines) {
+              self.emit_data(buffer);
             }
-            _ => {}
           }
+          _ => {}
         }
-        EventType::SubNegotiation(buffer, remaining) => {
-          let len = buffer.len();
-          if buffer[len - 2] == IAC && buffer[len - 1] == SE {
-            // Valid ending
-            let opt = self.options.get_option(buffer[2]);
-            if opt.local && opt.local_state && len - 2 >= 3 {
-              event_list.push(TelnetEvents::build_subnegotiation(
-                buffer[2],
-                vbytes!(&buffer[3..len - 2]),
-              ));
-              if let Some(rbuf) = remaining {
-                event_list.push(TelnetEvents::DecompressImmediate(rbuf));
-              }
+      }
+      EventType::SubNegotiation(buffer, remaining) => {
+        let len = buffer.len();
+        // `IAC SB IAC SE` is the start of a subnegotiation for option 255, not a complete one, and the SE of an
+        // escaped `IAC IAC SE` is part of the payload.
+        let escaped_iacs = buffer
+          .get(3..len.saturating_sub(1))
+          .unwrap_or_default()
+          .iter()
+          .rev()
+          .take_while(|&&b| b == IAC)
+          .count();
+        if len >= 5 && buffer[len - 1] == SE && escaped_iacs % 2 == 1 {
+          if self.over_subnegotiation_limit(&buffer) {
+            self.truncated_subnegotiation = false;
+            // Only the payload is dropped. Data following the start of compression must still be decompressed.
+            if let Some(rbuf) = remaining {
+              self.emit_decompress_immediate(len, rbuf);
             }
           } else {
-            // Missing the rest
-            self.buffer.put(&buffer[..]);
+            self.process_subnegotiation(&buffer, remaining);
           }
+        } else {
+          // Missing the rest
+          let kept = if self.over_subnegotiation_limit(&buffer) {
+            // Keep the header, and a trailing IAC that may start the IAC SE.
+            let trailing_iacs = buffer[3..].iter().rev().take_while(|&&b| b == IAC).count();
+            let mut kept = BytesMut::from(&buffer[..3]);
+            if trailing_iacs % 2 == 1 {
+              kept.put_u8(IAC);
+            }
+            kept.freeze()
+          } else {
+            buffer
+          };
+          self.feed(&kept);
+          self.offset -= kept.len() as u64;
+          self.decrypted = kept.len();
+          return false;
         }
       }
     }
-    event_list
+    true
   }
 
-  fn process_negotiation(&mut self, command: u8, opt: u8) -> Vec<TelnetEvents> {
+  /// Split an MSDP or MSSP payload between top level variables into parts no longer than `limit`.
+  ///
+  /// # Returns
+  ///
+  /// `Option<Vec<Bytes>>` - The parts, or None if the option has no way of splitting its payloads, the payload
+  /// doesn't start with a variable, or a single variable is over the limit.
+  fn split_subnegotiation(option: u8, data: &Bytes, limit: usize) -> Option<Vec<Bytes>> {
+    use telnet::msdp;
+    if !matches!(option, telnet::op_option::MSDP | telnet::op_option::MSSP)
+      || data.first() != Some(&msdp::VAR)
+    {
+      return None;
+    }
+    // MSSP shares the VAR and VAL markers of MSDP, and has no tables or arrays.
+    let mut starts = Vec::new();
+    let mut depth = 0_usize;
+    for (index, &byte) in data.iter().enumerate() {
+      match byte {
+        msdp::VAR if depth == 0 => starts.push(index),
+        msdp::TABLE_OPEN | msdp::ARRAY_OPEN if option == telnet::op_option::MSDP => depth += 1,
+        msdp::TABLE_CLOSE | msdp::ARRAY_CLOSE if option == telnet::op_option::MSDP => {
+          depth = depth.saturating_sub(1);
+        }
+        _ => {}
+      }
+    }
+    starts.push(data.len());
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for window in starts.windows(2) {
+      let (variable, end) = (window[0], window[1]);
+      if end - variable > limit {
+        return None;
+      }
+      if end - start > limit {
+        parts.push(data.slice(start..variable));
+        start = variable;
+      }
+    }
+    parts.push(data.slice(start..));
+    Some(parts)
+  }
+
+  /// Change an option's entry, publishing the change to the shared view if there is one.
+  fn set_option(&mut self, option: u8, entry: CompatibilityEntry) {
+    self.options.set_option(option, entry);
+    #[cfg(feature = "std")]
+    self.recent_replies.retain(|&(_, opt), _| opt != option);
+    self.publish_options();
+  }
+
+  #[cfg_attr(not(target_has_atomic = "ptr"), allow(clippy::unused_self))]
+  fn publish_options(&self) {
+    #[cfg(target_has_atomic = "ptr")]
+    if let Some(shared) = &self.shared_options {
+      shared.publish(&self.options);
+    }
+  }
+
+  /// Whether a subnegotiation, complete or not, is over the limit set with `set_subnegotiation_limit`. The first
+  /// time it is, a `ProtocolError::SubnegotiationTooLong` event is emitted.
+  fn over_subnegotiation_limit(&mut self, buffer: &[u8]) -> bool {
+    if self.truncated_subnegotiation {
+      return true;
+    }
+    let over = buffer.len() >= 3
+      && self
+        .subnegotiation_limit
+        .is_some_and(|limit| buffer.len() > limit);
+    if over {
+      self.truncated_subnegotiation = true;
+      self.emit(TelnetEvents::ProtocolError(
+        ProtocolError::SubnegotiationTooLong(buffer[2]),
+      ));
+    }
+    over
+  }
+
+  /// Process a complete subnegotiation, from `IAC SB` to `IAC SE`.
+  fn process_subnegotiation(&mut self, buffer: &Bytes, remaining: Option<Bytes>) {
+    let len = buffer.len();
+    #[cfg(feature = "std")]
+    self.record_activity(buffer[2]);
+    *self.subnegotiation_bytes.entry(buffer[2]).or_insert(0) += len as u64;
+    let terminal_3270 = buffer[2] == telnet::op_option::TTYPE
+      && buffer[3..len - 2].starts_with(&[IS])
+      && tn3270::is_3270_terminal(&buffer[4..len - 2]);
+    if terminal_3270 && self.tn3270_mode == Tn3270Mode::Nvt {
+      self.set_tn3270_mode(Tn3270Mode::Tn3270);
+    }
+    if buffer[2] == telnet::op_option::TTYPE && self.terminal_types.is_some() {
+      // Anything other than an IS reply is processed as usual.
+      if let Some(event) = self.process_terminal_type(&buffer[3..len - 2]) {
+        self.emit(event);
+        return;
+      }
+    }
+    if buffer[2] == telnet::op_option::TTYPE
+      && buffer[3..len - 2] == [SEND]
+      && self
+        .options
+        .get_option(telnet::op_option::TTYPE)
+        .local_state
+    {
+      if let Some(event) = self.next_terminal_type() {
+        self.emit(event);
+        return;
+      }
+    }
+    let switch = self.decryption_switch(buffer);
+    let opt = self.options.get_option(buffer[2]);
+    if buffer[2] == telnet::op_option::GMCP && (opt.local_state || opt.remote_state) {
+      let payload = Parser::unescape_iac(buffer.slice(3..len - 2));
+      if let Some(message) = gmcp::GmcpMessage::parse(&payload) {
+        self.remote_gmcp.apply(&message);
+      }
+    }
+    if self.refuse_unexpected_subnegotiations && !opt.local_state && !opt.remote_state {
+      self.emit_reply(&[IAC, WONT, buffer[2]]);
+      self.emit_reply(&[IAC, DONT, buffer[2]]);
+      self.emit(TelnetEvents::ProtocolError(
+        ProtocolError::UnexpectedSubnegotiation(buffer[2]),
+      ));
+    }
+    let environ_answer = self.environ_requested
+      && buffer[2] == telnet::op_option::NEWENVIRON
+      && opt.remote_state
+      && matches!(buffer.get(3), Some(&newenviron::IS | &newenviron::INFO));
+    if (opt.local && opt.local_state || environ_answer) && len - 2 >= 3 {
+      let mut payload = buffer.slice(3..len - 2);
+      if self.decode_policies.get(&buffer[2]) == Some(&DecodePolicy::Unescape) {
+        payload = Parser::unescape_iac(payload);
+      }
+      if !self.duplicate_msdp(buffer[2], &payload) {
+        let decoded = match self.decoders.get(&buffer[2]) {
+          Some(Decoder::Optional(decoder)) => decoder(&payload),
+          Some(Decoder::Fallible(decoder)) => Some(decoder(&payload).unwrap_or_else(|error| {
+            TelnetEvents::OptionDecodeError(Box::new(OptionDecodeError {
+              option: buffer[2],
+              error,
+              raw: payload.clone(),
+            }))
+          })),
+          None => None,
+        };
+        self
+          .emit(decoded.unwrap_or_else(|| TelnetEvents::build_subnegotiation(buffer[2], payload)));
+      }
+      if let Some(rbuf) = remaining {
+        self.emit_decompress_immediate(len, rbuf);
+      }
+    }
+    if let Some(decrypting) = switch {
+      self.decrypting = decrypting;
+      self.decrypted = 0;
+      self.emit(TelnetEvents::DecryptionChanged(decrypting));
+    }
+  }
+
+  /// Emit the data following a subnegotiation of `len` bytes that started compression.
+  fn emit_decompress_immediate(&mut self, len: usize, rbuf: Bytes) {
+    let meta = EventMeta {
+      offset: self.meta.offset + len as u64,
+    };
+    self.capture_raw(&rbuf);
+    self.emit_with_meta(meta, TelnetEvents::DecompressImmediate(rbuf));
+  }
+
+  /// Switch to a new TN3270 mode, if detection is enabled and the mode changed.
+  fn set_tn3270_mode(&mut self, mode: Tn3270Mode) {
+    if self.tn3270_detection && self.tn3270_mode != mode {
+      self.tn3270_mode = mode;
+      self.emit(TelnetEvents::Tn3270(mode));
+    }
+  }
+
+  /// Whether a subnegotiation payload carries MSDP on the channel not preferred with `set_msdp_preference`.
+  fn duplicate_msdp(&self, option: u8, payload: &Bytes) -> bool {
+    let Some(preference) = self.msdp_preference else {
+      return false;
+    };
+    let channel = match option {
+      telnet::op_option::MSDP => MsdpChannel::Msdp,
+      telnet::op_option::GMCP
+        if gmcp::GmcpMessage::parse(payload)
+          .is_some_and(|message| msdp::is_msdp_over_gmcp(&message)) =>
+      {
+        MsdpChannel::Gmcp
+      }
+      _ => return false,
+    };
+    channel != preference && self.msdp_channel() == Some(preference)
+  }
+
+  /// Whether a complete subnegotiation switches decryption on or off.
+  fn decryption_switch(&self, buffer: &[u8]) -> Option<bool> {
+    if self.cipher.is_none() || buffer[2] != telnet::op_option::ENCRYPT {
+      return None;
+    }
+    match (buffer.get(3), self.decrypting) {
+      (Some(&encrypt::START), false) => Some(true),
+      (Some(&encrypt::END), true) => Some(false),
+      _ => None,
+    }
+  }
+
+  /// Emit received data, switching to passthrough mode if it starts a file transfer.
+  fn emit_data(&mut self, data: Bytes) {
+    if let Some(&last) = data.last() {
+      self.line_ended = last == b'\n';
+    }
+    let detected = self
+      .transfer_detector
+      .as_mut()
+      .and_then(|detector| detector.scan(&data));
+    let Some((protocol, end)) = detected else {
+      self.emit_text(data);
+      return;
+    };
+    let start = end.saturating_sub(ZMODEM_SIGNATURE_LEN);
+    if start > 0 {
+      self.emit_text(data.slice(..start));
+    }
+    self.emit(TelnetEvents::FileTransferDetected(protocol));
+    self.passthrough = true;
+    let prefix = TransferDetector::carried_prefix(end);
+    let transfer = if prefix.is_empty() {
+      data.slice(start..)
+    } else {
+      let mut transfer = BytesMut::with_capacity(prefix.len() + data.len());
+      transfer.put(prefix);
+      transfer.put(&data[..]);
+      transfer.freeze()
+    };
+    self.emit(TelnetEvents::build_receive(transfer));
+  }
+
+  /// Emit received data that isn't part of a file transfer, applying the `NulPolicy`.
+  fn emit_text(&mut self, data: Bytes) {
+    if self.nul_policy == NulPolicy::Keep || !data.contains(&0) {
+      self.emit(TelnetEvents::build_receive(data));
+      return;
+    }
+    let stripped: Vec<u8> = data.iter().copied().filter(|&b| b != 0).collect();
+    if !stripped.is_empty() {
+      self.emit(TelnetEvents::build_receive(Bytes::from(stripped)));
+    }
+    if self.nul_policy == NulPolicy::Error {
+      self.emit(TelnetEvents::ProtocolError(ProtocolError::UnexpectedNul));
+    }
+  }
+
+  fn process_negotiation(&mut self, command: u8, opt: u8) {
+    #[cfg(feature = "std")]
+    self.record_activity(opt);
+    let before = self.options.get_option(opt);
+    self.reply_to_negotiation(command, opt);
+    let after = self.options.get_option(opt);
+    if opt == telnet::op_option::TTYPE && before.local_state != after.local_state {
+      self.terminal_type_index = 0;
+    }
+    if self.option_change_events && before != after {
+      self.emit(TelnetEvents::OptionStateChanged {
+        option: opt,
+        before,
+        after,
+      });
+    }
+    let direction = match command {
+      DO | DONT => Direction::Local,
+      _ => Direction::Remote,
+    };
+    if self.offers.remove(&(opt, direction)) {
+      self.emit(TelnetEvents::NegotiationComplete {
+        option: opt,
+        direction,
+        accepted: matches!(command, WILL | DO),
+      });
+    }
+  }
+
+  fn reply_to_negotiation(&mut self, command: u8, opt: u8) {
     let event = TelnetNegotiation::new(command, opt);
     match (command, self.options.get_option(opt)) {
       (
@@ -481,14 +2410,12 @@ impl Parser {
         },
       ) => {
         entry.remote_state = true;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, DO, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
+        self.set_option(opt, entry);
+        self.emit_reply(&[IAC, DO, opt]);
+        self.emit(TelnetEvents::Negotiation(event));
       }
       (WILL, CompatibilityEntry { remote: false, .. }) => {
-        vec![TelnetEvents::build_send(vbytes!(&[IAC, DONT, opt]))]
+        self.emit_reply(&[IAC, DONT, opt]);
       }
       (
         WONT,
@@ -497,11 +2424,17 @@ impl Parser {
         },
       ) => {
         entry.remote_state = false;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, DONT, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
+        self.set_option(opt, entry);
+        self.emit_reply(&[IAC, DONT, opt]);
+        self.emit(TelnetEvents::Negotiation(event));
+      }
+      (
+        DO,
+        CompatibilityEntry {
+          local_state: false, ..
+        },
+      ) if opt == telnet::op_option::ECHO && self.refuse_local_echo => {
+        self.emit_reply(&[IAC, WONT, opt]);
       }
       (
         DO,
@@ -512,12 +2445,31 @@ impl Parser {
         },
       ) => {
         entry.local_state = true;
-        entry.remote_state = true;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, WILL, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
+        self.set_option(opt, entry);
+        if opt == telnet::op_option::MXP {
+          // MXP starts once the server sends an empty subnegotiation.
+          self.emit_reply(&[IAC, WILL, opt, IAC, SB, opt, IAC, SE]);
+        } else {
+          self.emit_reply(&[IAC, WILL, opt]);
+        }
+        self.emit(TelnetEvents::Negotiation(event));
+      }
+      (
+        DO,
+        CompatibilityEntry {
+          local: true,
+          local_state: true,
+          ..
+        },
+      ) if self.will_offers.contains(&opt) => {
+        // Acknowledgement of an offer made with `_will`, which already enabled the option. A repeated DO changes
+        // nothing.
+        self.will_offers.remove(&opt);
+        if opt == telnet::op_option::MXP {
+          // MXP starts once the server sends an empty subnegotiation.
+          self.emit_reply(&[IAC, SB, opt, IAC, SE]);
+        }
+        self.emit(TelnetEvents::Negotiation(event));
       }
       (
         DO,
@@ -526,7 +2478,7 @@ impl Parser {
         }
         | CompatibilityEntry { local: false, .. },
       ) => {
-        vec![TelnetEvents::build_send(vbytes!(&[IAC, WONT, opt]))]
+        self.emit_reply(&[IAC, WONT, opt]);
       }
       (
         DONT,
@@ -535,16 +2487,16 @@ impl Parser {
         },
       ) => {
         entry.local_state = false;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, WONT, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
+        self.set_option(opt, entry);
+        self.will_offers.remove(&opt);
+        self.emit_reply(&[IAC, WONT, opt]);
+        self.emit(TelnetEvents::Negotiation(event));
       }
-      (DONT | WONT, CompatibilityEntry { .. }) => {
-        vec![TelnetEvents::Negotiation(event)]
+      // The option was already disabled.
+      (DONT | WONT, CompatibilityEntry { .. }) if !self.keepalive_filter.repeated_negotiations => {
+        self.emit(TelnetEvents::Negotiation(event));
       }
-      _ => Vec::default(),
+      _ => {}
     }
   }
 }
@@ -0,0 +1,312 @@
+//! Local line editing for LINEMODE ([RFC 1184]) clients.
+//!
+//! When the server enables LINEMODE with the `EDIT` mode bit set, the client is responsible for
+//! collecting a full line of input, applying the editing characters negotiated with `SLC`, and
+//! only then sending the line. [`LineEditor`] implements that state machine. Feed it the bytes
+//! typed by the user, and it produces the bytes to echo locally and the bytes to send.
+//!
+//! [RFC 1184]: https://www.rfc-editor.org/rfc/rfc1184
+
+use alloc::vec::Vec;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// LINEMODE `MODE` subnegotiation.
+pub const MODE: u8 = 1;
+/// LINEMODE `FORWARDMASK` subnegotiation.
+pub const FORWARDMASK: u8 = 2;
+/// LINEMODE `SLC` (Set Local Characters) subnegotiation.
+pub const SLC: u8 = 3;
+
+/// `MODE` bit: the client should do local line editing.
+pub const MODE_EDIT: u8 = 1;
+/// `MODE` bit: the client should trap signals locally.
+pub const MODE_TRAPSIG: u8 = 2;
+/// `MODE` bit: acknowledges a `MODE` change.
+pub const MODE_ACK: u8 = 4;
+/// `MODE` bit: the client should expand tabs.
+pub const MODE_SOFT_TAB: u8 = 8;
+/// `MODE` bit: the client should echo non-printable characters literally.
+pub const MODE_LIT_ECHO: u8 = 16;
+
+/// `SLC` function: erase the previous character.
+pub const SLC_EC: u8 = 10;
+/// `SLC` function: erase the whole line.
+pub const SLC_EL: u8 = 11;
+/// `SLC` function: erase the previous word.
+pub const SLC_EW: u8 = 12;
+/// `SLC` function: an additional character that forwards the line.
+pub const SLC_FORW1: u8 = 17;
+/// `SLC` function: a second additional character that forwards the line.
+pub const SLC_FORW2: u8 = 18;
+
+/// `SLC` modifier level: the function is not supported.
+pub const SLC_NOSUPPORT: u8 = 0;
+/// Mask for the level bits of an `SLC` modifier.
+pub const SLC_LEVELBITS: u8 = 3;
+
+/// Erases one character on a terminal: back, over-write with a space, back again.
+const ERASE: &[u8] = b"\x08 \x08";
+
+/// Output produced by the [`LineEditor`] in response to user input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EditEvent {
+  /// Bytes to display locally.
+  Echo(Bytes),
+  /// Bytes to transmit to the remote end. These have not been IAC escaped.
+  Send(Bytes),
+}
+
+/// A local line editing buffer driven by the negotiated LINEMODE state.
+#[derive(Clone, Debug)]
+pub struct LineEditor {
+  mode: u8,
+  echo: bool,
+  erase_char: Option<u8>,
+  erase_line: Option<u8>,
+  erase_word: Option<u8>,
+  forward: [Option<u8>; 2],
+  line: Vec<u8>,
+  last_cr: bool,
+}
+
+impl Default for LineEditor {
+  fn default() -> Self {
+    Self {
+      mode: 0,
+      echo: true,
+      erase_char: Some(0x7f),
+      erase_line: Some(0x15),
+      erase_word: Some(0x17),
+      forward: [None, None],
+      line: Vec::new(),
+      last_cr: false,
+    }
+  }
+}
+
+impl LineEditor {
+  /// Create an editor with local editing disabled and the common terminal editing characters:
+  /// DEL to erase a character, `^U` to erase the line, and `^W` to erase a word.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Whether local line editing is currently enabled by the negotiated `MODE`.
+  #[must_use]
+  pub fn editing(&self) -> bool {
+    self.mode & MODE_EDIT != 0
+  }
+
+  /// Set whether input should be echoed locally. This should be disabled while the remote end
+  /// has the ECHO option enabled.
+  pub fn set_echo(&mut self, echo: bool) {
+    self.echo = echo;
+  }
+
+  /// The partial line collected so far.
+  #[must_use]
+  pub fn line(&self) -> &[u8] {
+    &self.line
+  }
+
+  /// Apply a LINEMODE subnegotiation received from the server.
+  ///
+  /// # Returns
+  ///
+  /// `Option<Bytes>` - A LINEMODE subnegotiation payload to send in reply, if one is required. Pass this to
+  /// `Parser::subnegotiation` with the LINEMODE option.
+  pub fn handle_subnegotiation(&mut self, payload: &[u8]) -> Option<Bytes> {
+    match payload {
+      [MODE, mask] => {
+        if mask & MODE_ACK != 0 {
+          // A mode with the ACK bit set confirms an earlier change and must not be answered.
+          return None;
+        }
+        self.mode = *mask;
+        if !self.editing() {
+          self.line.clear();
+        }
+        Some(Bytes::copy_from_slice(&[MODE, mask | MODE_ACK]))
+      }
+      [SLC, triplets @ ..] => {
+        for triplet in triplets.chunks_exact(3) {
+          self.set_local_character(triplet[0], triplet[1], triplet[2]);
+        }
+        None
+      }
+      _ => None,
+    }
+  }
+
+  fn set_local_character(&mut self, function: u8, modifier: u8, value: u8) {
+    let value = match modifier & SLC_LEVELBITS {
+      SLC_NOSUPPORT => None,
+      _ => Some(value),
+    };
+    match function {
+      SLC_EC => self.erase_char = value,
+      SLC_EL => self.erase_line = value,
+      SLC_EW => self.erase_word = value,
+      SLC_FORW1 => self.forward[0] = value,
+      SLC_FORW2 => self.forward[1] = value,
+      _ => {}
+    }
+  }
+
+  /// Process bytes typed by the user.
+  ///
+  /// When local editing is disabled, input is sent immediately. Otherwise it's collected into a
+  /// line, which is sent with a `\r\n` terminator once the user presses enter, or with the
+  /// forwarding character itself if one of the `SLC` forwarding characters is typed.
+  pub fn input(&mut self, data: &[u8]) -> Vec<EditEvent> {
+    let mut events = Vec::new();
+    if !self.editing() {
+      if self.echo {
+        events.push(EditEvent::Echo(Bytes::copy_from_slice(data)));
+      }
+      events.push(EditEvent::Send(Bytes::copy_from_slice(data)));
+      return events;
+    }
+
+    let mut echo = BytesMut::new();
+    for &byte in data {
+      let last_cr = core::mem::replace(&mut self.last_cr, byte == b'\r');
+      if byte == b'\n' && last_cr {
+        continue;
+      }
+
+      if Some(byte) == self.erase_char {
+        if self.line.pop().is_some() {
+          echo.put(ERASE);
+        }
+      } else if Some(byte) == self.erase_line {
+        for _ in self.line.drain(..) {
+          echo.put(ERASE);
+        }
+      } else if Some(byte) == self.erase_word {
+        let trimmed = self.line.len()
+          - self
+            .line
+            .iter()
+            .rev()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+        let word_start = self.line[..trimmed]
+          .iter()
+          .rposition(u8::is_ascii_whitespace)
+          .map_or(0, |pos| pos + 1);
+        for _ in self.line.drain(word_start..) {
+          echo.put(ERASE);
+        }
+      } else if byte == b'\r' || byte == b'\n' {
+        echo.put(&b"\r\n"[..]);
+        self.line.extend_from_slice(b"\r\n");
+        self.flush_line(&mut events, &mut echo);
+      } else if self.forward.contains(&Some(byte)) {
+        self.line.push(byte);
+        self.flush_line(&mut events, &mut echo);
+      } else {
+        echo.put_u8(byte);
+        self.line.push(byte);
+      }
+    }
+
+    if self.echo && !echo.is_empty() {
+      events.push(EditEvent::Echo(echo.freeze()));
+    }
+    events
+  }
+
+  fn flush_line(&mut self, events: &mut Vec<EditEvent>, echo: &mut BytesMut) {
+    if self.echo && !echo.is_empty() {
+      events.push(EditEvent::Echo(echo.split().freeze()));
+    }
+    echo.clear();
+    events.push(EditEvent::Send(Bytes::from(core::mem::take(
+      &mut self.line,
+    ))));
+  }
+}
+
+#[cfg(test)]
+mod test_linemode {
+  use super::*;
+  use alloc::vec;
+
+  fn editor() -> LineEditor {
+    let mut editor = LineEditor::new();
+    assert_eq!(
+      editor.handle_subnegotiation(&[MODE, MODE_EDIT]),
+      Some(Bytes::from_static(&[MODE, MODE_EDIT | MODE_ACK]))
+    );
+    assert!(editor.editing());
+    editor
+  }
+
+  #[test]
+  fn test_passthrough_without_edit() {
+    let mut editor = LineEditor::new();
+    editor.set_echo(false);
+    assert_eq!(
+      editor.input(b"n"),
+      vec![EditEvent::Send(Bytes::from_static(b"n"))]
+    );
+  }
+
+  #[test]
+  fn test_line_editing() {
+    let mut editor = editor();
+    editor.set_echo(false);
+    assert_eq!(editor.input(b"look nrth\x7f\x7f\x7frth"), vec![]);
+    assert_eq!(editor.line(), b"look nrth");
+    assert_eq!(editor.input(b"\x17north\x15"), vec![]);
+    assert_eq!(editor.line(), b"");
+    assert_eq!(
+      editor.input(b"say hi\r\nsay bye\r"),
+      vec![
+        EditEvent::Send(Bytes::from_static(b"say hi\r\n")),
+        EditEvent::Send(Bytes::from_static(b"say bye\r\n")),
+      ]
+    );
+    // The \n completing the \r\n pair arrives in a separate read.
+    assert_eq!(editor.input(b"\n"), vec![]);
+  }
+
+  #[test]
+  fn test_slc_characters() {
+    let mut editor = editor();
+    editor.set_echo(false);
+    editor.handle_subnegotiation(&[
+      SLC,
+      SLC_EC,
+      2,
+      0x08,
+      SLC_EL,
+      SLC_NOSUPPORT,
+      0,
+      SLC_FORW1,
+      2,
+      b';',
+    ]);
+    assert_eq!(editor.input(b"ab\x08\x7f\x15"), vec![]);
+    assert_eq!(editor.line(), b"a\x7f\x15");
+    assert_eq!(
+      editor.input(b";"),
+      vec![EditEvent::Send(Bytes::from_static(b"a\x7f\x15;"))]
+    );
+  }
+
+  #[test]
+  fn test_echo() {
+    let mut editor = editor();
+    assert_eq!(
+      editor.input(b"ab\x7f\r"),
+      vec![
+        EditEvent::Echo(Bytes::from_static(b"ab\x08 \x08\r\n")),
+        EditEvent::Send(Bytes::from_static(b"a\r\n")),
+      ]
+    );
+  }
+}
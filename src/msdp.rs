@@ -0,0 +1,87 @@
+//! Choosing between MSDP (MUD Server Data Protocol) and MSDP over GMCP.
+//!
+//! The [MSDP specification] allows MSDP variables to be sent as GMCP messages of the `MSDP` package, with a JSON
+//! body, instead of in MSDP subnegotiations. When both options are enabled a server would otherwise report every
+//! variable twice. `Parser::set_msdp_preference` picks the channel to use, and `Parser::msdp_channel` reports the
+//! one in effect. The markers used in MSDP subnegotiations are in [`telnet::msdp`](crate::telnet::msdp).
+//!
+//! [MSDP specification]: https://tintin.mudhalla.net/protocols/msdp/
+
+use crate::gmcp::GmcpMessage;
+
+/// The GMCP package MSDP variables are sent in.
+pub const GMCP_PACKAGE: &str = "MSDP";
+
+/// A channel MSDP variables can be sent over.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum MsdpChannel {
+  /// MSDP subnegotiations.
+  Msdp,
+  /// GMCP messages of the `MSDP` package.
+  Gmcp,
+}
+
+impl MsdpChannel {
+  /// Choose the channel to use, given which options are enabled.
+  ///
+  /// # Arguments
+  ///
+  /// `msdp` - Whether the MSDP option is enabled.
+  ///
+  /// `gmcp` - Whether the GMCP option is enabled.
+  ///
+  /// `preference` - The channel to use when both options are enabled.
+  ///
+  /// # Returns
+  ///
+  /// `Option<MsdpChannel>` - The channel to use, or None if neither option is enabled.
+  #[must_use]
+  pub fn choose(msdp: bool, gmcp: bool, preference: MsdpChannel) -> Option<MsdpChannel> {
+    match (msdp, gmcp) {
+      (true, true) => Some(preference),
+      (true, false) => Some(MsdpChannel::Msdp),
+      (false, true) => Some(MsdpChannel::Gmcp),
+      (false, false) => None,
+    }
+  }
+}
+
+/// Whether a GMCP message carries MSDP variables.
+#[must_use]
+pub fn is_msdp_over_gmcp(message: &GmcpMessage) -> bool {
+  message.package.eq_ignore_ascii_case(GMCP_PACKAGE)
+}
+
+#[cfg(test)]
+mod test_msdp {
+  use super::*;
+  use bytes::Bytes;
+
+  #[test]
+  fn test_choose() {
+    for preference in [MsdpChannel::Msdp, MsdpChannel::Gmcp] {
+      assert_eq!(
+        MsdpChannel::choose(true, true, preference),
+        Some(preference)
+      );
+      assert_eq!(
+        MsdpChannel::choose(true, false, preference),
+        Some(MsdpChannel::Msdp)
+      );
+      assert_eq!(
+        MsdpChannel::choose(false, true, preference),
+        Some(MsdpChannel::Gmcp)
+      );
+      assert_eq!(MsdpChannel::choose(false, false, preference), None);
+    }
+  }
+
+  #[test]
+  fn test_is_msdp_over_gmcp() {
+    let message = |payload| GmcpMessage::parse(&Bytes::from_static(payload)).unwrap();
+    assert!(is_msdp_over_gmcp(&message(b"MSDP {\"LIST\":\"COMMANDS\"}")));
+    assert!(is_msdp_over_gmcp(&message(b"msdp {}")));
+    assert!(!is_msdp_over_gmcp(&message(b"MSDP.Extra {}")));
+    assert!(!is_msdp_over_gmcp(&message(b"Char.Vitals {}")));
+  }
+}
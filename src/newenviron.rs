@@ -0,0 +1,271 @@
+//! Encoding and decoding of NEW-ENVIRON ([RFC 1572]) subnegotiation payloads.
+//!
+//! A server asks for environment variables with a `SEND` message, see
+//! [`Parser::request_environ`], and the client answers with `IS`, later reporting changes with
+//! `INFO`. As with the [`charset`](crate::charset) module, payloads have telnet `IAC` escaping
+//! removed, while the `ESC` escaping of marker bytes inside names and values is handled here.
+//!
+//! [RFC 1572]: https://www.rfc-editor.org/rfc/rfc1572
+//! [`Parser::request_environ`]: crate::Parser::request_environ
+
+use alloc::vec::Vec;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Report the requested variables.
+pub const IS: u8 = 0;
+/// Request variables, or all of them if none are listed.
+pub const SEND: u8 = 1;
+/// Report variables that changed without being requested.
+pub const INFO: u8 = 2;
+
+/// Precede a well known variable name, e.g. `USER`.
+pub const VAR: u8 = 0;
+/// Precede a variable value.
+pub const VALUE: u8 = 1;
+/// Escape a following marker byte inside a name or value.
+pub const ESC: u8 = 2;
+/// Precede a user defined variable name.
+pub const USERVAR: u8 = 3;
+
+/// Whether a variable is one of the well known ones defined by RFC 1572, or user defined.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum VarKind {
+  /// A well known variable, such as `USER` or `DISPLAY`.
+  Var,
+  /// A user defined variable.
+  UserVar,
+}
+
+impl VarKind {
+  fn marker(self) -> u8 {
+    match self {
+      VarKind::Var => VAR,
+      VarKind::UserVar => USERVAR,
+    }
+  }
+}
+
+/// A variable reported in an `IS` or `INFO` message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Variable {
+  pub kind: VarKind,
+  pub name: Bytes,
+  /// The variable's value. None if the variable is undefined, which differs from being defined but empty.
+  pub value: Option<Bytes>,
+}
+
+/// A decoded NEW-ENVIRON subnegotiation message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EnvironMessage {
+  /// Request the listed variables. An empty name requests every variable of its kind, and an empty list requests
+  /// every variable.
+  Send(Vec<(VarKind, Bytes)>),
+  /// The variables requested by a `SEND`.
+  Is(Vec<Variable>),
+  /// Variables that changed since they were last reported.
+  Info(Vec<Variable>),
+}
+
+impl EnvironMessage {
+  /// Decode a NEW-ENVIRON subnegotiation payload.
+  ///
+  /// Returns `None` if the payload is empty, has an unknown command code, or is malformed.
+  #[must_use]
+  pub fn decode(payload: &[u8]) -> Option<Self> {
+    let (&code, body) = payload.split_first()?;
+    match code {
+      SEND => decode_names(body).map(EnvironMessage::Send),
+      IS => decode_variables(body).map(EnvironMessage::Is),
+      INFO => decode_variables(body).map(EnvironMessage::Info),
+      _ => None,
+    }
+  }
+
+  /// Encode the message into an (unescaped) subnegotiation payload.
+  #[must_use]
+  pub fn to_bytes(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    match self {
+      EnvironMessage::Send(names) => {
+        buf.put_u8(SEND);
+        for (kind, name) in names {
+          buf.put_u8(kind.marker());
+          put_escaped(&mut buf, name);
+        }
+      }
+      EnvironMessage::Is(variables) => {
+        buf.put_u8(IS);
+        put_variables(&mut buf, variables);
+      }
+      EnvironMessage::Info(variables) => {
+        buf.put_u8(INFO);
+        put_variables(&mut buf, variables);
+      }
+    }
+    buf.freeze()
+  }
+}
+
+fn put_variables(buf: &mut BytesMut, variables: &[Variable]) {
+  for variable in variables {
+    buf.put_u8(variable.kind.marker());
+    put_escaped(buf, &variable.name);
+    if let Some(value) = &variable.value {
+      buf.put_u8(VALUE);
+      put_escaped(buf, value);
+    }
+  }
+}
+
+/// Append `data`, prefixing marker bytes with `ESC`.
+fn put_escaped(buf: &mut BytesMut, data: &[u8]) {
+  for &byte in data {
+    if matches!(byte, VAR | VALUE | ESC | USERVAR) {
+      buf.put_u8(ESC);
+    }
+    buf.put_u8(byte);
+  }
+}
+
+/// A marker byte, or a byte of a name or value with any `ESC` escaping removed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Token {
+  Marker(u8),
+  Byte(u8),
+}
+
+fn tokens(body: &[u8]) -> impl Iterator<Item = Token> + '_ {
+  let mut escaped = false;
+  body.iter().filter_map(move |&byte| match byte {
+    _ if escaped => {
+      escaped = false;
+      Some(Token::Byte(byte))
+    }
+    ESC => {
+      escaped = true;
+      None
+    }
+    VAR | VALUE | USERVAR => Some(Token::Marker(byte)),
+    _ => Some(Token::Byte(byte)),
+  })
+}
+
+fn kind_of(marker: u8) -> Option<VarKind> {
+  match marker {
+    VAR => Some(VarKind::Var),
+    USERVAR => Some(VarKind::UserVar),
+    _ => None,
+  }
+}
+
+fn decode_names(body: &[u8]) -> Option<Vec<(VarKind, Bytes)>> {
+  let mut names: Vec<(VarKind, BytesMut)> = Vec::new();
+  for token in tokens(body) {
+    match token {
+      Token::Marker(marker) => names.push((kind_of(marker)?, BytesMut::new())),
+      Token::Byte(byte) => names.last_mut()?.1.put_u8(byte),
+    }
+  }
+  Some(
+    names
+      .into_iter()
+      .map(|(kind, name)| (kind, name.freeze()))
+      .collect(),
+  )
+}
+
+fn decode_variables(body: &[u8]) -> Option<Vec<Variable>> {
+  let mut variables: Vec<(VarKind, BytesMut, Option<BytesMut>)> = Vec::new();
+  for token in tokens(body) {
+    match token {
+      Token::Marker(VALUE) => {
+        let (_, _, value) = variables.last_mut()?;
+        if value.is_some() {
+          return None;
+        }
+        *value = Some(BytesMut::new());
+      }
+      Token::Marker(marker) => variables.push((kind_of(marker)?, BytesMut::new(), None)),
+      Token::Byte(byte) => match variables.last_mut()? {
+        (_, _, Some(value)) => value.put_u8(byte),
+        (_, name, None) => name.put_u8(byte),
+      },
+    }
+  }
+  Some(
+    variables
+      .into_iter()
+      .map(|(kind, name, value)| Variable {
+        kind,
+        name: name.freeze(),
+        value: value.map(BytesMut::freeze),
+      })
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod test_newenviron {
+  use super::*;
+  use alloc::vec;
+
+  #[test]
+  fn test_send() {
+    let message = EnvironMessage::Send(vec![
+      (VarKind::Var, Bytes::from_static(b"USER")),
+      (VarKind::UserVar, Bytes::from_static(b"A\x01B")),
+      (VarKind::UserVar, Bytes::new()),
+    ]);
+    let encoded = message.to_bytes();
+    assert_eq!(&encoded[..], b"\x01\x00USER\x03A\x02\x01B\x03");
+    assert_eq!(EnvironMessage::decode(&encoded), Some(message));
+    assert_eq!(
+      EnvironMessage::decode(&[SEND]),
+      Some(EnvironMessage::Send(vec![]))
+    );
+  }
+
+  #[test]
+  fn test_is() {
+    let payload = b"\x00\x00USER\x01bob\x00DISPLAY\x03EMPTY\x01\x03ESC\x01\x02\x00\x02\x03";
+    let message = EnvironMessage::decode(payload).unwrap();
+    assert_eq!(
+      message,
+      EnvironMessage::Is(vec![
+        Variable {
+          kind: VarKind::Var,
+          name: Bytes::from_static(b"USER"),
+          value: Some(Bytes::from_static(b"bob")),
+        },
+        Variable {
+          kind: VarKind::Var,
+          name: Bytes::from_static(b"DISPLAY"),
+          value: None,
+        },
+        Variable {
+          kind: VarKind::UserVar,
+          name: Bytes::from_static(b"EMPTY"),
+          value: Some(Bytes::new()),
+        },
+        Variable {
+          kind: VarKind::UserVar,
+          name: Bytes::from_static(b"ESC"),
+          value: Some(Bytes::from_static(b"\x00\x03")),
+        },
+      ])
+    );
+    assert_eq!(&message.to_bytes()[..], &payload[..]);
+  }
+
+  #[test]
+  fn test_decode_malformed() {
+    assert_eq!(EnvironMessage::decode(&[]), None);
+    assert_eq!(EnvironMessage::decode(&[INFO + 1]), None);
+    // Data before the first variable.
+    assert_eq!(EnvironMessage::decode(b"\x00USER"), None);
+    // A value in a request, or a second value.
+    assert_eq!(EnvironMessage::decode(b"\x01\x00USER\x01bob"), None);
+    assert_eq!(EnvironMessage::decode(b"\x00\x00USER\x01a\x01b"), None);
+  }
+}
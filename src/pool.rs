@@ -0,0 +1,23 @@
+//! Injection of the buffers the [`Parser`](crate::Parser) receives data into.
+//!
+//! By default the parser's internal buffer is allocated, and grown, with the global allocator. Servers handling
+//! many connections can supply a [`BufferPool`] with `Parser::with_pool` to hand out buffers from their own
+//! arenas or slabs instead.
+
+use bytes::BytesMut;
+
+/// A source of the buffers used by a `Parser`.
+pub trait BufferPool: Send + Sync {
+  /// Provide an empty buffer with a capacity of at least `capacity` bytes.
+  ///
+  /// If the buffer returned is smaller, the parser grows it with the global allocator.
+  fn acquire(&self, capacity: usize) -> BytesMut;
+
+  /// Take back a buffer the parser no longer needs.
+  ///
+  /// The buffer may still share its allocation with `Bytes` handed out in events, which keep it alive until
+  /// they're dropped. By default the buffer is simply dropped.
+  fn release(&self, buffer: BytesMut) {
+    drop(buffer);
+  }
+}
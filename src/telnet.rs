@@ -5,6 +5,53 @@ macro_rules! u8_const {
   };
 }
 
+// Define a public enum with the given u8 constant discriminants, along with conversions to and from u8, and the
+// constant names for display.
+macro_rules! u8_enum {
+  ($(#[$meta:meta])* $name:ident { $($variant:ident = $module:ident::$value:ident,)* }) => {
+    $(#[$meta])*
+    #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+    #[repr(u8)]
+    pub enum $name {
+      $($variant = $module::$value,)*
+    }
+
+    impl $name {
+      /// The name of the code's constant, e.g. `GMCP`.
+      #[must_use]
+      pub fn name(self) -> &'static str {
+        match self {
+          $($name::$variant => stringify!($value).trim_start_matches('_'),)*
+        }
+      }
+    }
+
+    impl core::fmt::Display for $name {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+      }
+    }
+
+    impl From<$name> for u8 {
+      fn from(value: $name) -> u8 {
+        value as u8
+      }
+    }
+
+    impl core::convert::TryFrom<u8> for $name {
+      /// The unrecognized value.
+      type Error = u8;
+
+      fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+          $($module::$value => Ok($name::$variant),)*
+          _ => Err(value),
+        }
+      }
+    }
+  };
+}
+
 /// Module containing constants for Telnet Command codes.
 // TODO(XXX): rename to cmd.
 pub mod op_command {
@@ -20,6 +67,38 @@ pub mod op_command {
   u8_const!(SEND, 1);
   u8_const!(GA, 249);
   u8_const!(EOR, 239);
+  u8_const!(DM, 242);
+  u8_const!(BRK, 243);
+  u8_const!(IP, 244);
+  u8_const!(AO, 245);
+  u8_const!(AYT, 246);
+  u8_const!(EC, 247);
+  u8_const!(EL, 248);
+}
+
+u8_enum! {
+  /// The Telnet Command codes, for exhaustive matching. Converts to and from the `op_command` constants.
+  ///
+  /// `IS` and `SEND` are subnegotiation markers rather than commands, and are not included.
+  Command {
+    Iac = op_command::IAC,
+    Will = op_command::WILL,
+    Wont = op_command::WONT,
+    Do = op_command::DO,
+    Dont = op_command::DONT,
+    Nop = op_command::NOP,
+    Sb = op_command::SB,
+    Se = op_command::SE,
+    Ga = op_command::GA,
+    Eor = op_command::EOR,
+    Dm = op_command::DM,
+    Brk = op_command::BRK,
+    Ip = op_command::IP,
+    Ao = op_command::AO,
+    Ayt = op_command::AYT,
+    Ec = op_command::EC,
+    El = op_command::EL,
+  }
 }
 
 /// Module containing constants for Telnet Option codes.
@@ -65,10 +144,166 @@ pub mod op_option {
   u8_const!(AUTHENTICATION, 37);
   u8_const!(ENCRYPT, 38);
   u8_const!(NEWENVIRON, 39);
+  u8_const!(TN3270E, 40);
+  u8_const!(CHARSET, 42);
+  u8_const!(COMPORT, 44);
+  u8_const!(MSDP, 69);
   u8_const!(MSSP, 70);
+  u8_const!(MSP, 90);
+  u8_const!(MXP, 91);
   u8_const!(ZMP, 93);
   u8_const!(EXOPL, 255);
   u8_const!(MCCP2, 86);
   u8_const!(MCCP3, 87);
   u8_const!(GMCP, 201);
 }
+
+u8_enum! {
+  /// The Telnet Option codes known to this crate, for exhaustive matching. Converts to and from the `op_option`
+  /// constants.
+  ///
+  /// Other option codes can still be negotiated using their `u8` value.
+  KnownOption {
+    Binary = op_option::BINARY,
+    Echo = op_option::ECHO,
+    Rcp = op_option::RCP,
+    Sga = op_option::SGA,
+    Nams = op_option::NAMS,
+    Status = op_option::STATUS,
+    Tm = op_option::TM,
+    Rcte = op_option::RCTE,
+    Naol = op_option::NAOL,
+    Naop = op_option::NAOP,
+    Naocrd = op_option::NAOCRD,
+    Naohts = op_option::NAOHTS,
+    Naohtd = op_option::NAOHTD,
+    Naoffd = op_option::NAOFFD,
+    Naovts = op_option::NAOVTS,
+    Naovtd = op_option::NAOVTD,
+    Naolfd = op_option::NAOLFD,
+    Xascii = op_option::XASCII,
+    Logout = op_option::LOGOUT,
+    Bm = op_option::BM,
+    Det = op_option::DET,
+    Supdup = op_option::SUPDUP,
+    SupdupOutput = op_option::SUPDUPOUTPUT,
+    Sndloc = op_option::SNDLOC,
+    Ttype = op_option::TTYPE,
+    Eor = op_option::EOR,
+    Tuid = op_option::TUID,
+    Outmrk = op_option::OUTMRK,
+    Ttyloc = op_option::TTYLOC,
+    Regime3270 = op_option::_3270REGIME,
+    X3Pad = op_option::X3PAD,
+    Naws = op_option::NAWS,
+    Tspeed = op_option::TSPEED,
+    Lflow = op_option::LFLOW,
+    Linemode = op_option::LINEMODE,
+    Xdisploc = op_option::XDISPLOC,
+    Environ = op_option::ENVIRON,
+    Authentication = op_option::AUTHENTICATION,
+    Encrypt = op_option::ENCRYPT,
+    NewEnviron = op_option::NEWENVIRON,
+    Tn3270E = op_option::TN3270E,
+    Charset = op_option::CHARSET,
+    ComPort = op_option::COMPORT,
+    Msdp = op_option::MSDP,
+    Mssp = op_option::MSSP,
+    Msp = op_option::MSP,
+    Mxp = op_option::MXP,
+    Zmp = op_option::ZMP,
+    Exopl = op_option::EXOPL,
+    Mccp2 = op_option::MCCP2,
+    Mccp3 = op_option::MCCP3,
+    Gmcp = op_option::GMCP,
+  }
+}
+
+/// Module containing the markers used in MSDP (MUD Server Data Protocol) subnegotiations.
+pub mod msdp {
+  /// Precedes a variable name.
+  pub const VAR: u8 = 1;
+  /// Precedes a variable value.
+  pub const VAL: u8 = 2;
+  /// Opens a table value, made of `VAR` and `VAL` pairs.
+  pub const TABLE_OPEN: u8 = 3;
+  /// Closes a table value.
+  pub const TABLE_CLOSE: u8 = 4;
+  /// Opens an array value, made of `VAL` entries.
+  pub const ARRAY_OPEN: u8 = 5;
+  /// Closes an array value.
+  pub const ARRAY_CLOSE: u8 = 6;
+}
+
+/// Module containing the markers used in MSSP (MUD Server Status Protocol) subnegotiations.
+pub mod mssp {
+  /// Precedes a variable name.
+  pub const VAR: u8 = 1;
+  /// Precedes a variable value. A variable may have several.
+  pub const VAL: u8 = 2;
+}
+
+/// Module containing the markers used in NEW-ENVIRON ([RFC 1572]) subnegotiations. See `crate::newenviron` for
+/// encoding and decoding them.
+///
+/// [RFC 1572]: https://www.rfc-editor.org/rfc/rfc1572
+pub mod newenviron {
+  pub use crate::newenviron::{ESC, INFO, IS, SEND, USERVAR, VALUE, VAR};
+}
+
+/// Module containing the markers used in CHARSET ([RFC 2066]) subnegotiations. See `crate::charset` for encoding and
+/// decoding them.
+///
+/// [RFC 2066]: https://www.rfc-editor.org/rfc/rfc2066
+pub mod charset {
+  pub use crate::charset::{
+    ACCEPTED, REJECTED, REQUEST, TTABLE_ACK, TTABLE_IS, TTABLE_NAK, TTABLE_REJECTED,
+  };
+}
+
+/// Module containing the markers used in LINEMODE ([RFC 1184]) subnegotiations. See `crate::linemode` for local
+/// line editing.
+///
+/// [RFC 1184]: https://www.rfc-editor.org/rfc/rfc1184
+pub mod linemode {
+  pub use crate::linemode::{
+    FORWARDMASK, MODE, MODE_ACK, MODE_EDIT, MODE_LIT_ECHO, MODE_SOFT_TAB, MODE_TRAPSIG, SLC,
+    SLC_EC, SLC_EL, SLC_EW, SLC_FORW1, SLC_FORW2, SLC_LEVELBITS, SLC_NOSUPPORT,
+  };
+}
+
+#[cfg(test)]
+mod test_telnet {
+  use super::*;
+  use core::convert::TryFrom;
+
+  #[test]
+  fn test_enum_roundtrip() {
+    for value in 0..=u8::MAX {
+      if let Ok(command) = Command::try_from(value) {
+        assert_eq!(u8::from(command), value);
+      }
+      if let Ok(option) = KnownOption::try_from(value) {
+        assert_eq!(u8::from(option), value);
+      }
+    }
+    assert_eq!(Command::try_from(op_command::SB), Ok(Command::Sb));
+    assert_eq!(Command::try_from(op_command::IS), Err(op_command::IS));
+    assert_eq!(
+      KnownOption::try_from(op_option::GMCP),
+      Ok(KnownOption::Gmcp)
+    );
+    assert_eq!(
+      KnownOption::try_from(op_option::MSDP),
+      Ok(KnownOption::Msdp)
+    );
+    assert_eq!(KnownOption::try_from(200), Err(200));
+  }
+
+  #[test]
+  fn test_enum_names() {
+    assert_eq!(Command::Sb.name(), "SB");
+    assert_eq!(KnownOption::NewEnviron.name(), "NEWENVIRON");
+    assert_eq!(KnownOption::Regime3270.name(), "3270REGIME");
+  }
+}
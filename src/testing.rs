@@ -0,0 +1,329 @@
+//! Helpers for testing the parser, and option handlers built on it, against a reference implementation or
+//! against itself. Enabled with the `testing` feature.
+//!
+//! These are the checks this crate runs against `libtelnet-rs` in its own differential tests and fuzzers.
+//! Forks and extensions can run them against their own reference by implementing [`ReferenceParser`], and use
+//! [`TelnetApplication`] as a fuzzer input when the `arbitrary` feature is enabled as well.
+//!
+//! [`duplex`] connects two parsers in memory, for testing negotiation between a client and a server.
+//!
+//! [`reparse`] and [`assert_event_roundtrip`] check that an event survives being serialized and parsed back, as it
+//! must for a proxy forwarding the events of one connection to another.
+
+use alloc::{rc::Rc, vec::Vec};
+use core::{cell::RefCell, convert::TryFrom};
+
+use crate::compatibility::{CompatibilityEntry, CompatibilityTable};
+use crate::events::TelnetEvents;
+use crate::telnet::op_command::{DO, WILL};
+use crate::Parser;
+
+/// A sequence of reads received by a parser supporting some options.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct TelnetApplication {
+  /// The `(option, bitmask)` pairs the parser's `CompatibilityTable` is created from.
+  pub options: Vec<(u8, u8)>,
+  /// The data received, one read per entry.
+  pub received_data: Vec<Vec<u8>>,
+  /// The seed for re-chunking the received data in `assert_seeded_chunking_invariant`.
+  pub chunk_seed: u64,
+}
+
+impl TelnetApplication {
+  /// Create a parser supporting the application's options.
+  #[must_use]
+  pub fn parser(&self) -> Parser {
+    Parser::with_support(CompatibilityTable::from_options(&self.options))
+  }
+
+  /// All of the received data, as a single stream.
+  #[must_use]
+  pub fn stream(&self) -> Vec<u8> {
+    self.received_data.concat()
+  }
+}
+
+/// A parser that `Parser` is expected to behave identically to.
+pub trait ReferenceParser {
+  /// Parse received data, returning the events in the form `Parser` would.
+  fn receive(&mut self, data: &[u8]) -> Vec<TelnetEvents>;
+
+  /// The bitmask of an option's `CompatibilityEntry`, as returned by `CompatibilityEntry::into_u8`.
+  fn option_bits(&self, option: u8) -> u8;
+}
+
+/// Feed an application's reads to a `Parser` and a reference parser, asserting both produce the same events for
+/// every read and end with the same option table.
+///
+/// # Panics
+///
+/// If the parsers disagree.
+pub fn assert_matches_reference<R: ReferenceParser>(app: &TelnetApplication, reference: &mut R) {
+  let mut parser = app.parser();
+  for data in &app.received_data {
+    assert_eq!(
+      parser.receive(data),
+      reference.receive(data),
+      "read {data:?}"
+    );
+  }
+  for option in 0..u8::MAX {
+    assert_eq!(
+      parser.options.get_option(option).into_u8(),
+      reference.option_bits(option),
+      "option {option}"
+    );
+  }
+}
+
+/// Replay `stream` through `parser`, one chunk per call to `receive`.
+///
+/// # Returns
+///
+/// `Vec<TelnetEvents>` - The events produced, with adjacent `DataReceive` events merged, since how data is split
+/// between them depends on the chunking.
+///
+/// # Notes
+///
+/// The replay stops before the first `DecompressImmediate` event, since the rest of the stream is compressed, and
+/// how much of it the event carries depends on the chunking.
+pub fn replay_chunked(
+  parser: &mut Parser,
+  stream: &[u8],
+  mut chunk_len: impl FnMut() -> usize,
+) -> Vec<TelnetEvents> {
+  let mut events: Vec<TelnetEvents> = Vec::new();
+  let mut rest = stream;
+  while !rest.is_empty() {
+    let (chunk, tail) = rest.split_at(chunk_len().clamp(1, rest.len()));
+    rest = tail;
+    for event in parser.receive(chunk) {
+      match (events.last_mut(), event) {
+        (_, TelnetEvents::DecompressImmediate(_)) => return events,
+        (Some(TelnetEvents::DataReceive(prev)), TelnetEvents::DataReceive(data)) => {
+          *prev = [&prev[..], &data[..]].concat().into();
+        }
+        (_, event) => events.push(event),
+      }
+    }
+  }
+  events
+}
+
+/// Assert that an application's stream produces the same events however it's split into reads, trying every
+/// fixed chunk size.
+///
+/// # Panics
+///
+/// If any chunking produces different events than receiving the whole stream at once.
+pub fn assert_chunking_invariant(app: &TelnetApplication) {
+  let stream = app.stream();
+  let expected = replay_chunked(&mut app.parser(), &stream, || stream.len());
+  for size in 1..stream.len() {
+    let events = replay_chunked(&mut app.parser(), &stream, || size);
+    assert_eq!(events, expected, "chunk size {size}");
+  }
+}
+
+/// A reproducible sequence of chunk lengths between 1 and 16, derived from `seed`, for `replay_chunked`.
+pub fn seeded_chunk_lengths(seed: u64) -> impl FnMut() -> usize {
+  let mut state = seed;
+  move || {
+    // splitmix64, to keep the chunking reproducible without a dependency.
+    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    usize::try_from(z % 16).unwrap_or_default() + 1
+  }
+}
+
+/// Assert that an application's stream produces the same events when split into reads by its `chunk_seed`, as
+/// when received in one read.
+///
+/// # Notes
+///
+/// Unlike `assert_chunking_invariant`, this tries a single chunking of varying sizes, which suits fuzzing: each
+/// input re-chunks its stream differently.
+///
+/// # Panics
+///
+/// If the chunking produces different events than receiving the whole stream at once.
+pub fn assert_seeded_chunking_invariant(app: &TelnetApplication) {
+  let stream = app.stream();
+  let expected = replay_chunked(&mut app.parser(), &stream, || stream.len());
+  let events = replay_chunked(
+    &mut app.parser(),
+    &stream,
+    seeded_chunk_lengths(app.chunk_seed),
+  );
+  assert_eq!(events, expected, "chunk seed {}", app.chunk_seed);
+}
+
+/// Assert that escaping and then unescaping IAC bytes in `data` gives back `data`.
+///
+/// # Panics
+///
+/// If the round trip changes the data.
+pub fn assert_escape_roundtrip(data: &[u8]) {
+  let escaped = Parser::escape_iac(data.to_vec());
+  assert_eq!(&Parser::unescape_iac(escaped)[..], data);
+}
+
+/// Serialize an event with `TelnetEvents::to_bytes`, and parse the bytes back with a fresh parser.
+///
+/// # Returns
+///
+/// `Vec<TelnetEvents>` - The events produced, without any `DataSend` replies the parser sends, and with adjacent
+/// `DataReceive` events merged.
+///
+/// # Notes
+///
+/// The parser supports, and has enabled, every option in both directions, other than the option of a `WILL` or
+/// `DO` negotiation, which is disabled so that the negotiation changes something. Subnegotiation payloads are
+/// decoded with the default `DecodePolicy::Raw`. It's in record mode when the event is a `Record`.
+#[must_use]
+pub fn reparse(event: &TelnetEvents) -> Vec<TelnetEvents> {
+  let mut table = CompatibilityTable::new();
+  for option in 0..=u8::MAX {
+    table.set_option(option, CompatibilityEntry::new(true, true, true, true));
+  }
+  if let TelnetEvents::Negotiation(negotiation) = event {
+    if matches!(negotiation.command, WILL | DO) {
+      // Repeating an enabled option's negotiation doesn't produce an event.
+      table.set_option(
+        negotiation.option,
+        CompatibilityEntry::new(true, true, false, false),
+      );
+    }
+  }
+  let mut parser = Parser::with_support(table);
+  parser.set_record_mode(matches!(event, TelnetEvents::Record(_)));
+  let mut events: Vec<TelnetEvents> = Vec::new();
+  for event in parser.receive(&event.clone().to_bytes()) {
+    match (events.last_mut(), event) {
+      (_, TelnetEvents::DataSend(_)) => {}
+      (Some(TelnetEvents::DataReceive(prev)), TelnetEvents::DataReceive(data)) => {
+        *prev = [&prev[..], &data[..]].concat().into();
+      }
+      (_, event) => events.push(event),
+    }
+  }
+  events
+}
+
+/// Assert that serializing an event and parsing it back with [`reparse`] gives back the same event.
+///
+/// # Notes
+///
+/// The events that round trip are those describing received data:
+///
+/// * `IAC`, for the two byte commands: `GA`, `EOR`, `NOP`, `DM`, `BRK`, `IP`, `AO`, `AYT`, `EC` and `EL`.
+/// * `Negotiation`, for the `WILL`, `WONT`, `DO` and `DONT` commands.
+/// * `Subnegotiation`, when the payload is IAC escaped as received, for options other than MCCP2 and MCCP3, which
+///   start compression instead.
+/// * `DataReceive`, when the data is IAC escaped as received, and not empty.
+/// * `Record`.
+///
+/// The other events either describe the parser's state, and have no bytes, or are already bytes to send.
+///
+/// # Panics
+///
+/// If the event doesn't round trip.
+pub fn assert_event_roundtrip(event: &TelnetEvents) {
+  assert_eq!(
+    reparse(event),
+    core::slice::from_ref(event),
+    "event {event:?}"
+  );
+}
+
+/// One end of an in-memory connection created by [`duplex`].
+pub struct Endpoint {
+  pub parser: Parser,
+  incoming: Rc<RefCell<Vec<u8>>>,
+  outgoing: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Endpoint {
+  /// Send the bytes of a `DataSend` event to the other end. Other events, and None, are ignored, so the results of
+  /// `Parser` methods can be passed straight in.
+  pub fn send(&mut self, event: impl Into<Option<TelnetEvents>>) {
+    if let Some(TelnetEvents::DataSend(data)) = event.into() {
+      self.outgoing.borrow_mut().extend_from_slice(&data);
+    }
+  }
+
+  /// Receive everything the other end has sent so far.
+  ///
+  /// # Returns
+  ///
+  /// `Vec<TelnetEvents>` - The events produced. `DataSend` events, such as negotiation replies, are included and
+  /// have already been sent to the other end.
+  pub fn poll(&mut self) -> Vec<TelnetEvents> {
+    let data = core::mem::take(&mut *self.incoming.borrow_mut());
+    if data.is_empty() {
+      return Vec::new();
+    }
+    let events = self.parser.receive(&data);
+    for event in &events {
+      self.send(event.clone());
+    }
+    events
+  }
+
+  /// Whether the other end has sent anything that hasn't been received yet.
+  #[must_use]
+  pub fn has_incoming(&self) -> bool {
+    !self.incoming.borrow().is_empty()
+  }
+}
+
+/// Create two connected in-memory endpoints, for exercising negotiation between a client and a server without
+/// sockets.
+///
+/// # Arguments
+///
+/// `client` - The `CompatibilityTable` of the first endpoint's parser.
+///
+/// `server` - The `CompatibilityTable` of the second endpoint's parser.
+#[must_use]
+pub fn duplex(client: CompatibilityTable, server: CompatibilityTable) -> (Endpoint, Endpoint) {
+  let to_client = Rc::new(RefCell::new(Vec::new()));
+  let to_server = Rc::new(RefCell::new(Vec::new()));
+  (
+    Endpoint {
+      parser: Parser::with_support(client),
+      incoming: Rc::clone(&to_client),
+      outgoing: Rc::clone(&to_server),
+    },
+    Endpoint {
+      parser: Parser::with_support(server),
+      incoming: to_server,
+      outgoing: to_client,
+    },
+  )
+}
+
+/// Poll both endpoints until neither has anything left to receive.
+///
+/// # Returns
+///
+/// `(Vec<TelnetEvents>, Vec<TelnetEvents>)` - The events each endpoint produced, in order.
+///
+/// # Panics
+///
+/// If the endpoints are still exchanging data after 100 rounds, which suggests a negotiation loop.
+pub fn settle(a: &mut Endpoint, b: &mut Endpoint) -> (Vec<TelnetEvents>, Vec<TelnetEvents>) {
+  let (mut a_events, mut b_events) = (Vec::new(), Vec::new());
+  for _ in 0..100 {
+    if !a.has_incoming() && !b.has_incoming() {
+      return (a_events, b_events);
+    }
+    a_events.extend(a.poll());
+    b_events.extend(b.poll());
+  }
+  panic!("endpoints didn't settle");
+}
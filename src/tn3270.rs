@@ -0,0 +1,49 @@
+//! Detection of IBM 3270 sessions ([RFC 1576], [RFC 2355]).
+//!
+//! A 3270 session is negotiated over Telnet either with the TN3270E option, or traditionally by
+//! exchanging an `IBM-3278` or `IBM-3279` terminal type and enabling BINARY and EOR. Once enabled
+//! with `Parser::set_tn3270_detection`, the parser reports the switch with a
+//! `TelnetEvents::Tn3270` event. 3270 data streams are sent as records terminated by `IAC EOR`,
+//! which `Parser::set_record_mode` delivers whole.
+//!
+//! [RFC 1576]: https://www.rfc-editor.org/rfc/rfc1576
+//! [RFC 2355]: https://www.rfc-editor.org/rfc/rfc2355
+
+/// The terminal type prefixes identifying a 3270 terminal.
+const TERMINAL_PREFIXES: [&[u8]; 2] = [b"IBM-3278", b"IBM-3279"];
+
+/// The kind of session negotiated with the remote end.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Tn3270Mode {
+  /// A regular Telnet session. This is the default.
+  #[default]
+  Nvt,
+  /// A traditional TN3270 session, following a 3270 terminal type.
+  Tn3270,
+  /// A TN3270E session, following the TN3270E option being enabled.
+  Tn3270E,
+}
+
+/// Whether a terminal type names a 3270 terminal, e.g. `IBM-3278-2-E`.
+#[must_use]
+pub fn is_3270_terminal(terminal_type: &[u8]) -> bool {
+  TERMINAL_PREFIXES.iter().any(|prefix| {
+    terminal_type.len() >= prefix.len()
+      && terminal_type[..prefix.len()].eq_ignore_ascii_case(prefix)
+  })
+}
+
+#[cfg(test)]
+mod test_tn3270 {
+  use super::*;
+
+  #[test]
+  fn test_is_3270_terminal() {
+    assert!(is_3270_terminal(b"IBM-3278-2-E"));
+    assert!(is_3270_terminal(b"ibm-3279-5"));
+    assert!(is_3270_terminal(b"IBM-3278"));
+    assert!(!is_3270_terminal(b"IBM-327"));
+    assert!(!is_3270_terminal(b"IBM-3179-2"));
+    assert!(!is_3270_terminal(b"XTERM-256COLOR"));
+  }
+}
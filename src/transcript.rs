@@ -0,0 +1,378 @@
+//! Logging of received text to a transcript, as MUD clients do. Enabled with the `std` feature.
+//!
+//! A [`Transcript`] is fed the events returned by the parser and writes each received line to any
+//! [`std::io::Write`], optionally without ANSI escape sequences and prefixed with the time it was received. Text
+//! that isn't followed by a line ending, such as a prompt, is written once it's marked with `IAC GA` or `IAC EOR`.
+//! [`RotatingFile`] is a writer that starts a new file once the current one grows too large.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::events::TelnetEvents;
+use crate::telnet::op_command::{EOR, GA};
+use crate::Parser;
+
+/// How received lines are written to a transcript. Lines are written as received, without timestamps, by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct TranscriptOptions {
+  /// Remove ANSI escape sequences, such as colours, from the lines.
+  pub strip_ansi: bool,
+  /// Prefix each line with the UTC time it was completed, e.g. `[2024-01-31 18:05:09] `.
+  pub timestamps: bool,
+}
+
+/// Writes the lines of received text to a writer.
+#[derive(Debug)]
+pub struct Transcript<W: Write> {
+  writer: W,
+  options: TranscriptOptions,
+  line: Vec<u8>,
+}
+
+impl<W: Write> Transcript<W> {
+  /// Start a transcript.
+  ///
+  /// # Arguments
+  ///
+  /// `writer` - Where the lines are written. Example: a `RotatingFile`
+  ///
+  /// `options` - How the lines are written.
+  pub fn new(writer: W, options: TranscriptOptions) -> Self {
+    Self {
+      writer,
+      options,
+      line: Vec::new(),
+    }
+  }
+
+  /// Record an event returned by the parser, at the current time.
+  ///
+  /// # Errors
+  ///
+  /// Returns any error from the writer.
+  pub fn record(&mut self, event: &TelnetEvents) -> io::Result<()> {
+    self.record_at(SystemTime::now(), event)
+  }
+
+  /// Record an event returned by the parser, at the given time.
+  ///
+  /// # Notes
+  ///
+  /// `DataReceive` events are written a line at a time, as their line endings arrive. A `Record` event is written
+  /// as a line of its own, and `IAC GA` and `IAC EOR` complete the current line. Other events are ignored.
+  ///
+  /// # Errors
+  ///
+  /// Returns any error from the writer.
+  pub fn record_at(&mut self, time: SystemTime, event: &TelnetEvents) -> io::Result<()> {
+    match event {
+      TelnetEvents::DataReceive(data) => {
+        // Received data keeps the IAC escaping it was sent with.
+        for byte in Parser::unescape_iac(data.clone()) {
+          if byte == b'\n' {
+            self.write_line(time)?;
+          } else {
+            self.line.push(byte);
+          }
+        }
+        Ok(())
+      }
+      TelnetEvents::Record(data) => {
+        self.write_line(time)?;
+        self.line.extend_from_slice(data);
+        self.write_line(time)
+      }
+      TelnetEvents::IAC(iac) if iac.command == GA || iac.command == EOR => self.write_line(time),
+      _ => Ok(()),
+    }
+  }
+
+  /// Write any incomplete line, and flush the writer. Call this before the transcript is dropped.
+  ///
+  /// # Errors
+  ///
+  /// Returns any error from the writer.
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.write_line(SystemTime::now())?;
+    self.writer.flush()
+  }
+
+  /// The writer the lines are written to.
+  pub fn get_ref(&self) -> &W {
+    &self.writer
+  }
+
+  /// Consume the transcript, returning the writer. Any incomplete line isn't written.
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
+
+  /// Write the current line, if there is one, in a single write so a `RotatingFile` never splits it.
+  fn write_line(&mut self, time: SystemTime) -> io::Result<()> {
+    if self.line.is_empty() {
+      return Ok(());
+    }
+    let mut text = if self.options.strip_ansi {
+      strip_ansi(&self.line)
+    } else {
+      core::mem::take(&mut self.line)
+    };
+    self.line.clear();
+    if text.last() == Some(&b'\r') {
+      text.pop();
+    }
+    let mut line = Vec::with_capacity(text.len() + 23);
+    if self.options.timestamps {
+      write_timestamp(&mut line, time);
+    }
+    line.extend_from_slice(&text);
+    line.push(b'\n');
+    self.writer.write_all(&line)
+  }
+}
+
+/// Remove ANSI escape sequences from text: control sequences such as `ESC [ 1 ; 31 m`, operating system commands
+/// terminated by `BEL` or `ESC \`, and other two byte escapes.
+#[must_use]
+pub fn strip_ansi(text: &[u8]) -> Vec<u8> {
+  #[derive(Clone, Copy)]
+  enum State {
+    Text,
+    Escape,
+    Csi,
+    Osc,
+    OscEscape,
+  }
+
+  let mut stripped = Vec::with_capacity(text.len());
+  let mut state = State::Text;
+  for &byte in text {
+    state = match (state, byte) {
+      (State::Text, 0x1b) => State::Escape,
+      (State::Text, _) => {
+        stripped.push(byte);
+        State::Text
+      }
+      (State::Escape, b'[') => State::Csi,
+      (State::Escape, b']') => State::Osc,
+      (State::Csi, 0x40..=0x7e) | (State::Escape | State::OscEscape, _) | (State::Osc, 0x07) => {
+        State::Text
+      }
+      (State::Osc, 0x1b) => State::OscEscape,
+      (State::Csi, _) => State::Csi,
+      (State::Osc, _) => State::Osc,
+    };
+  }
+  stripped
+}
+
+/// Append `[YYYY-MM-DD HH:MM:SS] ` in UTC.
+fn write_timestamp(line: &mut Vec<u8>, time: SystemTime) {
+  let seconds = time
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |since| since.as_secs());
+  let (days, time_of_day) = (seconds / 86_400, seconds % 86_400);
+  // Civil date from a day count, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+  let days = days + 719_468;
+  let era = days / 146_097;
+  let day_of_era = days % 146_097;
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+  let month = if month_index < 10 {
+    month_index + 3
+  } else {
+    month_index - 9
+  };
+  let year = year_of_era + era * 400 + u64::from(month <= 2);
+  // Writing to a Vec can't fail.
+  let _ = write!(
+    line,
+    "[{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}] ",
+    time_of_day / 3600,
+    time_of_day / 60 % 60,
+    time_of_day % 60
+  );
+}
+
+/// A file that's rotated once it would grow past a maximum length, keeping a number of older files alongside it.
+///
+/// Older files are named after the current one with a number appended, `.1` being the most recent. Each write
+/// goes to a single file, so a `Transcript` never splits a line between files.
+#[derive(Debug)]
+pub struct RotatingFile {
+  path: PathBuf,
+  max_len: u64,
+  keep: usize,
+  file: File,
+  len: u64,
+}
+
+impl RotatingFile {
+  /// Open a file for appending, creating it if needed.
+  ///
+  /// # Arguments
+  ///
+  /// `path` - The path of the current file. Example: `logs/session.log`
+  ///
+  /// `max_len` - The length, in bytes, a write may not take the file past. A single longer write is written to an
+  /// empty file.
+  ///
+  /// `keep` - How many older files to keep. Zero discards the current file when it's rotated.
+  ///
+  /// # Errors
+  ///
+  /// Returns any error opening the file.
+  pub fn open(path: impl AsRef<Path>, max_len: u64, keep: usize) -> io::Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let len = file.metadata()?.len();
+    Ok(Self {
+      path,
+      max_len,
+      keep,
+      file,
+      len,
+    })
+  }
+
+  /// The path of the `index`th older file, with the current file's path for zero.
+  fn rotated_path(&self, index: usize) -> PathBuf {
+    if index == 0 {
+      return self.path.clone();
+    }
+    let mut path = self.path.clone().into_os_string();
+    path.push(format!(".{index}"));
+    path.into()
+  }
+
+  fn rotate(&mut self) -> io::Result<()> {
+    self.file.flush()?;
+    if self.keep == 0 {
+      self.file = File::create(&self.path)?;
+    } else {
+      let oldest = self.rotated_path(self.keep);
+      if oldest.exists() {
+        fs::remove_file(oldest)?;
+      }
+      for index in (0..self.keep).rev() {
+        let from = self.rotated_path(index);
+        if from.exists() {
+          fs::rename(from, self.rotated_path(index + 1))?;
+        }
+      }
+      self.file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&self.path)?;
+    }
+    self.len = 0;
+    Ok(())
+  }
+}
+
+impl Write for RotatingFile {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self.len > 0 && self.len + buf.len() as u64 > self.max_len {
+      self.rotate()?;
+    }
+    let written = self.file.write(buf)?;
+    self.len += written as u64;
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.file.flush()
+  }
+}
+
+#[cfg(test)]
+mod test_transcript {
+  use super::*;
+  use crate::events::TelnetIAC;
+  use bytes::Bytes;
+  use core::time::Duration;
+
+  fn data(text: &'static [u8]) -> TelnetEvents {
+    TelnetEvents::DataReceive(Bytes::from_static(text))
+  }
+
+  #[test]
+  fn test_lines() {
+    let mut transcript = Transcript::new(Vec::new(), TranscriptOptions::default());
+    let time = UNIX_EPOCH;
+    for event in [
+      data(b"Hello\r\nWor"),
+      data(b"ld\r\n\x1b[1;31mHP\x1b[0m: 10>"),
+      TelnetEvents::IAC(TelnetIAC::new(GA)),
+      TelnetEvents::Record(Bytes::from_static(b"A record")),
+      data(b"partial"),
+    ] {
+      transcript.record_at(time, &event).unwrap();
+    }
+    assert_eq!(
+      transcript.get_ref(),
+      b"Hello\nWorld\n\x1b[1;31mHP\x1b[0m: 10>\nA record\n"
+    );
+    transcript.flush().unwrap();
+    assert!(transcript.into_inner().ends_with(b"A record\npartial\n"));
+  }
+
+  #[test]
+  fn test_escaped_iac() {
+    let mut transcript = Transcript::new(Vec::new(), TranscriptOptions::default());
+    transcript
+      .record_at(UNIX_EPOCH, &data(b"a\xff\xffb\r\n"))
+      .unwrap();
+    assert_eq!(transcript.get_ref(), b"a\xffb\n");
+  }
+
+  #[test]
+  fn test_options() {
+    let mut transcript = Transcript::new(
+      Vec::new(),
+      TranscriptOptions {
+        strip_ansi: true,
+        timestamps: true,
+      },
+    );
+    let time = UNIX_EPOCH + Duration::from_secs(1_706_724_309);
+    transcript
+      .record_at(
+        time,
+        &data(b"\x1b[32mok\x1b[0m \x1b]0;title\x07done\x1b7\r\n"),
+      )
+      .unwrap();
+    assert_eq!(transcript.get_ref(), b"[2024-01-31 18:05:09] ok done\n");
+  }
+
+  #[test]
+  fn test_strip_ansi() {
+    assert_eq!(strip_ansi(b"plain"), b"plain");
+    assert_eq!(strip_ansi(b"\x1b[38;5;208mx\x1b[K"), b"x");
+    assert_eq!(strip_ansi(b"a\x1b]8;;http://x\x1b\\b"), b"ab");
+    assert_eq!(strip_ansi(b"\x1bMup"), b"up");
+  }
+
+  #[test]
+  fn test_rotating_file() {
+    let dir = std::env::temp_dir().join(format!("libmudtelnet-transcript-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("session.log");
+    let mut file = RotatingFile::open(&path, 8, 2).unwrap();
+    for line in [&b"one\n"[..], b"two\n", b"three\n", b"four\n", b"five\n"] {
+      file.write_all(line).unwrap();
+    }
+    file.flush().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"five\n");
+    assert_eq!(fs::read(dir.join("session.log.1")).unwrap(), b"four\n");
+    assert_eq!(fs::read(dir.join("session.log.2")).unwrap(), b"three\n");
+    assert!(!dir.join("session.log.3").exists());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
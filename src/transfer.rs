@@ -0,0 +1,95 @@
+//! Detection of in-band file transfers.
+//!
+//! Some MUD clients support file transfers started from the server with tools like `sz` and `rz`, which write
+//! the transfer straight into the connection. Once enabled with `Parser::set_transfer_detection`, the parser
+//! looks for the start of such a transfer in received data, emits `TelnetEvents::FileTransferDetected` and switches
+//! to passthrough mode so the transfer isn't corrupted by Telnet interpretation.
+
+/// The leading bytes of a Zmodem hex header: `ZPAD ZPAD ZDLE ZHEX` and the first digit of the frame type.
+const ZMODEM_HEADER: &[u8] = b"**\x18B0";
+
+/// The frame types that start a Zmodem transfer: `ZRQINIT` from a sender and `ZRINIT` from a receiver.
+const ZMODEM_START_TYPES: &[u8] = b"01";
+
+/// The length of a complete Zmodem start signature.
+pub(crate) const ZMODEM_SIGNATURE_LEN: usize = ZMODEM_HEADER.len() + 1;
+
+/// A file transfer protocol recognized by the parser.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum TransferProtocol {
+  /// Zmodem, detected by the `ZRQINIT` (`**\x18B00`) or `ZRINIT` (`**\x18B01`) header that starts a transfer.
+  Zmodem,
+}
+
+/// Incremental matcher for transfer start signatures, which may be split across several reads.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TransferDetector {
+  matched: usize,
+}
+
+impl TransferDetector {
+  /// Forget any partially matched signature.
+  pub(crate) fn reset(&mut self) {
+    self.matched = 0;
+  }
+
+  /// Scan received data for the end of a transfer signature.
+  ///
+  /// # Returns
+  ///
+  /// `Option<(TransferProtocol, usize)>` - The protocol detected and the index in `data` just past the end of its
+  /// signature. The signature may have started in data scanned earlier.
+  pub(crate) fn scan(&mut self, data: &[u8]) -> Option<(TransferProtocol, usize)> {
+    for (index, &byte) in data.iter().enumerate() {
+      let expected = match ZMODEM_HEADER.get(self.matched) {
+        Some(expected) => byte == *expected,
+        None => ZMODEM_START_TYPES.contains(&byte),
+      };
+      if expected {
+        self.matched += 1;
+        if self.matched == ZMODEM_SIGNATURE_LEN {
+          self.matched = 0;
+          return Some((TransferProtocol::Zmodem, index + 1));
+        }
+      } else if byte == b'*' {
+        // A run of ZPAD bytes still matches the start of the header.
+        self.matched = if self.matched == 2 { 2 } else { 1 };
+      } else {
+        self.matched = 0;
+      }
+    }
+    None
+  }
+
+  /// The leading bytes of a signature that ended `end` bytes into the latest data scanned, but started in data
+  /// scanned earlier.
+  pub(crate) fn carried_prefix(end: usize) -> &'static [u8] {
+    &ZMODEM_HEADER[..ZMODEM_SIGNATURE_LEN.saturating_sub(end)]
+  }
+}
+
+#[cfg(test)]
+mod test_transfer {
+  use super::*;
+
+  #[test]
+  fn test_scan() {
+    let mut detector = TransferDetector::default();
+    assert_eq!(detector.scan(b"rz\r***\x18B0"), None);
+    assert_eq!(
+      detector.scan(b"0000000"),
+      Some((TransferProtocol::Zmodem, 1))
+    );
+    assert_eq!(TransferDetector::carried_prefix(1), b"**\x18B0");
+    assert_eq!(
+      detector.scan(b"x**\x18B01"),
+      Some((TransferProtocol::Zmodem, 7))
+    );
+    assert_eq!(TransferDetector::carried_prefix(7), b"");
+    assert_eq!(detector.scan(b"**\x18B02 *\x18B00"), None);
+
+    detector.scan(b"**");
+    detector.reset();
+    assert_eq!(detector.scan(b"\x18B00"), None);
+  }
+}
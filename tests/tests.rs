@@ -1,8 +1,19 @@
 use bytes::Bytes;
 
-use libmudtelnet::compatibility::{CompatibilityEntry, CompatibilityTable};
-use libmudtelnet::events::{TelnetEvents, TelnetSubnegotiation};
+use libmudtelnet::compatibility::{
+  CompatibilityEntry, CompatibilityTable, Direction, EnabledOptions,
+};
+use libmudtelnet::conformance::ConformanceIssue;
+use libmudtelnet::encrypt::{self, Cipher};
+use libmudtelnet::events::{
+  DecodePolicy, EventMeta, IacCommand, KeepaliveFilter, NegotiationRefused, NulPolicy,
+  OptionDecodeError, OversizedSubnegotiation, ProtocolError, SubnegotiationRefused, TelnetEvents,
+  TelnetIAC, TelnetNegotiation, TelnetSubnegotiation,
+};
+use libmudtelnet::gmcp::{self, GmcpMessage};
 use libmudtelnet::telnet::{op_command as cmd, op_option as opt};
+use libmudtelnet::tn3270::Tn3270Mode;
+use libmudtelnet::transfer::TransferProtocol;
 use libmudtelnet::Parser;
 
 /// Test the parser and its general functionality.
@@ -15,6 +26,17 @@ enum Event {
   Recv,
   Send,
   Decom,
+  TerminalTypes,
+  Gmcp,
+  FileTransfer,
+  ProtocolError,
+  OptionDecodeError,
+  Decryption,
+  Tn3270,
+  Record,
+  NegotiationComplete,
+  OptionStateChanged,
+  ShutdownComplete,
 }
 
 macro_rules! events {
@@ -86,6 +108,62 @@ fn handle_events(event_list: Vec<TelnetEvents>) -> CapturedEvents {
         println!("DECOMPRESS: {:?}", buffer);
         events.push(Event::Decom);
       }
+      TelnetEvents::TerminalTypes(types) => {
+        println!("Terminal types: {:?}", types);
+        events.push(Event::TerminalTypes);
+      }
+      TelnetEvents::Gmcp(message) => {
+        println!("GMCP: {} {:?}", message.package, message.data);
+        events.push(Event::Gmcp);
+      }
+      TelnetEvents::FileTransferDetected(protocol) => {
+        println!("File transfer: {:?}", protocol);
+        events.push(Event::FileTransfer);
+      }
+      TelnetEvents::DecryptionChanged(decrypting) => {
+        println!("Decrypting: {}", decrypting);
+        events.push(Event::Decryption);
+      }
+      TelnetEvents::Tn3270(mode) => {
+        println!("3270 mode: {:?}", mode);
+        events.push(Event::Tn3270);
+      }
+      TelnetEvents::Record(record) => {
+        println!("Record: {:?}", record);
+        events.push(Event::Record);
+      }
+      TelnetEvents::ProtocolError(error) => {
+        println!("Protocol error: {:?}", error);
+        events.push(Event::ProtocolError);
+      }
+      TelnetEvents::OptionDecodeError(error) => {
+        println!("Option decode error: {:?}", error);
+        events.push(Event::OptionDecodeError);
+      }
+      TelnetEvents::NegotiationComplete {
+        option,
+        direction,
+        accepted,
+      } => {
+        println!(
+          "Negotiation complete: {} {:?} {}",
+          option, direction, accepted
+        );
+        events.push(Event::NegotiationComplete);
+      }
+      TelnetEvents::OptionStateChanged {
+        option,
+        before,
+        after,
+      } => {
+        println!("Option state changed: {} {:?} {:?}", option, before, after);
+        events.push(Event::OptionStateChanged);
+      }
+      TelnetEvents::ShutdownComplete => {
+        println!("Shutdown complete");
+        events.push(Event::ShutdownComplete);
+      }
+      event => panic!("Unhandled event: {:?}", event),
     };
   }
   events
@@ -106,9 +184,10 @@ fn test_parser() {
     handle_events(instance.receive(&[b"Hello, rust!", &[cmd::IAC, cmd::GA][..]].concat())),
     events![Event::Recv, Event::Iac]
   );
+  // The DO acknowledging our offer needs no reply.
   assert_eq!(
     handle_events(instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP])),
-    events![]
+    events![Event::Negotiation]
   );
   assert_eq!(
     handle_events(instance.receive(&[&[cmd::IAC, cmd::DO, 200][..], b"Some random data"].concat())),
@@ -232,180 +311,3001 @@ fn test_subneg_utf8_content() {
   }
 }
 
-/// Test escaping IAC bytes in a buffer.
 #[test]
-fn test_escape() {
-  let initial = vec![
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::SE,
-  ];
-  let expected = Bytes::copy_from_slice(&[
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SE,
-  ]);
-  assert_eq!(expected, Parser::escape_iac(initial.clone()));
-  assert_eq!(initial, Parser::unescape_iac(expected));
+fn test_terminal_type_cycle() {
+  use cmd::{IAC, IS, SB, SE, SEND, WILL};
+  use opt::TTYPE;
+
+  let ttype_is = |name: &[u8]| [&[IAC, SB, TTYPE, IS][..], name, &[IAC, SE][..]].concat();
+  let ttype_send = TelnetEvents::DataSend(Bytes::copy_from_slice(&[IAC, SB, TTYPE, SEND, IAC, SE]));
+
+  let mut parser = Parser::new();
+  parser.options.support_remote(TTYPE);
+  // Until the remote end has agreed to TTYPE there's nothing to request.
+  assert_eq!(parser.request_terminal_types(), None);
+  parser.receive(&[IAC, WILL, TTYPE]);
+  assert_eq!(parser.request_terminal_types(), Some(ttype_send.clone()));
+
+  // Each new terminal type should prompt another request.
+  assert_eq!(
+    parser.receive(&ttype_is(b"BLIGHTMUD")),
+    vec![ttype_send.clone()]
+  );
+  assert_eq!(
+    parser.receive(&ttype_is(b"XTERM-256COLOR")),
+    vec![ttype_send.clone()]
+  );
+  assert_eq!(parser.receive(&ttype_is(b"MTTS 2831")), vec![ttype_send]);
+  // Once the last type is repeated the cycle is complete.
+  assert_eq!(
+    parser.receive(&ttype_is(b"MTTS 2831")),
+    vec![TelnetEvents::TerminalTypes(vec![
+      "BLIGHTMUD".to_string(),
+      "XTERM-256COLOR".to_string(),
+      "MTTS 2831".to_string(),
+    ])]
+  );
+  // Further responses aren't part of a cycle.
+  assert_eq!(parser.receive(&ttype_is(b"BLIGHTMUD")), vec![]);
+
+  // Other TTYPE subnegotiations received during a cycle are processed as usual.
+  let mut parser = Parser::with_support(CompatibilityTable::from_options(&[(
+    TTYPE,
+    CompatibilityEntry::new(true, true, true, true).into_u8(),
+  )]));
+  let ttype_send = parser.request_terminal_types().unwrap();
+  assert_eq!(
+    parser.receive(&[IAC, SB, TTYPE, SEND, IAC, SE]),
+    vec![TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      TTYPE,
+      Bytes::from_static(&[SEND])
+    ))]
+  );
+  assert_eq!(parser.receive(&ttype_is(b"BLIGHTMUD")), vec![ttype_send]);
 }
 
-/// Test unescaping IAC bytes in a buffer.
 #[test]
-fn test_unescape() {
-  let initial = vec![
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SE,
-  ];
-  let expected = Bytes::copy_from_slice(&[
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::SE,
-  ]);
-  assert_eq!(expected, Parser::unescape_iac(initial.clone()));
-  assert_eq!(initial, Parser::escape_iac(expected));
+fn test_feed_next_event() {
+  use cmd::{DO, GA, IAC, SB, SE};
+  use opt::GMCP;
+
+  let data = [
+    &b"Hello"[..],
+    &[IAC, GA, IAC, DO, GMCP, IAC, SB, GMCP][..],
+    b"Core.Ping",
+  ]
+  .concat();
+
+  let mut parser = Parser::new();
+  parser.options.support(GMCP);
+  let mut pull_parser = Parser::new();
+  pull_parser.options.support(GMCP);
+
+  // Pulling events one at a time should match the events from receive.
+  pull_parser.feed(&data);
+  let mut pulled = Vec::new();
+  while let Some(event) = pull_parser.next_event() {
+    pulled.push(event);
+  }
+  assert_eq!(pulled, parser.receive(&data));
+  assert_eq!(pulled.len(), 4);
+
+  // The unterminated subnegotiation stays buffered until the rest is fed.
+  assert_eq!(pull_parser.next_event(), None);
+  pull_parser.feed(&[IAC, SE]);
+  assert_eq!(
+    pull_parser.next_event(),
+    Some(TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      GMCP,
+      Bytes::from_static(b"Core.Ping")
+    )))
+  );
+  assert_eq!(pull_parser.next_event(), None);
 }
 
+#[cfg(feature = "serde_json")]
 #[test]
-fn test_escape_roundtrip_bug_one() {
-  // The original libtelnet-rs mishandles this input:
-  let data = vec![cmd::IAC, cmd::IAC, 228];
-  let escaped = Parser::escape_iac(data.clone());
-  let unescaped = Parser::unescape_iac(escaped);
-  assert_eq!(unescaped, data);
+fn test_send_gmcp() {
+  use cmd::{IAC, SB, SE};
+  use opt::GMCP;
+
+  let mut parser = Parser::new();
+  parser.options.support_local(GMCP);
+  // GMCP hasn't been enabled yet, so there's nothing to send.
+  assert_eq!(parser.send_gmcp("Core.Hello", &[1, 2]).unwrap(), None);
+
+  parser._will(GMCP);
+  let event = parser
+    .send_gmcp("Core.Supports.Set", &["Char 1", "Room 1"])
+    .unwrap()
+    .unwrap();
+  assert_eq!(
+    event,
+    TelnetEvents::DataSend(Bytes::copy_from_slice(
+      &[
+        &[IAC, SB, GMCP][..],
+        br#"Core.Supports.Set ["Char 1","Room 1"]"#,
+        &[IAC, SE][..],
+      ]
+      .concat()
+    ))
+  );
 }
 
 #[test]
-fn test_escape_roundtrip_bug_two() {
-  // The original libtelnet-rs mishandles this input:
-  let data = vec![228, cmd::IAC, cmd::IAC];
-  let escaped = Parser::escape_iac(data.clone());
-  let unescaped = Parser::unescape_iac(escaped);
-  assert_eq!(unescaped, data);
+fn test_event_offsets() {
+  use cmd::{DO, GA, IAC, SB, SE};
+  use opt::GMCP;
+
+  let mut parser = Parser::new();
+  parser.options.support(GMCP);
+  parser.feed(
+    &[
+      &b"Hi"[..],
+      &[IAC, GA, IAC, DO, GMCP, IAC, SB, GMCP, b'x'][..],
+    ]
+    .concat(),
+  );
+
+  let mut offsets = Vec::new();
+  while let Some((meta, _)) = parser.next_event_with_meta() {
+    offsets.push(meta.offset);
+  }
+  // Data, IAC GA, and the WILL reply and negotiation event for IAC DO GMCP.
+  assert_eq!(offsets, vec![0, 2, 4, 4]);
+  // The incomplete subnegotiation isn't counted until it's finished.
+  assert_eq!(parser.stream_offset(), 7);
+
+  parser.feed(&[IAC, SE, b'!']);
+  let (meta, event) = parser.next_event_with_meta().unwrap();
+  assert_eq!(meta.offset, 7);
+  assert!(matches!(event, TelnetEvents::Subnegotiation(_)));
+  let (meta, event) = parser.next_event_with_meta().unwrap();
+  assert_eq!(meta.offset, 13);
+  assert_eq!(event, TelnetEvents::DataReceive(Bytes::from_static(b"!")));
+  assert_eq!(parser.stream_offset(), 14);
 }
 
+// Servers commonly toggle ECHO around password prompts. Each toggle should be answered, and
+// re-enabling after a disable shouldn't be mistaken for a repeat of an option already enabled.
 #[test]
-fn test_bad_subneg_dbuffer() {
-  // Configure opt 0xFF (IAC) as local supported, and local state enabled.
-  let entry = CompatibilityEntry::new(true, false, true, false);
-  let opts = CompatibilityTable::from_options(&[(cmd::IAC, entry.into_u8())]);
-  // Receive a malformed subnegotiation - this should not panic.
-  Parser::with_support(opts).receive(&[cmd::IAC, cmd::SB, cmd::IAC, cmd::SE]);
+fn test_renegotiate_remote_echo() {
+  use cmd::{DO, DONT, IAC, WILL, WONT};
+  use opt::ECHO;
+
+  let send = |cmd| TelnetEvents::DataSend(Bytes::copy_from_slice(&[IAC, cmd, ECHO]));
+  let neg = |cmd| TelnetEvents::Negotiation(TelnetNegotiation::new(cmd, ECHO));
+
+  let mut parser = Parser::new();
+  parser.options.support_remote(ECHO);
+  for _ in 0..3 {
+    assert_eq!(
+      parser.receive(&[IAC, WILL, ECHO]),
+      vec![send(DO), neg(WILL)]
+    );
+    assert!(parser.options.get_option(ECHO).remote_state);
+    assert_eq!(
+      parser.receive(&[IAC, WONT, ECHO]),
+      vec![send(DONT), neg(WONT)]
+    );
+    assert!(!parser.options.get_option(ECHO).remote_state);
+  }
 }
 
 #[test]
-fn test_into_bytes() {
-  let bytes = libmudtelnet::events::TelnetIAC::new(cmd::IAC).to_bytes();
-  assert!(!bytes.is_empty())
+fn test_renegotiate_after_do() {
+  use cmd::{DO, IAC, WILL};
+  use opt::ECHO;
+
+  let mut parser = Parser::new();
+  parser.options.support(ECHO);
+  // Agreeing to enable ECHO locally must not mark it as enabled remotely...
+  assert_eq!(
+    handle_events(parser.receive(&[IAC, DO, ECHO])),
+    events![Event::Send, Event::Negotiation]
+  );
+  let entry = parser.options.get_option(ECHO);
+  assert!(entry.local_state);
+  assert!(!entry.remote_state);
+  // ...otherwise a later WILL from the remote end would go unanswered.
+  assert_eq!(
+    parser.receive(&[IAC, WILL, ECHO]),
+    vec![
+      TelnetEvents::DataSend(Bytes::copy_from_slice(&[IAC, DO, ECHO])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(WILL, ECHO)),
+    ]
+  );
+  assert!(parser.options.get_option(ECHO).remote_state);
 }
 
-#[cfg(test)]
-mod compat_tests {
-  use compat::{test_app, TelnetApplication};
+#[test]
+fn test_renegotiate_local_echo() {
+  use cmd::{DO, DONT, IAC, WILL, WONT};
+  use opt::ECHO;
 
-  #[test]
-  fn test_parser_diff1() {
-    test_app(&TelnetApplication {
-      options: vec![(255, 254)],
-      received_data: vec![vec![255, 255, 255, 255, 255, 254, 255, 0]],
-    });
+  let mut parser = Parser::new();
+  parser.options.support_local(ECHO);
+  for _ in 0..3 {
+    assert_eq!(
+      parser._will(ECHO),
+      Some(TelnetEvents::DataSend(Bytes::copy_from_slice(&[
+        IAC, WILL, ECHO
+      ])))
+    );
+    // The remote end's DO acknowledges our offer and needs no reply.
+    assert_eq!(
+      parser.receive(&[IAC, DO, ECHO]),
+      vec![TelnetEvents::Negotiation(TelnetNegotiation::new(DO, ECHO))]
+    );
+    assert_eq!(
+      parser._wont(ECHO),
+      Some(TelnetEvents::DataSend(Bytes::copy_from_slice(&[
+        IAC, WONT, ECHO
+      ])))
+    );
+    assert_eq!(
+      handle_events(parser.receive(&[IAC, DONT, ECHO])),
+      events![Event::Negotiation]
+    );
   }
+}
 
-  #[test]
-  fn test_parser_diff2() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![45, 255, 250, 255]],
-    });
-  }
+#[test]
+fn test_dont_clears_remote_state() {
+  use cmd::{DO, DONT, IAC, WILL, WONT};
+  use opt::NAWS;
 
-  #[test]
-  fn test_parser_diff3() {
-    test_app(&TelnetApplication {
-      options: vec![(0, 1)],
-      received_data: vec![vec![255, 253, 0]],
-    })
-  }
+  let mut parser = Parser::new();
+  parser.options.support(NAWS);
+  parser.receive(&[IAC, WILL, NAWS]);
+  assert!(parser.options.get_option(NAWS).remote_state);
 
-  #[test]
-  fn test_parser_diff4() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255, 250, 255, 255, 240, 250]],
-    })
-  }
+  // Disabling takes effect immediately...
+  let outcome = parser.request_option(DONT, NAWS).unwrap();
+  assert!(!outcome.is_pending());
+  assert_eq!(
+    outcome.into_event(),
+    TelnetEvents::DataSend(Bytes::copy_from_slice(&[IAC, DONT, NAWS]))
+  );
+  assert!(!parser.options.get_option(NAWS).remote_state);
+  assert_eq!(parser._dont(NAWS), None);
+  // ...so the acknowledgement isn't answered with another DONT.
+  assert_eq!(
+    parser.receive(&[IAC, WONT, NAWS]),
+    vec![TelnetEvents::Negotiation(TelnetNegotiation::new(
+      WONT, NAWS
+    ))]
+  );
 
-  #[test]
-  fn test_parser_diff5() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255, 250, 255, 240, 0]],
-    })
-  }
+  // Enabling a remote option waits for the remote end to agree.
+  let outcome = parser.request_option(DO, NAWS).unwrap();
+  assert!(outcome.is_pending());
+  assert!(!parser.options.get_option(NAWS).remote_state);
+  parser.receive(&[IAC, WILL, NAWS]);
+  assert!(parser.options.get_option(NAWS).remote_state);
 
-  #[test]
-  fn test_parser_diff6() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![240, 255, 250, 255, 240, 0]],
-    })
-  }
+  assert_eq!(parser.request_option(cmd::GA, NAWS), None);
+}
 
-  #[test]
-  fn test_parser_diff7() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255]],
-    })
-  }
+/// Test decoding subnegotiation payloads with a registered decoder.
+#[test]
+fn test_subnegotiation_decoder() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityEntry::new(true, false, true, false).into_u8(),
+  )]));
+  let data = [
+    &[cmd::IAC, cmd::SB, opt::GMCP][..],
+    b"Core.Ping",
+    &[cmd::IAC, cmd::SE],
+  ]
+  .concat();
+  assert_eq!(
+    handle_events(instance.receive(&data)),
+    events![Event::Subnegotiation]
+  );
 
-  #[test]
-  fn test_parser_diff8() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255, 252, 0]],
-    })
-  }
+  instance.set_subnegotiation_decoder(opt::GMCP, gmcp::decode);
+  assert_eq!(
+    instance.receive(&data),
+    vec![TelnetEvents::Gmcp(Box::new(GmcpMessage::new(
+      "Core.Ping",
+      None
+    )))]
+  );
+  // A payload the decoder rejects is still emitted as a subnegotiation.
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::SB, opt::GMCP, b' ', cmd::IAC, cmd::SE])),
+    events![Event::Subnegotiation]
+  );
 
-  #[test]
-  fn test_parser_diff9() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![254, 255, 255, 255, 254, 0]],
-    })
-  }
+  instance.clear_subnegotiation_decoder(opt::GMCP);
+  assert_eq!(
+    handle_events(instance.receive(&data)),
+    events![Event::Subnegotiation]
+  );
+}
 
-  #[test]
-  fn test_parser_diff10() {
-    test_app(&TelnetApplication {
-      options: vec![(255, 254), (1, 0)],
-      received_data: vec![vec![255, 253, 255]],
-    })
-  }
+#[test]
+fn test_decode_policy() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityEntry::new(true, false, true, false).into_u8(),
+  )]));
+  let stream = [
+    &[cmd::IAC, cmd::SB, opt::GMCP][..],
+    &[1, cmd::IAC, cmd::IAC, 2, cmd::IAC, 3],
+    &[cmd::IAC, cmd::SE],
+  ]
+  .concat();
+  assert_eq!(
+    instance.receive(&stream),
+    vec![TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(&[1, cmd::IAC, cmd::IAC, 2, cmd::IAC, 3])
+    ))]
+  );
+
+  instance.set_decode_policy(opt::GMCP, DecodePolicy::Unescape);
+  assert_eq!(
+    instance.receive(&stream),
+    vec![TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(&[1, cmd::IAC, 2, cmd::IAC, 3])
+    ))]
+  );
+
+  instance.set_decode_policy(opt::GMCP, DecodePolicy::Raw);
+  assert_eq!(
+    instance.receive(&stream),
+    vec![TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(&[1, cmd::IAC, cmd::IAC, 2, cmd::IAC, 3])
+    ))]
+  );
+}
+
+#[test]
+fn test_journal() {
+  let mut instance = Parser::new();
+  instance.receive(b"before");
+  assert!(instance.journal().is_empty());
+
+  instance.set_journal_capacity(2);
+  instance.receive(&[b'a', cmd::IAC, cmd::GA, b'b']);
+  let journal: Vec<(u64, TelnetEvents)> = instance
+    .journal()
+    .iter()
+    .map(|(meta, event)| (meta.offset, event.clone()))
+    .collect();
+  assert_eq!(
+    journal,
+    vec![
+      (7, TelnetEvents::IAC(TelnetIAC::new(cmd::GA))),
+      (9, TelnetEvents::DataReceive(Bytes::from_static(b"b"))),
+    ]
+  );
+
+  instance.set_journal_capacity(1);
+  assert_eq!(instance.journal().len(), 1);
+  instance.clear_journal();
+  assert!(instance.journal().is_empty());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_split_reads() {
+  use libmudtelnet::testing::{self, TelnetApplication};
+
+  let fixtures: &[&[u8]] = &[
+    // Negotiation burst, as sent on connect.
+    &[
+      cmd::IAC,
+      cmd::DO,
+      opt::GMCP,
+      cmd::IAC,
+      cmd::WILL,
+      opt::ECHO,
+      cmd::IAC,
+      cmd::DO,
+      opt::NAWS,
+      cmd::IAC,
+      cmd::DONT,
+      opt::MCCP2,
+    ],
+    // Prompts terminated by GA, with escaped IAC bytes in the data.
+    b"Name: \xff\xf9\r\nPass\xff\xffword: \xff\xf9\xff\xff\xff\xff!",
+    // Subnegotiations with escaped IAC bytes in the payload, between data.
+    &[
+      &[cmd::IAC, cmd::DO, opt::GMCP][..],
+      b"hp 10",
+      &[cmd::IAC, cmd::SB, opt::GMCP],
+      b"Char.Vitals {\"hp\": 10}",
+      &[cmd::IAC, cmd::SE],
+      b"mp \xff\xff",
+      &[
+        cmd::IAC,
+        cmd::SB,
+        opt::GMCP,
+        cmd::IAC,
+        cmd::IAC,
+        cmd::IAC,
+        cmd::SE,
+      ],
+      &[cmd::IAC, cmd::EOR, cmd::IAC, cmd::NOP],
+    ]
+    .concat(),
+    // Stray SE commands around data, negotiations and a subnegotiation.
+    &[
+      &[cmd::IAC, cmd::SE][..],
+      b"north",
+      &[
+        cmd::IAC,
+        cmd::SE,
+        cmd::IAC,
+        cmd::SE,
+        cmd::IAC,
+        cmd::DO,
+        opt::GMCP,
+      ],
+      b"\xff\xff",
+      &[cmd::IAC, cmd::SE, cmd::IAC, cmd::SB, opt::GMCP],
+      b"Core.Ping",
+      &[cmd::IAC, cmd::SE, cmd::IAC, cmd::SE],
+      b"south",
+    ]
+    .concat(),
+  ];
+  for stream in fixtures {
+    let mut app = TelnetApplication {
+      options: vec![
+        (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+        (opt::ECHO, CompatibilityTable::ENABLED_REMOTE),
+        (opt::NAWS, CompatibilityTable::ENABLED_LOCAL),
+      ],
+      received_data: vec![stream.to_vec()],
+      chunk_seed: 0,
+    };
+    testing::assert_chunking_invariant(&app);
+    for seed in 1..=200 {
+      app.chunk_seed = seed;
+      testing::assert_seeded_chunking_invariant(&app);
+    }
+  }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_testing_helpers() {
+  use libmudtelnet::testing::{self, ReferenceParser, TelnetApplication};
+
+  // A reference that is a second parser, to check the harness itself.
+  struct Twin(Parser);
+
+  impl ReferenceParser for Twin {
+    fn receive(&mut self, data: &[u8]) -> Vec<TelnetEvents> {
+      self.0.receive(data)
+    }
+
+    fn option_bits(&self, option: u8) -> u8 {
+      self.0.options.get_option(option).into_u8()
+    }
+  }
+
+  let app = TelnetApplication {
+    options: vec![(opt::GMCP, CompatibilityTable::ENABLED_LOCAL)],
+    received_data: vec![
+      vec![cmd::IAC, cmd::DO, opt::GMCP, b'h'],
+      b"p\xff\xff 10\xff\xf9".to_vec(),
+    ],
+    chunk_seed: 7,
+  };
+  testing::assert_matches_reference(&app, &mut Twin(app.parser()));
+  testing::assert_chunking_invariant(&app);
+  testing::assert_seeded_chunking_invariant(&app);
+  testing::assert_escape_roundtrip(&app.stream());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_duplex() {
+  use libmudtelnet::testing;
+
+  let (mut client, mut server) = testing::duplex(
+    CompatibilityTable::from_options(&[
+      (opt::NAWS, CompatibilityTable::ENABLED_LOCAL),
+      (opt::GMCP, CompatibilityTable::ENABLED_REMOTE),
+    ]),
+    CompatibilityTable::from_options(&[
+      (opt::NAWS, CompatibilityTable::ENABLED_REMOTE),
+      (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+      (opt::ECHO, CompatibilityTable::ENABLED_LOCAL),
+    ]),
+  );
+  let offers = server.parser.offer_all(&[
+    (opt::NAWS, Direction::Remote),
+    (opt::GMCP, Direction::Local),
+    (opt::ECHO, Direction::Local),
+  ]);
+  for event in offers {
+    server.send(event);
+  }
+  let (client_events, server_events) = testing::settle(&mut client, &mut server);
+  assert_eq!(
+    handle_events(client_events),
+    events![
+      Event::Send,
+      Event::Negotiation,
+      Event::Send,
+      Event::Negotiation,
+      Event::Send,
+      Event::Negotiation
+    ]
+  );
+  // The client accepts NAWS and GMCP, and refuses ECHO.
+  assert_eq!(
+    handle_events(server_events),
+    events![
+      Event::Send,
+      Event::Negotiation,
+      Event::Negotiation,
+      Event::Send,
+      Event::Negotiation
+    ]
+  );
+  assert!(client.parser.options.get_option(opt::NAWS).local_state);
+  assert!(client.parser.options.get_option(opt::GMCP).remote_state);
+  assert!(server.parser.options.get_option(opt::NAWS).remote_state);
+  assert!(server.parser.options.get_option(opt::GMCP).local_state);
+  assert!(!server.parser.options.get_option(opt::ECHO).local_state);
+
+  let text = server.parser.send_text("Welcome!");
+  server.send(text);
+  assert_eq!(
+    testing::settle(&mut client, &mut server),
+    (
+      vec![TelnetEvents::DataReceive(Bytes::from_static(
+        b"Welcome!\r\n"
+      ))],
+      vec![]
+    )
+  );
+}
+
+#[test]
+fn test_receive_into() {
+  let stream = [&[cmd::IAC, cmd::WILL, opt::ECHO][..], b"hp 10\xff\xf9"].concat();
+  let expected = Parser::new().receive(&stream);
+  let mut instance = Parser::new();
+  let mut events = Vec::new();
+  instance.receive_into(&stream[..3], &mut events);
+  instance.receive_into(&stream[3..], &mut events);
+  assert_eq!(events, expected);
+
+  events.clear();
+  instance.receive_into(b"", &mut events);
+  assert!(events.is_empty());
+}
+
+#[test]
+fn test_raw_capture() {
+  let stream = [
+    b"hp 10",
+    &[cmd::IAC, cmd::WILL, opt::ECHO][..],
+    &[cmd::IAC, cmd::SB, opt::GMCP],
+  ]
+  .concat();
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityEntry::new(true, false, true, false).into_u8(),
+  )]));
+  instance.feed(&stream);
+  assert_eq!(
+    instance.next_event_with_raw(),
+    Some((
+      EventMeta { offset: 0 },
+      TelnetEvents::DataReceive(Bytes::from_static(b"hp 10")),
+      Bytes::new()
+    ))
+  );
+
+  instance.set_raw_capture(true);
+  let negotiation = Bytes::from_static(&[cmd::IAC, cmd::WILL, opt::ECHO]);
+  let mut raw = Vec::new();
+  while let Some((_, event, bytes)) = instance.next_event_with_raw() {
+    raw.push((event, bytes));
+  }
+  assert_eq!(
+    raw,
+    vec![(
+      instance.negotiate(cmd::DONT, opt::ECHO),
+      negotiation.clone()
+    )]
+  );
+  // The subnegotiation is returned whole once it's complete.
+  instance.feed(&[b'x', cmd::IAC, cmd::SE]);
+  let (_, _, bytes) = instance.next_event_with_raw().unwrap();
+  assert_eq!(
+    &bytes[..],
+    &[cmd::IAC, cmd::SB, opt::GMCP, b'x', cmd::IAC, cmd::SE]
+  );
+}
+
+#[test]
+fn test_split_iac_command() {
+  let mut instance = Parser::new();
+  assert_eq!(
+    instance.receive(b"ab\xff"),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"ab"))]
+  );
+  assert_eq!(
+    instance.receive(&[cmd::GA]),
+    vec![TelnetEvents::IAC(TelnetIAC::new(cmd::GA))]
+  );
+  assert_eq!(instance.receive(&[cmd::IAC, cmd::WILL]), vec![]);
+  assert_eq!(
+    instance.receive(&[opt::ECHO]),
+    vec![TelnetEvents::DataSend(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::DONT,
+      opt::ECHO
+    ]))]
+  );
+  // Data starting with an escaped IAC is data, not a command.
+  assert_eq!(
+    instance.receive(b"\xff\xffcd"),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"\xff\xffcd"))]
+  );
+}
+
+#[test]
+fn test_unexpected_se() {
+  let stream = [
+    b"look",
+    &[cmd::IAC, cmd::SE][..],
+    b"north",
+    &[cmd::IAC, cmd::SE],
+  ]
+  .concat();
+  let mut instance = Parser::new();
+  assert_eq!(
+    instance.receive(&stream),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"look")),
+      TelnetEvents::DataReceive(Bytes::from_static(b"north")),
+    ]
+  );
+
+  instance.set_diagnostics(true);
+  assert_eq!(
+    handle_events(instance.receive(&stream)),
+    events![
+      Event::Recv,
+      Event::ProtocolError,
+      Event::Recv,
+      Event::ProtocolError
+    ]
+  );
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::SE]),
+    vec![TelnetEvents::ProtocolError(ProtocolError::UnexpectedSe)]
+  );
+}
+
+#[test]
+fn test_nul_policy() {
+  let stream = [b"hp\r\0", &[cmd::IAC, cmd::GA][..], b"\0\0"].concat();
+  let mut instance = Parser::new();
+  assert_eq!(
+    instance.receive(&stream),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"hp\r\0")),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+      TelnetEvents::DataReceive(Bytes::from_static(b"\0\0")),
+    ]
+  );
+
+  instance.set_nul_policy(NulPolicy::Strip);
+  assert_eq!(
+    instance.receive(&stream),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"hp\r")),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+    ]
+  );
+
+  instance.set_nul_policy(NulPolicy::Error);
+  assert_eq!(
+    instance.receive(&stream),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"hp\r")),
+      TelnetEvents::ProtocolError(ProtocolError::UnexpectedNul),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+      TelnetEvents::ProtocolError(ProtocolError::UnexpectedNul),
+    ]
+  );
+  assert_eq!(
+    instance.receive(b"\xff\xff\0"),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"\xff\xff")),
+      TelnetEvents::ProtocolError(ProtocolError::UnexpectedNul),
+    ]
+  );
+}
+
+/// A toy cipher XORing each byte with a key that advances, so decrypting out of order garbles the data.
+struct XorCipher(u8);
+
+impl Cipher for XorCipher {
+  fn decrypt(&mut self, data: &mut [u8]) {
+    for byte in data {
+      *byte ^= self.0;
+      self.0 = self.0.wrapping_add(1);
+    }
+  }
+}
+
+fn encrypted_stream() -> Vec<u8> {
+  let mut secret = [
+    b"secret",
+    &[cmd::IAC, cmd::GA][..],
+    &[
+      cmd::IAC,
+      cmd::SB,
+      opt::ENCRYPT,
+      encrypt::END,
+      cmd::IAC,
+      cmd::SE,
+    ],
+  ]
+  .concat();
+  XorCipher(0x5a).decrypt(&mut secret);
+  [
+    &[
+      cmd::IAC,
+      cmd::SB,
+      opt::ENCRYPT,
+      encrypt::START,
+      0,
+      cmd::IAC,
+      cmd::SE,
+    ][..],
+    &secret,
+    b"plain",
+  ]
+  .concat()
+}
+
+#[test]
+fn test_decryption() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::ENCRYPT,
+    CompatibilityEntry::new(true, false, true, false).into_u8(),
+  )]));
+  let stream = encrypted_stream();
+  // Without a cipher nothing is decrypted.
+  assert_eq!(
+    handle_events(instance.receive(&stream)),
+    events![Event::Subnegotiation, Event::Recv]
+  );
+
+  for chunk_size in 1..=stream.len() {
+    instance.set_cipher(Some(Box::new(XorCipher(0x5a))));
+    let mut events = Vec::new();
+    for chunk in stream.chunks(chunk_size) {
+      events.extend(instance.receive(chunk));
+    }
+    let data: Vec<u8> = events
+      .iter()
+      .filter_map(|event| match event {
+        TelnetEvents::DataReceive(data) => Some(&data[..]),
+        _ => None,
+      })
+      .flatten()
+      .copied()
+      .collect();
+    assert_eq!(data, b"secretplain", "chunk size {}", chunk_size);
+    let switches: Vec<&TelnetEvents> = events
+      .iter()
+      .filter(|event| !matches!(event, TelnetEvents::DataReceive(_)))
+      .collect();
+    assert_eq!(
+      switches,
+      [
+        &TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+          opt::ENCRYPT,
+          Bytes::from_static(&[encrypt::START, 0])
+        )),
+        &TelnetEvents::DecryptionChanged(true),
+        &TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+        &TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+          opt::ENCRYPT,
+          Bytes::from_static(&[encrypt::END])
+        )),
+        &TelnetEvents::DecryptionChanged(false),
+      ],
+      "chunk size {}",
+      chunk_size
+    );
+    assert!(!instance.decrypting());
+  }
+}
+
+#[test]
+fn test_tn3270_detection() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (
+      opt::TN3270E,
+      CompatibilityEntry::new(true, true, false, false).into_u8(),
+    ),
+    (
+      opt::TTYPE,
+      CompatibilityEntry::new(false, true, false, false).into_u8(),
+    ),
+  ]));
+  let terminal_type = [
+    &[cmd::IAC, cmd::SB, opt::TTYPE, cmd::IS][..],
+    b"IBM-3278-2-E",
+    &[cmd::IAC, cmd::SE],
+  ]
+  .concat();
+  assert_eq!(instance.receive(&terminal_type), vec![]);
+  assert_eq!(instance.tn3270_mode(), Tn3270Mode::Nvt);
+
+  instance.set_tn3270_detection(true);
+  assert_eq!(
+    instance.receive(&terminal_type),
+    vec![TelnetEvents::Tn3270(Tn3270Mode::Tn3270)]
+  );
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::WILL, opt::TN3270E]),
+    vec![
+      TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::DO, opt::TN3270E])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::WILL, opt::TN3270E)),
+      TelnetEvents::Tn3270(Tn3270Mode::Tn3270E),
+    ]
+  );
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::WONT, opt::TN3270E])),
+    events![Event::Send, Event::Negotiation, Event::Tn3270]
+  );
+  assert_eq!(instance.tn3270_mode(), Tn3270Mode::Nvt);
+}
+
+#[test]
+fn test_record_mode() {
+  let mut instance = Parser::new();
+  instance.set_record_mode(true);
+  assert_eq!(
+    instance.receive(&[
+      0xf5,
+      0xc3,
+      cmd::IAC,
+      cmd::IAC,
+      0x11,
+      cmd::IAC,
+      cmd::NOP,
+      0x40
+    ]),
+    vec![TelnetEvents::IAC(TelnetIAC::new(cmd::NOP))]
+  );
+  let events = instance.receive(&[
+    0x40,
+    cmd::IAC,
+    cmd::EOR,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::EOR,
+    1,
+  ]);
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::Record(Bytes::from_static(&[
+        0xf5,
+        0xc3,
+        cmd::IAC,
+        0x11,
+        0x40,
+        0x40
+      ])),
+      TelnetEvents::Record(Bytes::from_static(&[cmd::IAC])),
+    ]
+  );
+  assert_eq!(
+    events[1].clone().to_bytes(),
+    Bytes::from_static(&[cmd::IAC, cmd::IAC, cmd::IAC, cmd::EOR])
+  );
+
+  // The incomplete record is emitted as data with the next events.
+  instance.set_record_mode(false);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::EOR]),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(&[1])),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::EOR)),
+    ]
+  );
+}
+
+#[test]
+fn test_half_duplex() {
+  let ga = TelnetEvents::IAC(TelnetIAC::new(cmd::GA));
+  let mut instance = Parser::new();
+  assert_eq!(
+    instance.send_output(b"plain"),
+    Some(TelnetEvents::DataSend(Bytes::from_static(b"plain")))
+  );
+
+  instance.set_half_duplex(true);
+  assert_eq!(
+    instance.send_output(b"Name? "),
+    Some(TelnetEvents::DataSend(Bytes::from_static(&[
+      b'N',
+      b'a',
+      b'm',
+      b'e',
+      b'?',
+      b' ',
+      cmd::IAC,
+      cmd::GA
+    ])))
+  );
+  // Withheld until the remote end's GA.
+  assert_eq!(instance.send_output(b"a"), None);
+  assert_eq!(instance.send_output(&[cmd::IAC]), None);
+  assert_eq!(instance.queued_output(), 3);
+  assert_eq!(
+    instance.receive(&[b'x', cmd::IAC, cmd::GA]),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"x")),
+      ga.clone(),
+      TelnetEvents::DataSend(Bytes::from_static(&[
+        b'a',
+        cmd::IAC,
+        cmd::IAC,
+        cmd::IAC,
+        cmd::GA
+      ])),
+    ]
+  );
+  assert_eq!(instance.queued_output(), 0);
+
+  // The turn comes back without any queued output, so the next output is sent straight away.
+  assert_eq!(instance.receive(&[cmd::IAC, cmd::GA]), vec![ga.clone()]);
+  assert!(instance.send_output(b"b").is_some());
+  assert_eq!(instance.send_output(b"c"), None);
+  assert_eq!(
+    instance.flush_output(),
+    Some(TelnetEvents::DataSend(Bytes::from_static(&[
+      b'c',
+      cmd::IAC,
+      cmd::GA
+    ])))
+  );
+  assert_eq!(instance.flush_output(), None);
+
+  // Disabling emits the queued output with the next events.
+  assert_eq!(instance.send_output(b"d"), None);
+  instance.set_half_duplex(false);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::GA]),
+    vec![TelnetEvents::DataSend(Bytes::from_static(b"d")), ga]
+  );
+}
+
+#[test]
+fn test_negotiation_reply_order() {
+  for command in [cmd::WILL, cmd::WONT, cmd::DO, cmd::DONT] {
+    for support in 0..16 {
+      let mut instance =
+        Parser::with_support(CompatibilityTable::from_options(&[(opt::ECHO, support)]));
+      let events = instance.receive(&[cmd::IAC, command, opt::ECHO]);
+      let notification = TelnetEvents::Negotiation(TelnetNegotiation::new(command, opt::ECHO));
+      // Any reply comes before the notification.
+      let kinds: Vec<bool> = events
+        .iter()
+        .map(|event| matches!(event, TelnetEvents::DataSend(_)))
+        .collect();
+      assert!(
+        matches!(kinds[..], [] | [true] | [false] | [true, false]),
+        "{} {:#06b}: {:?}",
+        command,
+        support,
+        events
+      );
+      for event in events {
+        match event {
+          TelnetEvents::DataSend(data) => assert_eq!(data[2], opt::ECHO),
+          event => assert_eq!(event, notification),
+        }
+      }
+    }
+  }
+}
+
+#[test]
+fn test_try_negotiation() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+    (opt::ECHO, CompatibilityTable::ENABLED_REMOTE),
+  ]));
+  assert_eq!(
+    instance.try_will(opt::ECHO),
+    Err(NegotiationRefused::UnsupportedLocally)
+  );
+  assert_eq!(
+    instance.try_do(opt::GMCP),
+    Err(NegotiationRefused::UnsupportedRemotely)
+  );
+  assert_eq!(
+    instance.try_wont(opt::GMCP),
+    Err(NegotiationRefused::AlreadyDisabled)
+  );
+  assert_eq!(
+    instance.try_will(opt::GMCP),
+    Ok(instance.negotiate(cmd::WILL, opt::GMCP))
+  );
+  assert_eq!(
+    instance.try_will(opt::GMCP),
+    Err(NegotiationRefused::AlreadyEnabled)
+  );
+  assert_eq!(
+    instance.try_dont(opt::ECHO),
+    Err(NegotiationRefused::AlreadyDisabled)
+  );
+  assert_eq!(
+    instance.try_do(opt::ECHO),
+    Ok(instance.negotiate(cmd::DO, opt::ECHO))
+  );
+  instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO]);
+  assert_eq!(
+    instance.try_do(opt::ECHO),
+    Err(NegotiationRefused::AlreadyEnabled)
+  );
+  assert_eq!(
+    NegotiationRefused::UnsupportedLocally.to_string(),
+    "option not supported locally"
+  );
+}
+
+#[test]
+fn test_offer_all() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+    (opt::ECHO, CompatibilityTable::ENABLED_REMOTE),
+  ]));
+  let options = [
+    (opt::GMCP, Direction::Local),
+    (opt::NAWS, Direction::Local),
+    (opt::ECHO, Direction::Remote),
+  ];
+  assert_eq!(
+    instance.offer_all(&options),
+    vec![
+      instance.negotiate(cmd::WILL, opt::GMCP),
+      instance.negotiate(cmd::DO, opt::ECHO),
+    ]
+  );
+  instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO]);
+  assert_eq!(instance.offer_all(&options), vec![]);
+  assert_eq!(
+    instance.refuse_all(&options),
+    vec![
+      instance.negotiate(cmd::WONT, opt::GMCP),
+      instance.negotiate(cmd::DONT, opt::ECHO),
+    ]
+  );
+  assert_eq!(instance.refuse_all(&options), vec![]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_reply_suppression() {
+  let mut instance = Parser::new();
+  instance.set_reply_suppression(Some(std::time::Duration::from_secs(60)));
+  let offer = [cmd::IAC, cmd::WILL, opt::GMCP];
+  assert_eq!(
+    instance.receive(&offer),
+    vec![instance.negotiate(cmd::DONT, opt::GMCP)]
+  );
+  assert_eq!(instance.receive(&[offer, offer].concat()), vec![]);
+  // Replies for other options and commands aren't affected.
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]),
+    vec![instance.negotiate(cmd::WONT, opt::GMCP)]
+  );
+
+  instance.set_reply_suppression(Some(std::time::Duration::ZERO));
+  assert_eq!(
+    instance.receive(&[offer, offer].concat()),
+    vec![
+      instance.negotiate(cmd::DONT, opt::GMCP),
+      instance.negotiate(cmd::DONT, opt::GMCP),
+    ]
+  );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_reply_suppression_state_change() {
+  let mut instance = Parser::new();
+  instance.options.support_remote(opt::GMCP);
+  instance.set_reply_suppression(Some(std::time::Duration::from_secs(60)));
+  let will = TelnetNegotiation::new(cmd::WILL, opt::GMCP);
+  let wont = TelnetNegotiation::new(cmd::WONT, opt::GMCP);
+  // Replies that enable an option, and refusals after the option's state changed, are never suppressed.
+  for _ in 0..2 {
+    assert_eq!(
+      instance.receive(&[cmd::IAC, cmd::WILL, opt::GMCP]),
+      vec![
+        instance.negotiate(cmd::DO, opt::GMCP),
+        TelnetEvents::Negotiation(will),
+      ]
+    );
+    assert_eq!(
+      instance.receive(&[cmd::IAC, cmd::WONT, opt::GMCP]),
+      vec![
+        instance.negotiate(cmd::DONT, opt::GMCP),
+        TelnetEvents::Negotiation(wont),
+      ]
+    );
+  }
+}
+
+#[test]
+fn test_negotiation_complete() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+    (opt::ECHO, CompatibilityTable::ENABLED_REMOTE),
+  ]));
+  instance.set_offer_tracking(true);
+  instance._will(opt::GMCP).unwrap();
+  instance._do(opt::ECHO).unwrap();
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]),
+    vec![
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::GMCP)),
+      TelnetEvents::NegotiationComplete {
+        option: opt::GMCP,
+        direction: Direction::Local,
+        accepted: true,
+      },
+    ]
+  );
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::WONT, opt::ECHO]),
+    vec![
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::WONT, opt::ECHO)),
+      TelnetEvents::NegotiationComplete {
+        option: opt::ECHO,
+        direction: Direction::Remote,
+        accepted: false,
+      },
+    ]
+  );
+  // Answers are only reported once, and unprompted negotiations aren't reported.
+  assert_eq!(instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]), vec![]);
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO])),
+    events![Event::Send, Event::Negotiation]
+  );
+  // Withdrawing an offer before it's answered forgets it.
+  instance._wont(opt::GMCP).unwrap();
+  instance._will(opt::GMCP).unwrap();
+  instance._wont(opt::GMCP).unwrap();
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::DONT, opt::GMCP])),
+    events![Event::Negotiation]
+  );
+}
+
+#[test]
+fn test_subnegotiation_bytes() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityEntry::new(true, false, true, false).into_u8(),
+  )]));
+  let gmcp = [
+    &[cmd::IAC, cmd::SB, opt::GMCP][..],
+    b"Room.Info {}",
+    &[cmd::IAC, cmd::SE],
+  ]
+  .concat();
+  // Subnegotiations for disabled options are counted too, while data and partial subnegotiations aren't.
+  let msdp = [cmd::IAC, cmd::SB, opt::MSDP, 1, b'a', cmd::IAC, cmd::SE];
+  instance.receive(&[&gmcp[..], b"look", &msdp, &gmcp[..5]].concat());
+  assert_eq!(instance.subnegotiation_bytes(opt::GMCP), gmcp.len() as u64);
+  instance.receive(&gmcp[5..]);
+  assert_eq!(
+    instance.subnegotiation_byte_counts().collect::<Vec<_>>(),
+    vec![(opt::MSDP, 7), (opt::GMCP, 2 * gmcp.len() as u64)]
+  );
+  instance.reset_subnegotiation_bytes();
+  assert_eq!(instance.subnegotiation_bytes(opt::GMCP), 0);
+  assert_eq!(instance.subnegotiation_byte_counts().count(), 0);
+}
+
+#[test]
+fn test_refuse_unexpected_subnegotiations() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityEntry::new(true, false, true, false).into_u8(),
+  )]));
+  let subnegotiation = |option| [cmd::IAC, cmd::SB, option, b'x', cmd::IAC, cmd::SE];
+  instance.set_refuse_unexpected_subnegotiations(true);
+  assert_eq!(
+    instance.receive(&subnegotiation(opt::MSDP)),
+    vec![
+      instance.negotiate(cmd::WONT, opt::MSDP),
+      instance.negotiate(cmd::DONT, opt::MSDP),
+      TelnetEvents::ProtocolError(ProtocolError::UnexpectedSubnegotiation(opt::MSDP)),
+    ]
+  );
+  // Enabled options are unaffected.
+  assert_eq!(
+    handle_events(instance.receive(&subnegotiation(opt::GMCP))),
+    events![Event::Subnegotiation]
+  );
+
+  instance.set_refuse_unexpected_subnegotiations(false);
+  assert_eq!(instance.receive(&subnegotiation(opt::MSDP)), vec![]);
+}
+
+#[test]
+fn test_refuse_local_echo() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::ECHO,
+    CompatibilityTable::ENABLED_LOCAL,
+  )]));
+  instance.set_refuse_local_echo(true);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::DO, opt::ECHO]),
+    vec![instance.negotiate(cmd::WONT, opt::ECHO)]
+  );
+  assert!(!instance.options.get_option(opt::ECHO).local_state);
+
+  instance.set_refuse_local_echo(false);
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::DO, opt::ECHO])),
+    events![Event::Send, Event::Negotiation]
+  );
+  assert!(instance.options.get_option(opt::ECHO).local_state);
+}
+
+#[test]
+fn test_request_environ() {
+  use libmudtelnet::newenviron::{EnvironMessage, VarKind};
+
+  let mut server = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::NEWENVIRON,
+    CompatibilityTable::ENABLED_REMOTE,
+  )]));
+  let names: &[(VarKind, &[u8])] = &[(VarKind::Var, b"USER"), (VarKind::UserVar, b"")];
+  assert_eq!(server.request_environ(names), None);
+  server.receive(&[cmd::IAC, cmd::WILL, opt::NEWENVIRON]);
+  assert_eq!(
+    server.request_environ(names),
+    Some(TelnetEvents::DataSend(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::SB,
+      opt::NEWENVIRON,
+      1,
+      0,
+      b'U',
+      b'S',
+      b'E',
+      b'R',
+      3,
+      cmd::IAC,
+      cmd::SE
+    ])))
+  );
+  let answer = b"\x00\x00USER\x01bob";
+  let events = server.receive(
+    &[
+      &[cmd::IAC, cmd::SB, opt::NEWENVIRON][..],
+      answer,
+      &[cmd::IAC, cmd::SE],
+    ]
+    .concat(),
+  );
+  match &events[..] {
+    [TelnetEvents::Subnegotiation(sub)] => {
+      assert_eq!(sub.option, opt::NEWENVIRON);
+      assert_eq!(
+        EnvironMessage::decode(&sub.buffer),
+        EnvironMessage::decode(answer)
+      );
+    }
+    events => panic!("unexpected events {:?}", events),
+  }
+}
+
+#[test]
+fn test_event_order_invariants() {
+  let alphabet = [
+    cmd::IAC,
+    cmd::SB,
+    cmd::SE,
+    cmd::WILL,
+    cmd::WONT,
+    cmd::DO,
+    cmd::DONT,
+    cmd::GA,
+    opt::MCCP2,
+    opt::ECHO,
+    opt::GMCP,
+    b'a',
+  ];
+  let mut state = 0x2545_f491_4f6c_dd1d_u64;
+  let mut next = || {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    state
+  };
+
+  for _ in 0..2000 {
+    let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+      (
+        opt::MCCP2,
+        CompatibilityEntry::new(true, false, false, false).into_u8(),
+      ),
+      (
+        opt::ECHO,
+        CompatibilityEntry::new(true, true, false, false).into_u8(),
+      ),
+      (
+        opt::GMCP,
+        CompatibilityEntry::new(true, true, false, false).into_u8(),
+      ),
+    ]));
+    for _ in 0..4 {
+      let len = (next() % 24) as usize;
+      let data: Vec<u8> = (0..len)
+        .map(|_| alphabet[(next() % alphabet.len() as u64) as usize])
+        .collect();
+      let batch = instance.receive_batch(&data);
+      let events = batch.events();
+
+      for (index, event) in events.iter().enumerate() {
+        if let TelnetEvents::DecompressImmediate(_) = event {
+          assert_eq!(index, events.len() - 1, "{:?}: {:?}", data, events);
+        }
+      }
+
+      let decompress = batch.decompress_immediate().cloned();
+      let (rest, tail) = batch.into_parts();
+      assert_eq!(tail, decompress);
+      assert!(!rest
+        .iter()
+        .any(|event| matches!(event, TelnetEvents::DecompressImmediate(_))));
+      if tail.is_some() {
+        // The rest of the stream is compressed, start over.
+        break;
+      }
+    }
+  }
+}
+
+#[test]
+fn test_buffer_pool() {
+  use libmudtelnet::bytes::BytesMut;
+  use libmudtelnet::pool::BufferPool;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+
+  #[derive(Default)]
+  struct CountingPool {
+    acquired: AtomicUsize,
+    released: AtomicUsize,
+  }
+
+  impl BufferPool for CountingPool {
+    fn acquire(&self, capacity: usize) -> BytesMut {
+      self.acquired.fetch_add(1, Ordering::SeqCst);
+      BytesMut::with_capacity(capacity)
+    }
+
+    fn release(&self, _buffer: BytesMut) {
+      self.released.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  let pool = Arc::new(CountingPool::default());
+  let mut instance = Parser::with_pool(
+    CompatibilityTable::from_options(&[(
+      opt::GMCP,
+      CompatibilityEntry::new(true, false, true, false).into_u8(),
+    )]),
+    pool.clone(),
+  );
+  assert_eq!(pool.acquired.load(Ordering::SeqCst), 1);
+
+  let mut data = vec![cmd::IAC, cmd::SB, opt::GMCP];
+  data.extend_from_slice(&[b'x'; 300]);
+  assert_eq!(instance.receive(&data), vec![]);
+  // Every buffer but the one in use has been released.
+  assert!(pool.acquired.load(Ordering::SeqCst) > 1);
+  assert_eq!(
+    pool.released.load(Ordering::SeqCst),
+    pool.acquired.load(Ordering::SeqCst) - 1
+  );
+
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::SE])),
+    events![Event::Subnegotiation]
+  );
+  drop(instance);
+  assert_eq!(
+    pool.released.load(Ordering::SeqCst),
+    pool.acquired.load(Ordering::SeqCst)
+  );
+}
+
+#[test]
+fn test_event_size() {
+  use std::mem::size_of;
+
+  // Events are moved around in Vecs and queues on the hot path. Large variants should be boxed so these don't grow.
+  assert!(size_of::<TelnetEvents>() <= size_of::<Bytes>() + 16);
+  assert!(size_of::<TelnetSubnegotiation>() <= size_of::<Bytes>() + 8);
+  assert!(size_of::<(EventMeta, TelnetEvents)>() <= size_of::<TelnetEvents>() + 8);
+}
+
+#[test]
+fn test_msp_mxp() {
+  let mut server = Parser::new();
+  assert_eq!(
+    server.enable_msp(),
+    Some(TelnetEvents::DataSend(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::WILL,
+      opt::MSP
+    ])))
+  );
+  assert_eq!(
+    server.enable_mxp(),
+    Some(TelnetEvents::DataSend(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::WILL,
+      opt::MXP
+    ])))
+  );
+  assert_eq!(server.enable_mxp(), None);
+  // The client accepting MXP is answered with the MXP start sequence.
+  assert_eq!(
+    server.receive(&[cmd::IAC, cmd::DO, opt::MSP, cmd::IAC, cmd::DO, opt::MXP]),
+    vec![
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::MSP)),
+      TelnetEvents::DataSend(Bytes::from_static(&[
+        cmd::IAC,
+        cmd::SB,
+        opt::MXP,
+        cmd::IAC,
+        cmd::SE
+      ])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::MXP)),
+    ]
+  );
+  // MXP has already started, so repeating DO changes nothing.
+  assert_eq!(server.receive(&[cmd::IAC, cmd::DO, opt::MXP]), vec![]);
+
+  // A client asking for MXP before it was offered gets the start sequence with the acceptance.
+  let mut server = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::MXP,
+    CompatibilityEntry::new(true, false, false, false).into_u8(),
+  )]));
+  assert_eq!(
+    server.receive(&[cmd::IAC, cmd::DO, opt::MXP]),
+    vec![
+      TelnetEvents::DataSend(Bytes::from_static(&[
+        cmd::IAC,
+        cmd::WILL,
+        opt::MXP,
+        cmd::IAC,
+        cmd::SB,
+        opt::MXP,
+        cmd::IAC,
+        cmd::SE
+      ])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::MXP)),
+    ]
+  );
+  assert_eq!(server.receive(&[cmd::IAC, cmd::DO, opt::MXP]), vec![]);
+
+  let mut client = Parser::with_support(CompatibilityTable::mud_client());
+  assert_eq!(
+    client.receive(&[cmd::IAC, cmd::WILL, opt::MXP]),
+    vec![
+      TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::DO, opt::MXP])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::WILL, opt::MXP)),
+    ]
+  );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_last_activity() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityEntry::new(true, false, false, false).into_u8(),
+  )]));
+  assert_eq!(instance.last_activity(opt::GMCP), None);
+
+  let before = std::time::Instant::now();
+  instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
+  let negotiated = instance.last_activity(opt::GMCP).unwrap();
+  assert!(negotiated >= before);
+
+  instance.receive(&[cmd::IAC, cmd::SB, opt::GMCP, b'x', cmd::IAC, cmd::SE]);
+  assert!(instance.last_activity(opt::GMCP).unwrap() >= negotiated);
+  assert_eq!(instance.last_activity(opt::NAWS), None);
+}
+
+#[test]
+fn test_prepare_reconnect() {
+  let mut instance = Parser::with_support(CompatibilityTable::mud_client());
+  instance.receive(&[
+    cmd::IAC,
+    cmd::WILL,
+    opt::GMCP,
+    cmd::IAC,
+    cmd::DO,
+    opt::NAWS,
+    cmd::IAC,
+    cmd::WILL,
+    opt::MSP,
+    cmd::IAC,
+    cmd::SB,
+    opt::GMCP,
+  ]);
+  instance.set_carry_over(opt::MSP, false);
+
+  let enabled = instance.prepare_reconnect();
+  assert_eq!(
+    enabled,
+    EnabledOptions {
+      local: vec![opt::NAWS],
+      remote: vec![opt::GMCP],
+    }
+  );
+  assert_eq!(instance.stream_offset(), 0);
+  for option in [opt::GMCP, opt::NAWS, opt::MSP] {
+    let entry = instance.options.get_option(option);
+    assert!(!entry.local_state && !entry.remote_state);
+  }
+  // The partial subnegotiation from the old connection is gone.
+  assert_eq!(
+    instance.receive(b"hello"),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"hello"))]
+  );
+
+  assert_eq!(
+    instance.reoffer(&enabled),
+    vec![
+      TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::WILL, opt::NAWS])),
+      TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::DO, opt::GMCP])),
+    ]
+  );
+}
+
+#[test]
+fn test_keepalive_filter() {
+  let data = [
+    &[cmd::IAC, cmd::NOP][..],
+    b"\r\n",
+    &[cmd::IAC, cmd::WONT, opt::ECHO, cmd::IAC, cmd::GA],
+    b"\r\nprompt>",
+  ]
+  .concat();
+  let mut instance = Parser::new();
+  assert_eq!(
+    handle_events(instance.receive(&data)),
+    events![
+      Event::Iac,
+      Event::Recv,
+      Event::Negotiation,
+      Event::Iac,
+      Event::Recv
+    ]
+  );
+
+  // The line endings after the previous read's prompt end its line, so they aren't left out.
+  instance.set_keepalive_filter(KeepaliveFilter::all());
+  assert_eq!(
+    instance.receive(&data),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"\r\n")),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+      TelnetEvents::DataReceive(Bytes::from_static(b"\r\nprompt>")),
+    ]
+  );
+
+  let mut instance = Parser::new();
+  instance.set_keepalive_filter(KeepaliveFilter::all());
+  assert_eq!(
+    instance.receive(&data),
+    vec![
+      TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+      TelnetEvents::DataReceive(Bytes::from_static(b"\r\nprompt>")),
+    ]
+  );
+  assert_eq!(
+    instance.receive(&[&b"\r\n"[..], &[cmd::IAC, cmd::NOP], b"\r\n"].concat()),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"\r\n"))]
+  );
+
+  // Negotiations that change state are still reported.
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::ECHO,
+    CompatibilityEntry::new(false, true, false, true).into_u8(),
+  )]));
+  instance.set_keepalive_filter(KeepaliveFilter::all());
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::WONT, opt::ECHO])),
+    events![Event::Send, Event::Negotiation]
+  );
+  assert_eq!(instance.receive(&[cmd::IAC, cmd::WONT, opt::ECHO]), vec![]);
+}
+
+#[test]
+fn test_passthrough() {
+  let mut instance = Parser::new();
+  assert!(!instance.passthrough());
+  assert_eq!(
+    instance.receive(&[b'a', cmd::IAC, cmd::IAC, cmd::IAC, cmd::SB]),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"a")),
+      TelnetEvents::DataReceive(Bytes::from_static(&[cmd::IAC, cmd::IAC])),
+    ]
+  );
+
+  // The buffered start of a subnegotiation is passed through as well.
+  instance.set_passthrough(true);
+  let binary = [0, cmd::IAC, cmd::GA, cmd::IAC, cmd::IAC, 1];
+  assert_eq!(
+    instance.receive(&binary),
+    vec![TelnetEvents::DataReceive(Bytes::from(
+      [&[cmd::IAC, cmd::SB][..], &binary].concat()
+    ))]
+  );
+  assert_eq!(instance.stream_offset(), 11);
+
+  instance.set_passthrough(false);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::GA]),
+    vec![TelnetEvents::IAC(TelnetIAC::new(cmd::GA))]
+  );
+}
+
+#[test]
+fn test_transfer_detection() {
+  let mut instance = Parser::new();
+  assert_eq!(
+    handle_events(instance.receive(b"rz waiting\r\n**\x18B00000000")),
+    events![Event::Recv]
+  );
+
+  instance.set_transfer_detection(true);
+  let transfer = b"**\x18B0100000023be50\r\x8a\x11";
+  assert_eq!(
+    instance.receive(&[b"rz ready\r\n", &transfer[..], &[cmd::IAC, cmd::IAC]].concat()),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"rz ready\r\n")),
+      TelnetEvents::FileTransferDetected(TransferProtocol::Zmodem),
+      TelnetEvents::DataReceive(Bytes::from_static(transfer)),
+      TelnetEvents::DataReceive(Bytes::from_static(&[cmd::IAC, cmd::IAC])),
+    ]
+  );
+  assert!(instance.passthrough());
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::GA]),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::GA
+    ]))]
+  );
+
+  // A signature split across reads is repeated in full at the start of the transfer.
+  instance.set_passthrough(false);
+  assert_eq!(
+    instance.receive(b"**\x18"),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"**\x18"))]
+  );
+  assert_eq!(
+    instance.receive(b"B00000"),
+    vec![
+      TelnetEvents::FileTransferDetected(TransferProtocol::Zmodem),
+      TelnetEvents::DataReceive(Bytes::from_static(b"**\x18B00000")),
+    ]
+  );
+}
+
+#[test]
+fn test_event_display() {
+  let events = [
+    (TelnetEvents::IAC(TelnetIAC::new(cmd::GA)), "IAC GA"),
+    (TelnetEvents::IAC(TelnetIAC::new(17)), "IAC 17"),
+    (
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::WILL, opt::GMCP)),
+      "IAC WILL GMCP",
+    ),
+    (
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, 200)),
+      "IAC DO 200",
+    ),
+    (
+      TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+        opt::GMCP,
+        Bytes::from_static(b"Char.Vitals {\"hp\": 1}\xff\xff"),
+      )),
+      r#"IAC SB GMCP "Char.Vitals {\"hp\": 1}\xff\xff" IAC SE"#,
+    ),
+    (
+      TelnetEvents::Gmcp(Box::new(GmcpMessage::new("Core.Ping", None))),
+      r#"IAC SB GMCP "Core.Ping" IAC SE"#,
+    ),
+    (
+      TelnetEvents::DataReceive(Bytes::from_static(b"hp\r\n")),
+      "DataReceive 4 bytes: 68 70 0d 0a |hp..|",
+    ),
+    (
+      TelnetEvents::DecryptionChanged(true),
+      "DecryptionChanged true",
+    ),
+    (
+      TelnetEvents::NegotiationComplete {
+        option: opt::NAWS,
+        direction: Direction::Local,
+        accepted: false,
+      },
+      "NegotiationComplete NAWS Local refused",
+    ),
+    (
+      TelnetEvents::OptionStateChanged {
+        option: opt::ECHO,
+        before: CompatibilityEntry::new(false, true, false, false),
+        after: CompatibilityEntry::new(false, true, false, true),
+      },
+      "OptionStateChanged ECHO remote enabled",
+    ),
+    (TelnetEvents::ShutdownComplete, "ShutdownComplete"),
+  ];
+  for (event, expected) in events {
+    assert_eq!(event.to_string(), expected);
+  }
+
+  let long = TelnetEvents::DataSend(Bytes::from(vec![b'a'; 40]));
+  assert_eq!(
+    long.to_string(),
+    format!(
+      "DataSend 40 bytes:{} ... |{}|",
+      " 61".repeat(32),
+      "a".repeat(32)
+    )
+  );
+  assert_eq!(
+    format!("{:#}", long),
+    format!(
+      "DataSend 40 bytes:{} |{}|",
+      " 61".repeat(40),
+      "a".repeat(40)
+    )
+  );
+  let long = TelnetSubnegotiation::new(opt::GMCP, Bytes::from(vec![b'a'; 40]));
+  assert_eq!(
+    long.to_string(),
+    format!("IAC SB GMCP \"{}\"... IAC SE", "a".repeat(32))
+  );
+}
+
+#[test]
+fn test_slice_accessors() {
+  let mut instance = Parser::new();
+  let events = instance.receive(b"hp 10\xff\xf9");
+  assert_eq!(events[0].data(), Some(&b"hp 10"[..]));
+  assert_eq!(events[1].data(), None);
+  let bytes: Vec<u8> = events[1].clone().into();
+  assert_eq!(bytes, vec![cmd::IAC, cmd::GA]);
+
+  let send: Vec<u8> = instance.send_text("look").into();
+  assert_eq!(send, b"look\r\n");
+}
+
+/// Test escaping IAC bytes in a buffer.
+#[test]
+fn test_escape() {
+  let initial = vec![
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::SE,
+  ];
+  let expected = Bytes::copy_from_slice(&[
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SE,
+  ]);
+  assert_eq!(expected, Parser::escape_iac(initial.clone()));
+  assert_eq!(initial, Parser::unescape_iac(expected));
+}
+
+/// Test unescaping IAC bytes in a buffer.
+#[test]
+fn test_unescape() {
+  let initial = vec![
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SE,
+  ];
+  let expected = Bytes::copy_from_slice(&[
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::SE,
+  ]);
+  assert_eq!(expected, Parser::unescape_iac(initial.clone()));
+  assert_eq!(initial, Parser::escape_iac(expected));
+}
+
+#[test]
+fn test_escape_roundtrip_bug_one() {
+  // The original libtelnet-rs mishandles this input:
+  let data = vec![cmd::IAC, cmd::IAC, 228];
+  let escaped = Parser::escape_iac(data.clone());
+  let unescaped = Parser::unescape_iac(escaped);
+  assert_eq!(unescaped, data);
+}
+
+#[test]
+fn test_escape_roundtrip_bug_two() {
+  // The original libtelnet-rs mishandles this input:
+  let data = vec![228, cmd::IAC, cmd::IAC];
+  let escaped = Parser::escape_iac(data.clone());
+  let unescaped = Parser::unescape_iac(escaped);
+  assert_eq!(unescaped, data);
+}
+
+#[test]
+fn test_bad_subneg_dbuffer() {
+  // Configure opt 0xFF (IAC) as local supported, and local state enabled.
+  let entry = CompatibilityEntry::new(true, false, true, false);
+  let opts = CompatibilityTable::from_options(&[(cmd::IAC, entry.into_u8())]);
+  // Receive a malformed subnegotiation - this should not panic.
+  Parser::with_support(opts).receive(&[cmd::IAC, cmd::SB, cmd::IAC, cmd::SE]);
+}
+
+#[test]
+fn test_escaped_se_in_subnegotiation() {
+  // `IAC IAC SE` is an escaped IAC followed by SE, which doesn't end the subnegotiation, however the reads are split.
+  let entry = CompatibilityEntry::new(true, false, true, false);
+  let opts = CompatibilityTable::from_options(&[(opt::GMCP, entry.into_u8())]);
+  let stream = [
+    cmd::IAC,
+    cmd::SB,
+    opt::GMCP,
+    b'a',
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SE,
+    b'b',
+    cmd::IAC,
+    cmd::SE,
+    b'c',
+  ];
+  let expected = vec![
+    TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(&[b'a', cmd::IAC, cmd::IAC, cmd::SE, b'b']),
+    )),
+    TelnetEvents::DataReceive(Bytes::from_static(b"c")),
+  ];
+  for split in 0..stream.len() {
+    let mut instance = Parser::with_support(opts.clone());
+    let mut events = instance.receive(&stream[..split]);
+    events.extend(instance.receive(&stream[split..]));
+    assert_eq!(events, expected, "split at {split}");
+  }
+}
+
+#[test]
+fn test_subnegotiation_bytes_and_decode_policy() {
+  let entry = CompatibilityEntry::new(true, false, true, false);
+  let opts = CompatibilityTable::from_options(&[(opt::GMCP, entry.into_u8())]);
+  let received = [
+    cmd::IAC,
+    cmd::SB,
+    opt::GMCP,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SE,
+  ];
+
+  // The raw payload keeps its escaping, which `TelnetSubnegotiation::to_bytes` escapes again, while the event's
+  // bytes are the bytes received.
+  let mut instance = Parser::with_support(opts.clone());
+  let event = instance.receive(&received).remove(0);
+  let TelnetEvents::Subnegotiation(subnegotiation) = event.clone() else {
+    panic!("expected a subnegotiation, got {:?}", event);
+  };
+  assert_eq!(
+    subnegotiation.to_bytes(),
+    Bytes::from_static(&[
+      cmd::IAC,
+      cmd::SB,
+      opt::GMCP,
+      cmd::IAC,
+      cmd::IAC,
+      cmd::IAC,
+      cmd::IAC,
+      cmd::IAC,
+      cmd::SE
+    ])
+  );
+  assert_eq!(Bytes::from(event), &received[..]);
+
+  let mut instance = Parser::with_support(opts);
+  instance.set_decode_policy(opt::GMCP, DecodePolicy::Unescape);
+  let event = instance.receive(&received).remove(0);
+  assert_eq!(Bytes::from(event), &received[..]);
+}
+
+#[test]
+fn test_split_subneg_for_iac() {
+  // `IAC SE` right after `IAC SB` is option 255 followed by data, however the reads are split.
+  let entry = CompatibilityEntry::new(true, false, true, false);
+  let opts = CompatibilityTable::from_options(&[(cmd::IAC, entry.into_u8())]);
+  let expected = vec![TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+    cmd::IAC,
+    Bytes::from_static(&[cmd::SE, b'x']),
+  ))];
+  let mut instance = Parser::with_support(opts);
+  assert_eq!(
+    instance.receive(&[
+      cmd::IAC,
+      cmd::SB,
+      cmd::IAC,
+      cmd::SE,
+      b'x',
+      cmd::IAC,
+      cmd::SE
+    ]),
+    expected
+  );
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::SB, cmd::IAC, cmd::SE]),
+    vec![]
+  );
+  assert_eq!(instance.receive(&[b'x', cmd::IAC, cmd::SE]), expected);
+}
+
+#[test]
+fn test_mccp_subneg_when_disabled() {
+  // Data following the start of compression is only handed over for decompression if MCCP2 is enabled.
+  let mut instance = Parser::new();
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::SB, opt::MCCP2, cmd::IAC, cmd::SE, b'h', b'i']),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"hi"))]
+  );
+}
+
+#[test]
+fn test_into_bytes() {
+  let bytes = libmudtelnet::events::TelnetIAC::new(cmd::IAC).to_bytes();
+  assert!(!bytes.is_empty())
+}
+
+#[test]
+fn test_iac_command() {
+  let mut instance = Parser::new();
+  let commands = [
+    cmd::GA,
+    cmd::EOR,
+    cmd::NOP,
+    cmd::DM,
+    cmd::BRK,
+    cmd::IP,
+    cmd::AO,
+    cmd::AYT,
+    cmd::EC,
+    cmd::EL,
+  ];
+  let stream: Vec<u8> = commands
+    .iter()
+    .flat_map(|&command| [cmd::IAC, command, b'x'])
+    .collect();
+  let events = instance.receive(&stream);
+  let kinds: Vec<IacCommand> = events
+    .iter()
+    .filter_map(|ev| match ev {
+      TelnetEvents::IAC(iac) => Some(iac.kind()),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(
+    kinds,
+    vec![
+      IacCommand::GoAhead,
+      IacCommand::Eor,
+      IacCommand::Nop,
+      IacCommand::DataMark,
+      IacCommand::Break,
+      IacCommand::InterruptProcess,
+      IacCommand::AbortOutput,
+      IacCommand::AreYouThere,
+      IacCommand::EraseChar,
+      IacCommand::EraseLine,
+    ]
+  );
+  // The byte following each command is data, not an option code.
+  assert_eq!(events.len(), commands.len() * 2);
+  assert!(events
+    .iter()
+    .skip(1)
+    .step_by(2)
+    .all(|event| *event == TelnetEvents::DataReceive(Bytes::from_static(b"x"))));
+  assert_eq!(TelnetIAC::new(200).kind(), IacCommand::Other(200));
+  assert_eq!(
+    TelnetIAC::from(IacCommand::EraseLine).to_bytes(),
+    Bytes::from_static(&[cmd::IAC, cmd::EL])
+  );
+}
+
+#[cfg(test)]
+mod compat_tests {
+  use compat::{test_app, TelnetApplication};
+
+  #[test]
+  fn test_parser_diff1() {
+    test_app(&TelnetApplication {
+      options: vec![(255, 254)],
+      received_data: vec![vec![255, 255, 255, 255, 255, 254, 255, 0]],
+      chunk_seed: 0,
+    });
+  }
+
+  #[test]
+  fn test_parser_diff2() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![45, 255, 250, 255]],
+      chunk_seed: 0,
+    });
+  }
+
+  #[test]
+  fn test_parser_diff3() {
+    test_app(&TelnetApplication {
+      options: vec![(0, 1)],
+      received_data: vec![vec![255, 253, 0]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff4() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 250, 255, 255, 240, 250]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff5() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 250, 255, 240, 0]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff6() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![240, 255, 250, 255, 240, 0]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff7() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff8() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 252, 0]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff9() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![254, 255, 255, 255, 254, 0]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff10() {
+    test_app(&TelnetApplication {
+      options: vec![(255, 254), (1, 0)],
+      received_data: vec![vec![255, 253, 255]],
+      chunk_seed: 0,
+    })
+  }
+
+  #[test]
+  fn test_parser_diff11() {
+    test_app(&TelnetApplication {
+      options: vec![(1, 15)],
+      received_data: vec![vec![255], vec![251, 1, 255, 255], vec![0]],
+      chunk_seed: 0,
+    });
+  }
+
+  #[test]
+  fn test_parser_diff12() {
+    test_app(&TelnetApplication {
+      options: vec![(91, 1)],
+      received_data: vec![vec![255, 253, 91], vec![255, 253, 91]],
+      chunk_seed: 0,
+    });
+  }
+
+  #[test]
+  fn test_parser_diff13() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 242, 65, 255, 246, 66, 255, 240, 67]],
+      chunk_seed: 0,
+    });
+  }
+
+  #[test]
+  fn test_parser_diff14() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 250, 86, 255, 240, 0]],
+      chunk_seed: 0,
+    });
+  }
+
+  #[test]
+  fn test_parser_diff15() {
+    test_app(&TelnetApplication {
+      options: vec![(1, 15)],
+      received_data: vec![vec![255, 250, 1, 255, 255, 240, 255, 240]],
+      chunk_seed: 0,
+    });
+  }
+}
+
+#[test]
+fn test_gmcp_supports() {
+  let gmcp = |payload: &[u8]| {
+    [
+      &[cmd::IAC, cmd::SB, opt::GMCP][..],
+      payload,
+      &[cmd::IAC, cmd::SE],
+    ]
+    .concat()
+  };
+  let supports = b"Core.Supports.Set [\"Char 1\", \"Room 1\"]";
+
+  let mut server = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityTable::ENABLED_LOCAL,
+  )]));
+  // Ignored until GMCP is enabled.
+  server.receive(&gmcp(supports));
+  assert!(!server.gmcp_supports("Char"));
+  server.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
+  server.receive(&gmcp(supports));
+  server.receive(&gmcp(b"Core.Supports.Remove [\"Room\"]"));
+  assert!(server.gmcp_supports("Char 1"));
+  assert!(!server.gmcp_supports("Char 2"));
+  assert!(!server.gmcp_supports("Room"));
+  assert!(server.gmcp_packages(Direction::Local).is_empty());
+  server.prepare_reconnect();
+  assert!(!server.gmcp_supports("Char"));
+
+  let mut client = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityTable::ENABLED_LOCAL,
+  )]));
+  client.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
+  assert!(client
+    .subnegotiation(opt::GMCP, Bytes::from_static(supports))
+    .is_some());
+  assert_eq!(
+    client
+      .gmcp_packages(Direction::Local)
+      .iter()
+      .collect::<Vec<_>>(),
+    [("Char", 1), ("Room", 1)]
+  );
+  assert!(!client.gmcp_supports("Char"));
+}
+
+#[test]
+fn test_msdp_preference() {
+  use libmudtelnet::msdp::MsdpChannel;
+
+  let msdp = [
+    cmd::IAC,
+    cmd::SB,
+    opt::MSDP,
+    1,
+    b'H',
+    b'P',
+    2,
+    b'1',
+    cmd::IAC,
+    cmd::SE,
+  ];
+  let gmcp = [
+    &[cmd::IAC, cmd::SB, opt::GMCP][..],
+    b"MSDP {\"HP\":\"1\"}",
+    &[cmd::IAC, cmd::SE],
+  ]
+  .concat();
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (opt::MSDP, CompatibilityTable::ENABLED_LOCAL),
+    (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+  ]));
+  instance.set_msdp_preference(Some(MsdpChannel::Gmcp));
+  assert_eq!(instance.msdp_channel(), None);
+  instance.receive(&[cmd::IAC, cmd::DO, opt::MSDP]);
+  assert_eq!(instance.msdp_channel(), Some(MsdpChannel::Msdp));
+  // Only one channel is enabled, so nothing is a duplicate.
+  assert_eq!(
+    handle_events(instance.receive(&msdp)),
+    events![Event::Subnegotiation]
+  );
+
+  instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
+  assert_eq!(instance.msdp_channel(), Some(MsdpChannel::Gmcp));
+  assert_eq!(instance.receive(&msdp), vec![]);
+  assert_eq!(
+    handle_events(instance.receive(&gmcp)),
+    events![Event::Subnegotiation]
+  );
+
+  instance.set_msdp_preference(Some(MsdpChannel::Msdp));
+  assert_eq!(instance.msdp_channel(), Some(MsdpChannel::Msdp));
+  assert_eq!(instance.receive(&gmcp), vec![]);
+  // Other GMCP packages are unaffected.
+  assert_eq!(
+    handle_events(
+      instance.receive(
+        &[
+          &[cmd::IAC, cmd::SB, opt::GMCP][..],
+          b"Char.Vitals {}",
+          &[cmd::IAC, cmd::SE]
+        ]
+        .concat()
+      )
+    ),
+    events![Event::Subnegotiation]
+  );
+
+  instance.set_msdp_preference(None);
+  assert_eq!(
+    handle_events(instance.receive(&[&msdp[..], &gmcp].concat())),
+    events![Event::Subnegotiation, Event::Subnegotiation]
+  );
+}
+
+#[test]
+fn test_begin_shutdown() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (opt::ECHO, CompatibilityTable::ENABLED_LOCAL),
+    (opt::NAWS, CompatibilityTable::ENABLED_REMOTE),
+    (opt::MCCP2, CompatibilityTable::ENABLED_LOCAL),
+    (opt::SGA, CompatibilityTable::ENABLED_LOCAL),
+  ]));
+  instance.receive(&[
+    cmd::IAC,
+    cmd::DO,
+    opt::ECHO,
+    cmd::IAC,
+    cmd::WILL,
+    opt::NAWS,
+    cmd::IAC,
+    cmd::DO,
+    opt::MCCP2,
+  ]);
+  instance.set_half_duplex(true);
+  instance.send_output(b"a");
+  instance.send_output(b"b");
+  assert_eq!(
+    instance.begin_shutdown(),
+    vec![
+      TelnetEvents::DataSend(Bytes::from_static(&[b'b', cmd::IAC, cmd::GA])),
+      instance.negotiate(cmd::WONT, opt::ECHO),
+      instance.negotiate(cmd::DONT, opt::NAWS),
+      TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::GA])),
+      TelnetEvents::ShutdownComplete,
+    ]
+  );
+  assert!(!instance.options.get_option(opt::ECHO).local_state);
+  assert!(!instance.options.get_option(opt::NAWS).remote_state);
+  assert!(instance.options.get_option(opt::MCCP2).local_state);
+
+  // No final GA while SUPPRESS-GO-AHEAD is enabled.
+  instance.receive(&[cmd::IAC, cmd::DO, opt::SGA]);
+  assert_eq!(
+    instance.begin_shutdown(),
+    vec![
+      instance.negotiate(cmd::WONT, opt::SGA),
+      TelnetEvents::ShutdownComplete,
+    ]
+  );
+}
+
+#[test]
+fn test_finish() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityTable::ENABLED_LOCAL,
+  )]));
+  assert_eq!(instance.finish(), vec![]);
+
+  instance.feed(b"Name? ");
+  assert_eq!(
+    instance.finish(),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"Name? "))]
+  );
+
+  instance.receive(&[cmd::IAC, cmd::SB, opt::GMCP, b'C', b'o']);
+  assert_eq!(
+    instance.finish(),
+    vec![TelnetEvents::ProtocolError(
+      ProtocolError::UnterminatedSubnegotiation(opt::GMCP)
+    )]
+  );
+  // The discarded subnegotiation doesn't swallow later data.
+  assert_eq!(
+    instance.receive(b"hi"),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"hi"))]
+  );
+
+  for partial in [
+    &[cmd::IAC][..],
+    &[cmd::IAC, cmd::WILL],
+    &[cmd::IAC, cmd::SB],
+  ] {
+    assert_eq!(instance.receive(partial), vec![]);
+    assert_eq!(
+      instance.finish(),
+      vec![TelnetEvents::ProtocolError(ProtocolError::TruncatedCommand)]
+    );
+  }
+
+  instance.set_record_mode(true);
+  instance.receive(&[0xf5, cmd::IAC]);
+  assert_eq!(
+    instance.finish(),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(&[0xf5])),
+      TelnetEvents::ProtocolError(ProtocolError::TruncatedCommand),
+    ]
+  );
+}
+
+#[test]
+fn test_subnegotiation_limit() {
+  let too_long = TelnetEvents::ProtocolError(ProtocolError::SubnegotiationTooLong(opt::GMCP));
+  let subneg = |payload: &[u8]| {
+    [
+      &[cmd::IAC, cmd::SB, opt::GMCP][..],
+      payload,
+      &[cmd::IAC, cmd::SE],
+    ]
+    .concat()
+  };
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::GMCP,
+    CompatibilityTable::ENABLED_LOCAL,
+  )]));
+  instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
+  instance.set_subnegotiation_limit(Some(10));
+  assert_eq!(
+    handle_events(instance.receive(&subneg(b"12345"))),
+    events![Event::Subnegotiation]
+  );
+  assert_eq!(
+    instance.receive(&[&subneg(b"123456")[..], b"ok"].concat()),
+    vec![
+      too_long.clone(),
+      TelnetEvents::DataReceive(Bytes::from_static(b"ok"))
+    ]
+  );
+
+  // An unterminated subnegotiation stops growing the buffer once it's over the limit.
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::SB, opt::GMCP, b'1', b'2']),
+    vec![]
+  );
+  assert_eq!(instance.receive(b"3456789"), vec![too_long.clone()]);
+  for _ in 0..100 {
+    assert_eq!(instance.receive(&[b'x'; 64]), vec![]);
+  }
+  assert_eq!(instance.receive(&[b'x', cmd::IAC]), vec![]);
+  assert_eq!(
+    instance.receive(&[cmd::SE, b'o', b'k']),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"ok"))]
+  );
+
+  // An escaped IAC isn't mistaken for the start of IAC SE.
+  instance.receive(&subneg(b"1234567")[..10]);
+  assert_eq!(instance.receive(&[cmd::IAC, cmd::IAC]), vec![too_long]);
+  assert_eq!(instance.receive(&[cmd::SE]), vec![]);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::SE, b'o', b'k']),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"ok"))]
+  );
+}
+
+#[test]
+fn test_subnegotiation_limit_mccp() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::MCCP2,
+    CompatibilityTable::ENABLED_LOCAL,
+  )]));
+  instance.receive(&[cmd::IAC, cmd::DO, opt::MCCP2]);
+  instance.set_subnegotiation_limit(Some(4));
+  let too_long = TelnetEvents::ProtocolError(ProtocolError::SubnegotiationTooLong(opt::MCCP2));
+  let compressed = TelnetEvents::DecompressImmediate(Bytes::from_static(b"zz"));
+  // The payload of a start of compression over the limit is dropped, but not the compressed data after it.
+  assert_eq!(
+    instance.receive(&[
+      cmd::IAC,
+      cmd::SB,
+      opt::MCCP2,
+      b'1',
+      b'2',
+      cmd::IAC,
+      cmd::SE,
+      b'z',
+      b'z'
+    ]),
+    vec![too_long.clone(), compressed.clone()]
+  );
+  // Likewise once the rest of a truncated one arrives.
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::SB, opt::MCCP2, b'1', b'2']),
+    vec![too_long]
+  );
+  assert_eq!(
+    instance.receive(&[b'3', cmd::IAC, cmd::SE, b'z', b'z']),
+    vec![compressed]
+  );
+}
+
+#[test]
+fn test_option_change_events() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::ECHO,
+    CompatibilityTable::ENABLED_REMOTE,
+  )]));
+  instance.set_option_change_events(true);
+  let disabled = CompatibilityEntry::new(false, true, false, false);
+  let enabled = CompatibilityEntry::new(false, true, false, true);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO]),
+    vec![
+      instance.negotiate(cmd::DO, opt::ECHO),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::WILL, opt::ECHO)),
+      TelnetEvents::OptionStateChanged {
+        option: opt::ECHO,
+        before: disabled,
+        after: enabled,
+      },
+    ]
+  );
+  // Repeated and refused negotiations don't change anything.
+  assert_eq!(
+    handle_events(instance.receive(&[
+      cmd::IAC,
+      cmd::WILL,
+      opt::ECHO,
+      cmd::IAC,
+      cmd::DO,
+      opt::NAWS
+    ])),
+    events![Event::Send]
+  );
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::WONT, opt::ECHO])),
+    events![Event::Send, Event::Negotiation, Event::OptionStateChanged]
+  );
+  instance.set_option_change_events(false);
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO])),
+    events![Event::Send, Event::Negotiation]
+  );
+}
+
+#[test]
+#[cfg(target_has_atomic = "ptr")]
+fn test_shared_options() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::ECHO,
+    CompatibilityTable::ENABLED_REMOTE,
+  )]));
+  let shared = instance.shared_options();
+  assert_eq!(shared.version(), 0);
+  assert!(!shared.get_option(opt::ECHO).remote_state);
+  instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO]);
+  // Readers on other threads see the negotiated state without borrowing the parser.
+  let reader = {
+    let shared = shared.clone();
+    std::thread::spawn(move || (shared.get_option(opt::ECHO), shared.version()))
+  };
+  let (entry, version) = reader.join().unwrap();
+  assert!(entry.remote_state);
+  assert_eq!(version, 1);
+  // Repeated negotiations don't change the table.
+  instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO]);
+  assert_eq!(shared.version(), 1);
+  // Direct writes are published by the next call.
+  instance.options.reset_states();
+  assert!(shared.get_option(opt::ECHO).remote_state);
+  let again = instance.shared_options();
+  assert!(!again.get_option(opt::ECHO).remote_state);
+  assert_eq!(shared.version(), 2);
+  assert_eq!(
+    shared.snapshot().get_option(opt::ECHO),
+    instance.options.get_option(opt::ECHO)
+  );
+}
+
+#[test]
+fn test_outgoing_subnegotiation_limit() {
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+    (opt::MSDP, CompatibilityTable::ENABLED_LOCAL),
+    (opt::NAWS, CompatibilityTable::ENABLED_LOCAL),
+  ]));
+  assert_eq!(
+    instance.try_subnegotiation(opt::GMCP, &b"Core.Ping"[..]),
+    Err(SubnegotiationRefused::NotEnabled)
+  );
+  instance._will(opt::GMCP);
+  instance._will(opt::MSDP);
+  instance.set_outgoing_subnegotiation_limit(Some(8), OversizedSubnegotiation::Refuse);
+  assert!(instance
+    .try_subnegotiation(opt::GMCP, &b"Core.Ping"[..8])
+    .is_ok());
+  let refused = instance.try_subnegotiation(opt::GMCP, &b"Core.Ping"[..]);
+  assert_eq!(
+    refused,
+    Err(SubnegotiationRefused::TooLong {
+      length: 9,
+      limit: 8
+    })
+  );
+  assert_eq!(
+    refused.unwrap_err().to_string(),
+    "subnegotiation of 9 bytes is over the limit of 8"
+  );
+  assert_eq!(instance.subnegotiation(opt::GMCP, &b"Core.Ping"[..]), None);
+  // MSDP payloads are split between top level variables, keeping tables whole.
+  instance.set_outgoing_subnegotiation_limit(Some(9), OversizedSubnegotiation::Split);
+  let payload = b"\x01A\x02a\x01B\x02\x03\x01C\x02c\x04\x01D\x02d";
+  assert_eq!(
+    instance.try_subnegotiation(opt::MSDP, &payload[..]),
+    Ok(TelnetEvents::DataSend(Bytes::from_static(
+      b"\xff\xfa\x45\x01A\x02a\xff\xf0\
+        \xff\xfa\x45\x01B\x02\x03\x01C\x02c\x04\xff\xf0\
+        \xff\xfa\x45\x01D\x02d\xff\xf0"
+    )))
+  );
+  // Unless a single variable is over the limit, or the option can't be split.
+  instance.set_outgoing_subnegotiation_limit(Some(8), OversizedSubnegotiation::Split);
+  assert_eq!(
+    instance.try_subnegotiation(opt::MSDP, &payload[..]),
+    Err(SubnegotiationRefused::TooLong {
+      length: payload.len(),
+      limit: 8
+    })
+  );
+  assert_eq!(instance.subnegotiation(opt::GMCP, &b"Core.Ping"[..]), None);
+  instance.set_outgoing_subnegotiation_limit(None, OversizedSubnegotiation::Refuse);
+  assert!(instance.subnegotiation(opt::MSDP, &payload[..]).is_some());
+}
+
+/// Events describing received data, in the forms `testing::assert_event_roundtrip` documents as round tripping.
+#[cfg(feature = "testing")]
+fn received_event() -> impl proptest::strategy::Strategy<Value = TelnetEvents> {
+  use proptest::prelude::*;
+
+  let payload = || prop::collection::vec(any::<u8>(), 0..32);
+  prop_oneof![
+    prop::sample::select(vec![
+      cmd::GA,
+      cmd::EOR,
+      cmd::NOP,
+      cmd::DM,
+      cmd::BRK,
+      cmd::IP,
+      cmd::AO,
+      cmd::AYT,
+      cmd::EC,
+      cmd::EL
+    ])
+    .prop_map(|command| TelnetEvents::IAC(TelnetIAC::new(command))),
+    (
+      prop::sample::select(vec![cmd::WILL, cmd::WONT, cmd::DO, cmd::DONT]),
+      any::<u8>()
+    )
+      .prop_map(
+        |(command, option)| TelnetEvents::Negotiation(TelnetNegotiation::new(command, option))
+      ),
+    (
+      any::<u8>().prop_filter("starts compression", |&option| {
+        option != opt::MCCP2 && option != opt::MCCP3
+      }),
+      payload()
+    )
+      .prop_map(
+        |(option, payload)| TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+          option,
+          Parser::escape_iac(payload)
+        ))
+      ),
+    prop::collection::vec(any::<u8>(), 1..32)
+      .prop_map(|data| TelnetEvents::DataReceive(Parser::escape_iac(data))),
+    payload().prop_map(|data| TelnetEvents::Record(data.into())),
+  ]
+}
+
+#[cfg(feature = "testing")]
+proptest::proptest! {
+  #[test]
+  fn test_event_roundtrip(event in received_event()) {
+    libmudtelnet::testing::assert_event_roundtrip(&event);
+  }
+}
+
+#[test]
+fn test_strip_go_ahead() {
+  let data = [
+    &b"prompt>"[..],
+    &[cmd::IAC, cmd::GA, cmd::IAC, cmd::NOP],
+    b"hp>",
+    &[cmd::IAC, cmd::EOR],
+  ]
+  .concat();
+  let mut instance = Parser::new();
+  instance.set_strip_go_ahead(true);
+  assert_eq!(
+    instance.receive(&data),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"prompt>")),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::NOP)),
+      TelnetEvents::DataReceive(Bytes::from_static(b"hp>")),
+    ]
+  );
+  // Records still end at IAC EOR.
+  instance.set_record_mode(true);
+  assert_eq!(
+    instance.receive(&data[data.len() - 5..]),
+    vec![TelnetEvents::Record(Bytes::from_static(b"hp>"))]
+  );
+  instance.set_record_mode(false);
+  instance.set_strip_go_ahead(false);
+  assert_eq!(
+    handle_events(instance.receive(&data)),
+    events![Event::Recv, Event::Iac, Event::Iac, Event::Recv, Event::Iac]
+  );
+}
+
+#[test]
+fn test_set_terminal_types() {
+  let send = [cmd::IAC, cmd::SB, opt::TTYPE, 1, cmd::IAC, cmd::SE];
+  let is = |name: &[u8]| {
+    TelnetEvents::DataSend(Bytes::from(
+      [
+        &[cmd::IAC, cmd::SB, opt::TTYPE, 0][..],
+        name,
+        &[cmd::IAC, cmd::SE],
+      ]
+      .concat(),
+    ))
+  };
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[(
+    opt::TTYPE,
+    CompatibilityTable::ENABLED_LOCAL,
+  )]));
+  instance.set_terminal_types(&["BLIGHTMUD", "XTERM-256COLOR", "MTTS 2831"]);
+  // Not answered until TTYPE is enabled.
+  assert_eq!(instance.receive(&send), vec![]);
+  instance.receive(&[cmd::IAC, cmd::DO, opt::TTYPE]);
+  let mut answers = Vec::new();
+  for _ in 0..5 {
+    answers.extend(instance.receive(&send));
+  }
+  assert_eq!(
+    answers,
+    vec![
+      is(b"BLIGHTMUD"),
+      is(b"XTERM-256COLOR"),
+      is(b"MTTS 2831"),
+      is(b"MTTS 2831"),
+      is(b"BLIGHTMUD"),
+    ]
+  );
+  // Enabling TTYPE again starts over.
+  instance.receive(&[
+    cmd::IAC,
+    cmd::DONT,
+    opt::TTYPE,
+    cmd::IAC,
+    cmd::DO,
+    opt::TTYPE,
+  ]);
+  assert_eq!(instance.receive(&send), vec![is(b"BLIGHTMUD")]);
+  instance.set_terminal_types(&[]);
+  assert_eq!(
+    handle_events(instance.receive(&send)),
+    events![Event::Subnegotiation]
+  );
+}
+
+#[test]
+fn test_self_test() {
+  assert!(Parser::with_support(CompatibilityTable::mud_client())
+    .self_test()
+    .is_ok());
+
+  let mut table = CompatibilityTable::from_options(&[
+    (
+      opt::ECHO,
+      CompatibilityTable::ENABLED_LOCAL | CompatibilityTable::ENABLED_REMOTE,
+    ),
+    (opt::SGA, CompatibilityTable::ENABLED_LOCAL),
+  ]);
+  table.set_option(
+    opt::NAWS,
+    CompatibilityEntry::new(false, false, true, false),
+  );
+  let mut instance = Parser::with_support(table);
+  instance.set_refuse_local_echo(true);
+  instance.set_half_duplex(true);
+  instance.set_terminal_types(&["XTERM"]);
+  instance.set_subnegotiation_limit(Some(4));
+  instance.set_tn3270_detection(true);
+  let report = instance.self_test();
+  assert!(!report.is_ok());
+  assert_eq!(
+    report.issues,
+    vec![
+      ConformanceIssue::EnabledWithoutSupport {
+        option: opt::NAWS,
+        direction: Direction::Local
+      },
+      ConformanceIssue::EchoBothWays,
+      ConformanceIssue::RefusedLocalEcho,
+      ConformanceIssue::HalfDuplexWithSga,
+      ConformanceIssue::TerminalTypesWithoutTtype,
+      ConformanceIssue::SubnegotiationLimitTooSmall(4),
+      ConformanceIssue::Tn3270WithoutBinaryEor,
+    ]
+  );
+}
+
+#[test]
+fn test_raw_builders() {
+  use libmudtelnet::events::{raw_command, raw_subnegotiation};
+
+  // The payload isn't escaped again.
+  assert_eq!(
+    raw_subnegotiation(200, Bytes::from_static(&[1, cmd::IAC, cmd::IAC, 2])),
+    TelnetEvents::DataSend(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::SB,
+      200,
+      1,
+      cmd::IAC,
+      cmd::IAC,
+      2,
+      cmd::IAC,
+      cmd::SE
+    ]))
+  );
+  assert_eq!(
+    raw_command(&[cmd::IAC, 238]),
+    TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, 238]))
+  );
+}
+
+#[cfg(feature = "compat_api")]
+#[test]
+fn test_compat_api() {
+  use libmudtelnet::compat_api::events::TelnetEvents as CompatEvents;
+  use libmudtelnet::compat_api::Parser as CompatParser;
+
+  let mut instance = CompatParser::with_support(CompatibilityTable::from_options(&[
+    (opt::GMCP, CompatibilityTable::ENABLED_LOCAL),
+    (opt::ECHO, CompatibilityTable::ENABLED_REMOTE),
+  ]));
+  assert_eq!(
+    instance._will(opt::GMCP),
+    Some(CompatEvents::DataSend(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::WILL,
+      opt::GMCP
+    ])))
+  );
+  assert_eq!(
+    instance.send_text("look"),
+    CompatEvents::DataSend(Bytes::from_static(b"look\r\n"))
+  );
+
+  // Events the original TelnetEvents can't represent are left out.
+  instance.set_option_change_events(true);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::WILL, opt::ECHO, b'>']),
+    vec![
+      CompatEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::DO, opt::ECHO])),
+      CompatEvents::Negotiation(TelnetNegotiation::new(cmd::WILL, opt::ECHO)),
+      CompatEvents::DataReceive(Bytes::from_static(b">")),
+    ]
+  );
+  assert!(instance.options.get_option(opt::ECHO).remote_state);
+}
+
+#[test]
+fn test_split_at_subnegotiation_option() {
+  // Reads split right after `IAC SB`, before the option byte, are reassembled wherever else they're split.
+  let entry = CompatibilityEntry::new(true, false, true, false);
+  let opts = CompatibilityTable::from_options(&[(opt::GMCP, entry.into_u8())]);
+  let stream = [
+    &b"hi"[..],
+    &[cmd::IAC, cmd::SB],
+    &[opt::GMCP, b'x', cmd::IAC, cmd::SE],
+    b"yo",
+  ];
+  let expected = vec![
+    TelnetEvents::DataReceive(Bytes::from_static(b"hi")),
+    TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(b"x"),
+    )),
+    TelnetEvents::DataReceive(Bytes::from_static(b"yo")),
+  ];
+  let data_end = stream[0].len();
+  let option_at = data_end + stream[1].len();
+  let stream = stream.concat();
+  // Splits within the text would only split its DataReceive event.
+  for split in data_end..=stream.len() - 2 {
+    let mut instance = Parser::with_support(opts.clone());
+    let mut events = instance.receive(&stream[..split]);
+    events.extend(instance.receive(&stream[split..option_at.max(split)]));
+    events.extend(instance.receive(&stream[option_at.max(split)..]));
+    assert_eq!(events, expected, "split at {split}");
+    assert_eq!(instance.finish(), vec![]);
+  }
+
+  // A stream ending before the option byte is reported once it's finished.
+  let mut instance = Parser::with_support(opts);
+  assert_eq!(
+    instance.receive(&stream[..option_at]),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"hi"))]
+  );
+  assert_eq!(
+    instance.finish(),
+    vec![TelnetEvents::ProtocolError(ProtocolError::TruncatedCommand)]
+  );
+}
+
+#[test]
+fn test_fallible_subnegotiation_decoder() {
+  let entry = CompatibilityEntry::new(true, false, true, false).into_u8();
+  let mut instance = Parser::with_support(CompatibilityTable::from_options(&[
+    (opt::GMCP, entry),
+    (opt::NAWS, entry),
+  ]));
+  instance.set_fallible_subnegotiation_decoder(opt::GMCP, gmcp::try_decode);
+  instance.set_fallible_subnegotiation_decoder(opt::NAWS, |payload| {
+    if payload.len() == 4 {
+      Ok(TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+        opt::NAWS,
+        payload.clone(),
+      )))
+    } else {
+      Err(format!("expected 4 bytes, got {}", payload.len()))
+    }
+  });
+
+  assert_eq!(
+    instance.receive(&[
+      cmd::IAC,
+      cmd::SB,
+      opt::GMCP,
+      b'C',
+      b'o',
+      b'r',
+      b'e',
+      cmd::IAC,
+      cmd::SE
+    ]),
+    vec![TelnetEvents::Gmcp(Box::new(GmcpMessage::new("Core", None)))]
+  );
+  // A rejected payload is kept, alongside the decoder's error.
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::SB, opt::GMCP, b' ', cmd::IAC, cmd::SE]),
+    vec![TelnetEvents::OptionDecodeError(Box::new(
+      OptionDecodeError {
+        option: opt::GMCP,
+        error: "missing package name".to_string(),
+        raw: Bytes::from_static(b" "),
+      }
+    ))]
+  );
+  let short = [cmd::IAC, cmd::SB, opt::NAWS, 0, 80, 0, cmd::IAC, cmd::SE];
+  let events = instance.receive(&short);
+  assert_eq!(
+    events,
+    vec![TelnetEvents::OptionDecodeError(Box::new(
+      OptionDecodeError {
+        option: opt::NAWS,
+        error: "expected 4 bytes, got 3".to_string(),
+        raw: Bytes::from_static(&[0, 80, 0]),
+      }
+    ))]
+  );
+  assert_eq!(&events[0].clone().to_bytes()[..], &short[..]);
+  assert_eq!(
+    events[0].to_string(),
+    "OptionDecodeError NAWS (expected 4 bytes, got 3) \"\\x00P\\x00\""
+  );
+
+  // Registering an infallible decoder replaces the fallible one.
+  instance.set_subnegotiation_decoder(opt::GMCP, gmcp::decode);
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::SB, opt::GMCP, b' ', cmd::IAC, cmd::SE])),
+    events![Event::Subnegotiation]
+  );
 }